@@ -0,0 +1,70 @@
+// Query editor served on `GET /graphql`: posts the query and variables to the
+// endpoint and shows the JSON response. Runs under the app's default CSP, so
+// it loads nothing from other origins and uses no inline code.
+(function () {
+  "use strict";
+
+  const form = document.getElementById("explorer");
+  const result = document.getElementById("result");
+
+  function cookie(name) {
+    const prefix = name + "=";
+    const entry = document.cookie
+      .split("; ")
+      .find((part) => part.startsWith(prefix));
+    return entry ? decodeURIComponent(entry.slice(prefix.length)) : null;
+  }
+
+  async function run(event) {
+    event.preventDefault();
+
+    let variables;
+    const text = form.variables.value.trim();
+    if (text) {
+      try {
+        variables = JSON.parse(text);
+      } catch (error) {
+        result.textContent = "Variables are not valid JSON: " + error.message;
+        return;
+      }
+    }
+
+    const headers = {
+      "Content-Type": "application/json",
+      Accept: "application/json",
+    };
+    if (form.token.value) {
+      headers.Authorization = "Bearer " + form.token.value.trim();
+    }
+    const csrf = cookie("csrf_token");
+    if (csrf) {
+      headers["X-CSRF-Token"] = csrf;
+    }
+
+    result.textContent = "Running…";
+    try {
+      const response = await fetch(form.action, {
+        method: "POST",
+        headers,
+        credentials: "same-origin",
+        body: JSON.stringify({ query: form.query.value, variables }),
+      });
+      const body = await response.text();
+      try {
+        result.textContent = JSON.stringify(JSON.parse(body), null, 2);
+      } catch {
+        result.textContent = response.status + " " + body;
+      }
+    } catch (error) {
+      result.textContent = "Request failed: " + error.message;
+    }
+  }
+
+  form.addEventListener("submit", run);
+  // Ctrl+Enter or Cmd+Enter runs the query from any field.
+  form.addEventListener("keydown", (event) => {
+    if (event.key === "Enter" && (event.ctrlKey || event.metaKey)) {
+      form.requestSubmit();
+    }
+  });
+})();
@@ -0,0 +1,14 @@
+use std::{env, path::PathBuf};
+
+fn main() {
+    // No system `protoc` needed.
+    let protoc = protoc_bin_vendored::protoc_bin_path().unwrap();
+    // SAFETY: build scripts are single threaded.
+    unsafe { env::set_var("PROTOC", protoc) };
+
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap());
+    tonic_prost_build::configure()
+        .file_descriptor_set_path(out.join("auth_descriptor.bin"))
+        .compile_protos(&["proto/axum_rs/auth/v1/auth.proto"], &["proto"])
+        .unwrap();
+}
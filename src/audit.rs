@@ -0,0 +1,85 @@
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditAction {
+    LoginFailed,
+    LoginSucceeded,
+    LockedOut,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub action: AuditAction,
+    pub subject: String,
+    pub detail: String,
+    pub at: SystemTime,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction, subject: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            action,
+            subject: subject.into(),
+            detail: detail.into(),
+            at: SystemTime::now(),
+        }
+    }
+}
+
+pub trait AuditLog: Send + Sync {
+    fn record(&self, event: AuditEvent);
+}
+
+// Writes events to the `audit` tracing target; keeping them is up to the
+// subscriber.
+#[derive(Default)]
+pub struct TracingAuditLog;
+
+impl AuditLog for TracingAuditLog {
+    fn record(&self, event: AuditEvent) {
+        tracing::info!(
+            target: "audit",
+            action = ?event.action,
+            subject = %event.subject,
+            detail = %event.detail,
+            "audit"
+        );
+    }
+}
+
+// Keeps every event, for tests to inspect.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryAuditLog {
+    events: std::sync::Mutex<Vec<AuditEvent>>,
+}
+
+#[cfg(test)]
+impl MemoryAuditLog {
+    pub fn events(&self) -> Vec<AuditEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl AuditLog for MemoryAuditLog {
+    fn record(&self, event: AuditEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_audit_log() {
+        let log = MemoryAuditLog::default();
+        log.record(AuditEvent::new(AuditAction::LockedOut, "user:hadi", "5 failed attempts"));
+
+        let events = log.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].action, AuditAction::LockedOut);
+        assert_eq!(events[0].subject, "user:hadi");
+    }
+}
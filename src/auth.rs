@@ -0,0 +1,452 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    Form, Router,
+    extract::{FromRef, FromRequest, FromRequestParts, Request, State},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::get,
+};
+use axum_extra::extract::{CookieJar, cookie::{Cookie, SameSite}};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, KeyInit, Mac};
+use http::{HeaderMap, header, request::Parts};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use utoipa::OpenApi;
+
+use crate::{
+    client_ip::ClientIp,
+    error::{AppError, ErrorBody},
+    flash::Flash,
+    login_guard::LoginGuard,
+    login_request::LoginRequest,
+    negotiate::{Format, Negotiated, accepts_html},
+    templates::{RenderError, Template},
+    try_form::LoginFormRequest,
+    try_response::AuthResponse,
+};
+
+pub const TOKEN_COOKIE: &str = "token";
+
+#[derive(Default)]
+pub struct UserStore {
+    users: HashMap<String, [u8; 32]>,
+}
+
+impl UserStore {
+    fn digest(username: &str, password: &str) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(username.as_bytes());
+        hasher.update(b":");
+        hasher.update(password.as_bytes());
+        hasher.finalize().into()
+    }
+
+    pub fn with_user(mut self, username: &str, password: &str) -> Self {
+        self.users
+            .insert(username.to_string(), Self::digest(username, password));
+        self
+    }
+
+    pub fn contains(&self, username: &str) -> bool {
+        self.users.contains_key(username)
+    }
+
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        let candidate = Self::digest(username, password);
+        match self.users.get(username) {
+            Some(stored) => stored.ct_eq(&candidate).into(),
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: u64,
+}
+
+pub struct TokenService {
+    key: Vec<u8>,
+    ttl: Duration,
+}
+
+impl TokenService {
+    pub fn new(secret: &str, ttl: Duration) -> Self {
+        Self {
+            key: secret.as_bytes().to_vec(),
+            ttl,
+        }
+    }
+
+    fn sign(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).unwrap();
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    pub fn issue(&self, username: &str) -> String {
+        let exp = SystemTime::now() + self.ttl;
+        let claims = Claims {
+            sub: username.to_string(),
+            exp: exp.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        };
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap());
+        let signature = URL_SAFE_NO_PAD.encode(self.sign(&payload).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    pub fn verify(&self, token: &str) -> Option<Claims> {
+        let (payload, signature) = token.split_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        self.sign(payload).verify_slice(&signature).ok()?;
+
+        let claims: Claims = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        (claims.exp > now).then_some(claims)
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthState {
+    pub users: Arc<UserStore>,
+    pub tokens: Arc<TokenService>,
+    pub guard: Arc<LoginGuard>,
+}
+
+impl FromRef<AuthState> for Arc<TokenService> {
+    fn from_ref(state: &AuthState) -> Self {
+        state.tokens.clone()
+    }
+}
+
+// Authenticated caller, taken from a bearer token or the session cookie.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthUser {
+    pub username: String,
+}
+
+impl AuthUser {
+    pub fn bearer_token(parts: &Parts) -> Option<&str> {
+        parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+    }
+}
+
+impl<S> FromRequestParts<S> for AuthUser
+where
+    Arc<TokenService>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let tokens = Arc::<TokenService>::from_ref(state);
+        let jar = CookieJar::from_headers(&parts.headers);
+
+        let token = Self::bearer_token(parts)
+            .map(str::to_string)
+            .or_else(|| jar.get(TOKEN_COOKIE).map(|cookie| cookie.value().to_string()))
+            .ok_or_else(|| AppError::unauthorized("Missing credentials"))?;
+
+        let claims = tokens
+            .verify(&token)
+            .ok_or_else(|| AppError::unauthorized("Invalid token"))?;
+        Ok(AuthUser { username: claims.sub })
+    }
+}
+
+// Accepts a `LoginRequest` in any negotiated format, or the urlencoded `LoginFormRequest`.
+pub struct LoginCredentials {
+    pub username: String,
+    pub password: String,
+    pub is_form: bool,
+}
+
+impl<S: Send + Sync> FromRequest<S> for LoginCredentials {
+    type Rejection = AppError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_form = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+
+        if is_form {
+            let Form(form) = Form::<LoginFormRequest>::from_request(request, state)
+                .await
+                .map_err(|err| AppError::new(err.status(), err.body_text()))?;
+            Ok(LoginCredentials {
+                username: form.username,
+                password: form.password,
+                is_form: true,
+            })
+        } else {
+            let Negotiated(_, login) =
+                Negotiated::<LoginRequest>::from_request(request, state).await?;
+            Ok(LoginCredentials {
+                username: login.username,
+                password: login.password,
+                is_form: false,
+            })
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "auth",
+    request_body(content(
+        (LoginRequest = "application/json"),
+        (LoginRequest = "application/msgpack"),
+        (LoginRequest = "application/cbor"),
+        (LoginRequest = "application/xml"),
+        (LoginFormRequest = "application/x-www-form-urlencoded"),
+    )),
+    responses(
+        (status = 200, description = "Token, also set as the session cookie", content(
+            (AuthResponse = "application/json"),
+            (AuthResponse = "application/msgpack"),
+            (AuthResponse = "application/cbor"),
+            (AuthResponse = "application/xml"),
+        )),
+        (status = 303, description = "Browser form submissions are redirected with a flash message"),
+        (status = 401, description = "Invalid credentials", body = ErrorBody),
+        (status = 406, description = "No acceptable response type", body = ErrorBody),
+        (status = 415, description = "Unsupported request body type", body = ErrorBody),
+        (status = 429, description = "Too many failed attempts", body = ErrorBody),
+    )
+)]
+async fn login(
+    State(state): State<AuthState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    jar: CookieJar,
+    flash: Flash,
+    format: Result<Format, AppError>,
+    credentials: LoginCredentials,
+) -> Result<Response, AppError> {
+    // The sign-in page gets redirects with a flash message instead of data.
+    let is_page = credentials.is_form && accepts_html(&headers);
+
+    let failure = if let Err(wait) = state.guard.check(&credentials.username, ip) {
+        Some(AppError::too_many_requests(wait.as_secs_f64().ceil() as u64))
+    } else if !state
+        .users
+        .verify(&credentials.username, &credentials.password)
+    {
+        state.guard.record_failure(&credentials.username, ip);
+        Some(AppError::unauthorized("Invalid username or password"))
+    } else {
+        None
+    };
+    if let Some(error) = failure {
+        if is_page {
+            return Ok((flash.error(error.message), Redirect::to("/login")).into_response());
+        }
+        return Err(error);
+    }
+
+    state.guard.record_success(&credentials.username, ip);
+    let token = state.tokens.issue(&credentials.username);
+    let cookie = Cookie::build((TOKEN_COOKIE, token.clone()))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .build();
+
+    if is_page {
+        let flash = flash.success(format!("Signed in as {}", credentials.username));
+        return Ok((jar.add(cookie), flash, Redirect::to("/")).into_response());
+    }
+    Ok((jar.add(cookie), Negotiated(format?, AuthResponse { token })).into_response())
+}
+
+// The sign-in form, which posts urlencoded credentials to `login`.
+async fn login_page(template: Template) -> Result<Html<String>, RenderError> {
+    template.render("login.html", ())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(login))]
+pub struct AuthApi;
+
+pub fn router(state: AuthState) -> Router {
+    Router::new()
+        .route("/login", get(login_page).post(login))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audit::MemoryAuditLog,
+        flash::{FlashConfig, Flashes, flash},
+        login_guard::LoginGuardConfig,
+    };
+    use axum::routing::get;
+    use axum_test::TestServer;
+    use http::StatusCode;
+
+    fn state() -> AuthState {
+        AuthState {
+            users: Arc::new(UserStore::default().with_user("hadi", "password")),
+            tokens: Arc::new(TokenService::new("secret", Duration::from_secs(60))),
+            guard: Arc::new(LoginGuard::new(
+                LoginGuardConfig {
+                    free_attempts: 2,
+                    ..LoginGuardConfig::default()
+                },
+                Arc::new(MemoryAuditLog::default()),
+            )),
+        }
+    }
+
+    #[test]
+    fn test_token_roundtrip() {
+        let tokens = TokenService::new("secret", Duration::from_secs(60));
+        let token = tokens.issue("hadi");
+        assert_eq!(tokens.verify(&token).unwrap().sub, "hadi");
+
+        let other = TokenService::new("other", Duration::from_secs(60));
+        assert!(other.verify(&token).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_login_json_and_form() {
+        let server = TestServer::new(router(state())).unwrap();
+
+        let response = server
+            .post("/login")
+            .json(&LoginRequest {
+                username: "hadi".to_string(),
+                password: "password".to_string(),
+            })
+            .await;
+        response.assert_status_ok();
+        response.assert_contains_header("Set-Cookie");
+        let token = response.json::<AuthResponse>().token;
+        assert!(!token.is_empty());
+
+        let response = server
+            .post("/login")
+            .form(&LoginFormRequest {
+                username: "hadi".to_string(),
+                password: "password".to_string(),
+            })
+            .await;
+        response.assert_status_ok();
+
+        let response = server
+            .post("/login")
+            .content_type("application/msgpack")
+            .add_header("Accept", "application/cbor")
+            .bytes(
+                Format::MessagePack
+                    .encode(&LoginRequest {
+                        username: "hadi".to_string(),
+                        password: "password".to_string(),
+                    })
+                    .unwrap()
+                    .into(),
+            )
+            .await;
+        response.assert_status_ok();
+        response.assert_header("Content-Type", "application/cbor");
+        let body: AuthResponse = Format::Cbor.decode(response.as_bytes()).unwrap();
+        assert!(!body.token.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_login_page_redirects_with_flash() {
+        let app = router(state()).layer(axum::middleware::from_fn_with_state(
+            Arc::new(Flashes::new(FlashConfig::default(), "secret")),
+            flash,
+        ));
+        let mut server = TestServer::new(app).unwrap();
+        server.save_cookies();
+        let submit = |password: &str| {
+            server
+                .post("/login")
+                .add_header("Accept", "text/html,application/xhtml+xml")
+                .form(&LoginFormRequest {
+                    username: "hadi".to_string(),
+                    password: password.to_string(),
+                })
+        };
+
+        let response = submit("wrong").await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header("Location", "/login");
+
+        let response = submit("password").await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header("Location", "/");
+        assert!(!response.cookie(TOKEN_COOKIE).value().is_empty());
+        assert!(!response.cookie("flash").value().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_login_locks_out_after_failures() {
+        let server = TestServer::new(router(state())).unwrap();
+        let wrong = LoginFormRequest {
+            username: "hadi".to_string(),
+            password: "wrong".to_string(),
+        };
+
+        for _ in 0..2 {
+            let response = server.post("/login").form(&wrong).await;
+            response.assert_status(StatusCode::UNAUTHORIZED);
+        }
+
+        let response = server
+            .post("/login")
+            .json(&LoginRequest {
+                username: "hadi".to_string(),
+                password: "password".to_string(),
+            })
+            .await;
+        response.assert_status(StatusCode::TOO_MANY_REQUESTS);
+        response.assert_header("Retry-After", "1");
+    }
+
+    #[tokio::test]
+    async fn test_auth_user_extractor() {
+        async fn route(user: AuthUser) -> String {
+            format!("Hello, {}", user.username)
+        }
+
+        let state = state();
+        let token = state.tokens.issue("hadi");
+        let app = Router::new().route("/me", get(route)).with_state(state);
+
+        let server = TestServer::new(app).unwrap();
+        let response = server.get("/me").await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        let response = server
+            .get("/me")
+            .authorization_bearer(&token)
+            .await;
+        response.assert_status_ok();
+        response.assert_text("Hello, hadi");
+
+        let response = server
+            .get("/me")
+            .add_cookie(Cookie::new(TOKEN_COOKIE, token))
+            .await;
+        response.assert_status_ok();
+    }
+}
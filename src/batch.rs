@@ -0,0 +1,433 @@
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    Extension, Json, Router,
+    body::{Body, to_bytes},
+    extract::{ConnectInfo, Request, State},
+    response::Response,
+    routing::post,
+};
+use futures_util::{StreamExt, stream};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri, header};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tower::ServiceExt;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::error::{AppError, ErrorBody};
+
+// Headers every sub-request takes from the batch request, so the whole batch
+// runs as the same caller. Sub-requests cannot override them.
+const INHERITED: [HeaderName; 3] = [
+    header::AUTHORIZATION,
+    header::COOKIE,
+    HeaderName::from_static("x-forwarded-for"),
+];
+
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    pub max_requests: usize,
+    // Sub-requests dispatched at the same time.
+    pub concurrency: usize,
+    // Sub-responses with larger bodies are replaced by an error.
+    pub max_response: usize,
+    // Sub-requests still running after this, body included, get a 504.
+    pub timeout: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_requests: 25,
+            concurrency: 4,
+            max_response: 1024 * 1024,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SubRequest {
+    #[serde(default = "default_method")]
+    pub method: String,
+    pub path: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    // Sent as JSON when present.
+    #[serde(default)]
+    pub body: Option<Value>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SubResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    // JSON bodies are embedded as is, anything else as a string.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+}
+
+impl SubResponse {
+    fn error(status: StatusCode, message: &str) -> Self {
+        Self {
+            status: status.as_u16(),
+            headers: BTreeMap::new(),
+            body: Some(serde_json::json!({
+                "code": status.as_u16(),
+                "message": message,
+            })),
+        }
+    }
+
+    async fn from_response(response: Response, limit: usize) -> Self {
+        let (parts, body) = response.into_parts();
+        let headers = parts
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let Ok(bytes) = to_bytes(body, limit).await else {
+            return Self::error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Response too large to include in a batch",
+            );
+        };
+        let is_json = parts
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("application/json"));
+
+        let body = if bytes.is_empty() {
+            None
+        } else if is_json && let Ok(value) = serde_json::from_slice(&bytes) {
+            Some(value)
+        } else {
+            Some(Value::String(String::from_utf8_lossy(&bytes).into_owned()))
+        };
+        Self {
+            status: parts.status.as_u16(),
+            headers,
+            body,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BatchState {
+    pub config: Arc<BatchConfig>,
+    // Sub-requests are dispatched through this router, which should not
+    // contain `/batch` itself. Give it the app's layers so they apply to each
+    // sub-request.
+    pub router: Router,
+}
+
+fn build_request(
+    item: SubRequest,
+    parent: &HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+) -> Result<Request, &'static str> {
+    let method =
+        Method::from_bytes(item.method.to_uppercase().as_bytes()).map_err(|_| "Invalid method")?;
+    if !item.path.starts_with('/') || item.path.starts_with("//") {
+        return Err("Path must be absolute");
+    }
+    let uri: Uri = item.path.parse().map_err(|_| "Invalid path")?;
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in &item.headers {
+        let name = HeaderName::try_from(name.as_str()).map_err(|_| "Invalid header name")?;
+        if INHERITED.contains(&name) {
+            continue;
+        }
+        let value = HeaderValue::try_from(value.as_str()).map_err(|_| "Invalid header value")?;
+        headers.insert(name, value);
+    }
+    for name in INHERITED {
+        for value in parent.get_all(&name) {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+
+    let body = match item.body {
+        Some(body) => {
+            headers
+                .entry(header::CONTENT_TYPE)
+                .or_insert(HeaderValue::from_static("application/json"));
+            Body::from(serde_json::to_vec(&body).unwrap())
+        }
+        None => Body::empty(),
+    };
+
+    let mut request = Request::new(body);
+    *request.method_mut() = method;
+    *request.uri_mut() = uri;
+    *request.headers_mut() = headers;
+    if let Some(connect_info) = connect_info {
+        request.extensions_mut().insert(connect_info);
+    }
+    Ok(request)
+}
+
+// Runs each sub-request through the router and returns their responses in
+// the order they were submitted. A failing sub-request does not fail the batch.
+#[utoipa::path(
+    post,
+    path = "/batch",
+    tag = "batch",
+    request_body = Vec<SubRequest>,
+    responses(
+        (status = 200, description = "One response per sub-request, in order", body = Vec<SubResponse>),
+        (status = 413, description = "Too many sub-requests", body = ErrorBody),
+    )
+)]
+async fn batch(
+    State(state): State<BatchState>,
+    headers: HeaderMap,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    Json(items): Json<Vec<SubRequest>>,
+) -> Result<Json<Vec<SubResponse>>, AppError> {
+    if items.len() > state.config.max_requests {
+        return Err(AppError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "A batch may contain at most {} requests",
+                state.config.max_requests
+            ),
+        ));
+    }
+
+    let connect_info = connect_info.map(|Extension(connect_info)| connect_info);
+    let responses = stream::iter(items)
+        .map(|item| {
+            let request = build_request(item, &headers, connect_info);
+            let router = state.router.clone();
+            let limit = state.config.max_response;
+            let timeout = state.config.timeout;
+            async move {
+                let request = match request {
+                    Ok(request) => request,
+                    Err(message) => return SubResponse::error(StatusCode::BAD_REQUEST, message),
+                };
+                let dispatch = async {
+                    let Ok(response) = router.oneshot(request).await;
+                    SubResponse::from_response(response, limit).await
+                };
+                tokio::time::timeout(timeout, dispatch)
+                    .await
+                    .unwrap_or_else(|_| {
+                        SubResponse::error(StatusCode::GATEWAY_TIMEOUT, "Request timed out")
+                    })
+            }
+        })
+        .buffered(state.config.concurrency.max(1))
+        .collect()
+        .await;
+    Ok(Json(responses))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(batch))]
+pub struct BatchApi;
+
+pub fn router(state: BatchState) -> Router {
+    Router::new().route("/batch", post(batch)).with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{
+        app,
+        blob_store::StorageConfig,
+        config::AppConfig,
+        rate_limit::{Quota, RateLimitConfig},
+    };
+    use axum::routing::get;
+    use axum_test::TestServer;
+    use serde_json::json;
+
+    fn server(config: BatchConfig, inner: Router) -> TestServer {
+        let app = inner.clone().merge(router(BatchState {
+            config: Arc::new(config),
+            router: inner,
+        }));
+        TestServer::new(app).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_batch_dispatch() {
+        async fn whoami(headers: HeaderMap) -> String {
+            headers
+                .get(header::AUTHORIZATION)
+                .map(|value| value.to_str().unwrap().to_string())
+                .unwrap_or_default()
+        }
+
+        let inner = Router::new().route("/whoami", get(whoami)).route(
+            "/echo",
+            post(|Json(body): Json<Value>| async { Json(body) }),
+        );
+        let server = server(BatchConfig::default(), inner);
+
+        let response = server
+            .post("/batch")
+            .add_header("Authorization", "Bearer parent")
+            .json(&json!([
+                {"path": "/whoami", "headers": {"Authorization": "Bearer other"}},
+                {"method": "post", "path": "/echo", "body": {"n": 1}},
+                {"path": "/missing"},
+                {"path": "relative"},
+            ]))
+            .await;
+        response.assert_status_ok();
+
+        let items = response.json::<Value>();
+        assert_eq!(items[0]["status"], 200);
+        assert_eq!(items[0]["body"], "Bearer parent");
+        assert_eq!(items[1]["status"], 200);
+        assert_eq!(items[1]["body"], json!({"n": 1}));
+        assert_eq!(items[1]["headers"]["content-type"], "application/json");
+        assert_eq!(items[2]["status"], 404);
+        assert_eq!(items[3]["status"], 400);
+        assert_eq!(items[3]["body"]["message"], "Path must be absolute");
+
+        // Nested batches are not dispatched.
+        let response = server
+            .post("/batch")
+            .json(&json!([{"method": "POST", "path": "/batch", "body": []}]))
+            .await;
+        assert_eq!(response.json::<Value>()[0]["status"], 404);
+    }
+
+    #[tokio::test]
+    async fn test_batch_limits() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let route = {
+            let (active, peak) = (active.clone(), peak.clone());
+            move || {
+                let (active, peak) = (active.clone(), peak.clone());
+                async move {
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    "ok"
+                }
+            }
+        };
+        let server = server(
+            BatchConfig {
+                max_requests: 6,
+                concurrency: 2,
+                ..BatchConfig::default()
+            },
+            Router::new().route("/slow", get(route)),
+        );
+
+        let items: Vec<_> = (0..6).map(|_| json!({"path": "/slow"})).collect();
+        let response = server.post("/batch").json(&items).await;
+        response.assert_status_ok();
+        assert_eq!(response.json::<Vec<Value>>().len(), 6);
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+
+        let items: Vec<_> = (0..7).map(|_| json!({"path": "/slow"})).collect();
+        let response = server.post("/batch").json(&items).await;
+        response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+        response.assert_json(&json!({
+            "code": 413,
+            "message": "A batch may contain at most 6 requests",
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_large_sub_response() {
+        let inner = Router::new().route("/large", get(|| async { "x".repeat(64) }));
+        let server = server(
+            BatchConfig {
+                max_response: 16,
+                ..BatchConfig::default()
+            },
+            inner,
+        );
+
+        let response = server.post("/batch").json(&json!([{"path": "/large"}])).await;
+        let items = response.json::<Value>();
+        assert_eq!(items[0]["status"], 500);
+        assert_eq!(
+            items[0]["body"]["message"],
+            "Response too large to include in a batch"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sub_request_timeout() {
+        let inner = Router::new()
+            .route("/fast", get(|| async { "ok" }))
+            .route(
+                "/hang",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    "late"
+                }),
+            );
+        let server = server(
+            BatchConfig {
+                timeout: Duration::from_millis(50),
+                ..BatchConfig::default()
+            },
+            inner,
+        );
+
+        let response = server
+            .post("/batch")
+            .json(&json!([{"path": "/hang"}, {"path": "/fast"}]))
+            .await;
+        response.assert_status_ok();
+        let items = response.json::<Value>();
+        assert_eq!(items[0]["status"], 504);
+        assert_eq!(items[0]["body"]["message"], "Request timed out");
+        assert_eq!(items[1]["status"], 200);
+        assert_eq!(items[1]["body"], "ok");
+    }
+
+    // Sub-requests pay for themselves in the app's rate limiter.
+    #[tokio::test]
+    async fn test_app_rate_limits_each_item() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.upload.dir = dir.path().join("uploads");
+        config.tus.dir = dir.path().join("tus");
+        config.storage = StorageConfig::Local {
+            dir: dir.path().join("blobs"),
+            base_url: String::new(),
+        };
+        config.rate_limit = RateLimitConfig {
+            default: Quota::per_minute(3),
+            ..RateLimitConfig::default()
+        };
+        let server = TestServer::new(app(&config).await).unwrap();
+
+        let items: Vec<_> = (0..4).map(|_| json!({"path": "/categories"})).collect();
+        let response = server
+            .post("/batch")
+            .add_header("Authorization", "Bearer batch")
+            .json(&items)
+            .await;
+        response.assert_status_ok();
+        let statuses: Vec<_> = response
+            .json::<Vec<Value>>()
+            .iter()
+            .map(|item| item["status"].as_u64().unwrap())
+            .collect();
+        // The batch itself took the first of the three.
+        assert_eq!(statuses, vec![200, 200, 429, 429]);
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+
+use axum::extract::{ConnectInfo, FromRequestParts};
+use http::{HeaderMap, request::Parts};
+
+// Peer address of the caller. `X-Forwarded-For` is only trusted when the
+// connection comes from a local reverse proxy (or when there is no peer
+// address at all, as with the in-memory test transport), and then only its
+// last entry: the one the proxy appended. Earlier entries are whatever the
+// client sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClientIp(pub IpAddr);
+
+impl ClientIp {
+    pub fn from_parts(parts: &Parts) -> Self {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        Self::new(peer, &parts.headers)
+    }
+
+    pub fn new(peer: Option<IpAddr>, headers: &HeaderMap) -> Self {
+        let forwarded = || {
+            headers
+                .get_all("X-Forwarded-For")
+                .iter()
+                .next_back()
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.rsplit(',').next())
+                .and_then(|value| value.trim().parse::<IpAddr>().ok())
+        };
+
+        let ip = match peer {
+            Some(ip) if ip.is_loopback() => forwarded().unwrap_or(ip),
+            Some(ip) => ip,
+            None => forwarded().unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        };
+        ClientIp(ip)
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ClientIp::from_parts(parts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, routing::get};
+    use axum_test::TestServer;
+
+    #[tokio::test]
+    async fn test_client_ip_ignores_spoofed_forwarded() {
+        async fn route(ClientIp(ip): ClientIp) -> String {
+            format!("Hello, {}", ip)
+        }
+
+        let app = Router::new().route("/", get(route));
+
+        let server = TestServer::new(app).unwrap();
+        let response = server
+            .get("/")
+            .add_header("X-Forwarded-For", "10.0.0.1, 192.168.0.1")
+            .await;
+        response.assert_status_ok();
+        response.assert_text("Hello, 192.168.0.1");
+
+        // A client cannot pick its address by sending its own header; the
+        // proxy appends the real one.
+        let response = server
+            .get("/")
+            .add_header("X-Forwarded-For", "1.2.3.4")
+            .add_header("X-Forwarded-For", "192.168.0.2")
+            .await;
+        response.assert_text("Hello, 192.168.0.2");
+    }
+
+    #[tokio::test]
+    async fn test_client_ip_connect_info() {
+        async fn route(ClientIp(ip): ClientIp) -> String {
+            format!("Hello, {}", ip)
+        }
+
+        let app = Router::new().route("/", get(route));
+
+        let server = TestServer::builder()
+            .http_transport()
+            .build(app.into_make_service_with_connect_info::<SocketAddr>())
+            .unwrap();
+        let response = server.get("/").await;
+        response.assert_status_ok();
+        response.assert_text("Hello, 127.0.0.1");
+    }
+}
@@ -0,0 +1,264 @@
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{HeaderMap, HeaderValue, StatusCode, Version, header};
+use http_body_util::Limited;
+use tower::{ServiceExt, service_fn};
+use tower_http::{
+    compression::{
+        CompressionLayer,
+        predicate::{NotForContentType, Predicate, SizeAbove},
+    },
+    decompression::{DecompressionBody, RequestDecompression},
+};
+
+use crate::error::AppError;
+
+// Request encodings we decode; anything else is a 415.
+const REQUEST_ENCODINGS: &[&str] = &["gzip", "br", "zstd"];
+
+// Never compressed, even if an allowlist entry would match.
+const ALREADY_COMPRESSED: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "video/",
+    "audio/",
+    "font/woff",
+    "application/zip",
+    "application/gzip",
+    "application/zstd",
+    "application/x-7z-compressed",
+    "application/x-bzip2",
+    "application/x-xz",
+];
+
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    // Responses smaller than this are sent as is.
+    pub min_size: u16,
+    // Content type prefixes that are compressed.
+    pub content_types: Vec<String>,
+    // Limit on a request body after decoding its `Content-Encoding`.
+    pub max_decompressed_size: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            min_size: 1024,
+            content_types: [
+                "text/",
+                "application/json",
+                "application/problem+json",
+                "application/xml",
+                "application/javascript",
+                "application/msgpack",
+                "application/cbor",
+                "image/svg+xml",
+            ]
+            .map(String::from)
+            .to_vec(),
+            max_decompressed_size: 10 * 1024 * 1024,
+        }
+    }
+}
+
+fn essence(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+    Some(value.split(';').next()?.trim().to_ascii_lowercase())
+}
+
+// Gzip, brotli or zstd, whichever `Accept-Encoding` prefers. Server-sent
+// events are left alone so every event is flushed as it is written.
+pub fn compression_layer(config: &CompressionConfig) -> CompressionLayer<impl Predicate + use<>> {
+    let allowed = Arc::new(config.content_types.clone());
+    let allowlist = move |_: StatusCode, _: Version, headers: &HeaderMap, _: &http::Extensions| {
+        essence(headers).is_some_and(|content_type| {
+            allowed
+                .iter()
+                .any(|prefix| content_type.starts_with(prefix.as_str()))
+                && !ALREADY_COMPRESSED
+                    .iter()
+                    .any(|prefix| content_type.starts_with(prefix))
+        })
+    };
+
+    CompressionLayer::new().no_deflate().compress_when(
+        SizeAbove::new(config.min_size)
+            .and(NotForContentType::SSE)
+            .and(allowlist),
+    )
+}
+
+// Decodes `Content-Encoding` request bodies. The decoded stream is capped,
+// so a small compressed body cannot expand into unbounded memory; extractors
+// report the overflow as 413.
+pub async fn decompress_request(
+    State(config): State<Arc<CompressionConfig>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(encoding) = request.headers().get(header::CONTENT_ENCODING) else {
+        return next.run(request).await;
+    };
+    let encoding = encoding
+        .to_str()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if encoding == "identity" {
+        return next.run(request).await;
+    }
+    if !REQUEST_ENCODINGS.contains(&encoding.as_str()) {
+        return AppError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Unsupported Content-Encoding {}", encoding),
+        )
+        .with_header(
+            header::ACCEPT_ENCODING,
+            HeaderValue::from_static("gzip, br, zstd"),
+        )
+        .into_response();
+    }
+
+    let limit = config.max_decompressed_size;
+    let inner = service_fn(move |request: Request<DecompressionBody<Body>>| {
+        let next = next.clone();
+        async move {
+            let request = request.map(|body| Body::new(Limited::new(body, limit)));
+            Ok::<_, Infallible>(next.run(request).await)
+        }
+    });
+    match RequestDecompression::new(inner).oneshot(request).await {
+        Ok(response) => response.map(Body::new),
+        Err(err) => match err {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Json, Router,
+        body::Bytes,
+        middleware::from_fn_with_state,
+        routing::{get, post},
+    };
+    use axum_test::TestServer;
+
+    fn server(config: CompressionConfig) -> TestServer {
+        async fn zeros() -> ([(header::HeaderName, &'static str); 1], Vec<u8>) {
+            (
+                [(header::CONTENT_TYPE, "text/plain")],
+                vec![b'0'; 256 * 1024],
+            )
+        }
+
+        let app = Router::new()
+            .route("/json", get(|| async { Json(vec!["item"; 1000]) }))
+            .route("/small", get(|| async { Json("small") }))
+            .route(
+                "/png",
+                get(|| async { ([(header::CONTENT_TYPE, "image/png")], vec![0u8; 4096]) }),
+            )
+            .route("/zeros", get(zeros))
+            .route(
+                "/echo",
+                post(|body: Bytes| async move { body.len().to_string() }),
+            )
+            .layer(from_fn_with_state(
+                Arc::new(config.clone()),
+                decompress_request,
+            ))
+            .layer(compression_layer(&config));
+        TestServer::new(app).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_response_compression() {
+        let server = server(CompressionConfig::default());
+
+        for encoding in ["gzip", "br", "zstd"] {
+            let response = server
+                .get("/json")
+                .add_header("Accept-Encoding", encoding)
+                .await;
+            response.assert_header("Content-Encoding", encoding);
+            assert!(response.as_bytes().len() < 1000);
+        }
+
+        let response = server
+            .get("/json")
+            .add_header("Accept-Encoding", "gzip;q=0.5, zstd")
+            .await;
+        response.assert_header("Content-Encoding", "zstd");
+
+        // Too small, already compressed, or not asked for.
+        for (path, encoding) in [("/small", "gzip"), ("/png", "gzip"), ("/json", "identity")] {
+            let response = server
+                .get(path)
+                .add_header("Accept-Encoding", encoding)
+                .await;
+            assert!(
+                response.maybe_header("Content-Encoding").is_none(),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_decompression() {
+        let limited = server(CompressionConfig {
+            max_decompressed_size: 64 * 1024,
+            ..CompressionConfig::default()
+        });
+
+        // 256 KiB compress to a few hundred bytes, well over the limit once decoded.
+        for encoding in ["gzip", "br", "zstd"] {
+            let bomb = limited
+                .get("/zeros")
+                .add_header("Accept-Encoding", encoding)
+                .await
+                .into_bytes();
+            assert!(bomb.len() < 4096);
+
+            let response = limited
+                .post("/echo")
+                .add_header("Content-Encoding", encoding)
+                .bytes(bomb)
+                .await;
+            response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        let server = server(CompressionConfig::default());
+        let compressed = server
+            .get("/zeros")
+            .add_header("Accept-Encoding", "gzip")
+            .await
+            .into_bytes();
+        let response = server
+            .post("/echo")
+            .add_header("Content-Encoding", "gzip")
+            .bytes(compressed)
+            .await;
+        response.assert_status_ok();
+        response.assert_text((256 * 1024).to_string());
+
+        let response = server
+            .post("/echo")
+            .add_header("Content-Encoding", "compress")
+            .text("data")
+            .await;
+        response.assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        response.assert_header("Accept-Encoding", "gzip, br, zstd");
+    }
+}
@@ -0,0 +1,190 @@
+use std::{env, time::Duration};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+
+use crate::{
+    batch::BatchConfig,
+    blob_store::{S3Config, StorageConfig},
+    compression::CompressionConfig,
+    cors::CorsConfig,
+    csrf::CsrfConfig,
+    flash::FlashConfig,
+    graphql::GraphqlConfig,
+    grpc::GrpcConfig,
+    hub::HubConfig,
+    idempotency::IdempotencyConfig,
+    login_guard::LoginGuardConfig,
+    profile_image::ImageConfig,
+    rate_limit::{KeyBy, RateLimitConfig},
+    security_headers::SecurityHeadersConfig,
+    sse::SseConfig,
+    static_files::{AssetSource, StaticConfig},
+    templates::TemplateConfig,
+    tls::TlsConfig,
+    tus::TusConfig,
+    upload::UploadConfig,
+    websocket::WebSocketConfig,
+};
+
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub bind_address: String,
+    pub token_secret: String,
+    pub token_ttl: Duration,
+    // `username:password` pairs seeded into the in-memory user store.
+    pub users: Vec<(String, String)>,
+    pub login_guard: LoginGuardConfig,
+    pub rate_limit: RateLimitConfig,
+    pub csrf: CsrfConfig,
+    pub flash: FlashConfig,
+    pub batch: BatchConfig,
+    pub idempotency: IdempotencyConfig,
+    pub hub: HubConfig,
+    pub websocket: WebSocketConfig,
+    pub sse: SseConfig,
+    pub cors: CorsConfig,
+    pub security_headers: SecurityHeadersConfig,
+    pub compression: CompressionConfig,
+    // Frontend served for paths no route matches.
+    pub static_files: Option<StaticConfig>,
+    pub templates: TemplateConfig,
+    pub graphql: GraphqlConfig,
+    pub grpc: GrpcConfig,
+    pub tls: Option<TlsConfig>,
+    pub upload: UploadConfig,
+    pub storage: StorageConfig,
+    pub images: ImageConfig,
+    pub tus: TusConfig,
+    // SQLite URL for the catalog; in-memory storage when unset.
+    pub database_url: Option<String>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: "127.0.0.1:3000".to_string(),
+            // Tokens from one process are useless to the next; set
+            // `APP_TOKEN_SECRET` to keep them across restarts and instances.
+            token_secret: URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>()),
+            token_ttl: Duration::from_secs(60 * 60),
+            users: Vec::new(),
+            login_guard: LoginGuardConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            csrf: CsrfConfig::default(),
+            flash: FlashConfig::default(),
+            batch: BatchConfig::default(),
+            idempotency: IdempotencyConfig::default(),
+            hub: HubConfig::default(),
+            websocket: WebSocketConfig::default(),
+            sse: SseConfig::default(),
+            cors: CorsConfig::default(),
+            security_headers: SecurityHeadersConfig::default(),
+            compression: CompressionConfig::default(),
+            static_files: None,
+            templates: TemplateConfig::default(),
+            graphql: GraphqlConfig::default(),
+            grpc: GrpcConfig::default(),
+            tls: None,
+            upload: UploadConfig::default(),
+            storage: StorageConfig::default(),
+            images: ImageConfig::default(),
+            tus: TusConfig::default(),
+            database_url: None,
+        }
+    }
+}
+
+impl AppConfig {
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(value) = env::var("APP_BIND_ADDRESS") {
+            config.bind_address = value;
+        }
+        match env::var("APP_TOKEN_SECRET") {
+            Ok(value) => config.token_secret = value,
+            // Signed tokens, cookies and URLs must not silently depend on a
+            // secret nobody chose.
+            Err(_) if !cfg!(debug_assertions) => {
+                panic!("APP_TOKEN_SECRET must be set in release builds")
+            }
+            Err(_) => {}
+        }
+        if let Ok(value) = env::var("APP_USERS") {
+            config.users = value
+                .split(',')
+                .filter_map(|pair| pair.split_once(':'))
+                .map(|(username, password)| (username.to_string(), password.to_string()))
+                .collect();
+        }
+        if let Ok(value) = env::var("APP_REDIS_ADDRESS") {
+            config.rate_limit.redis_address = Some(value);
+        }
+        if let Ok(value) = env::var("APP_API_KEYS") {
+            config.rate_limit.api_keys = value
+                .split(',')
+                .map(|key| key.trim().to_string())
+                .filter(|key| !key.is_empty())
+                .collect();
+        }
+        match env::var("APP_RATE_LIMIT_KEY").as_deref() {
+            Ok("api-key") => config.rate_limit.default_key = KeyBy::ApiKey,
+            Ok("user") => config.rate_limit.default_key = KeyBy::User,
+            _ => {}
+        }
+        if let Ok(value) = env::var("APP_CORS_ORIGINS") {
+            config.cors.allowed_origins = value
+                .split(',')
+                .map(|origin| origin.trim().to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect();
+        }
+        if let Ok(value) = env::var("APP_UPLOAD_DIR") {
+            config.upload.dir = value.into();
+        }
+        if let Ok(value) = env::var("APP_STATIC_DIR") {
+            config.static_files = Some(StaticConfig {
+                source: AssetSource::Dir(value.into()),
+                ..StaticConfig::default()
+            });
+        } else if env::var("APP_STATIC_EMBEDDED").is_ok_and(|value| value == "true") {
+            config.static_files = Some(StaticConfig::default());
+        }
+        if let Ok(value) = env::var("APP_TEMPLATE_DIR") {
+            config.templates.dir = Some(value.into());
+        }
+        if let Ok(value) = env::var("APP_TEMPLATE_RELOAD") {
+            config.templates.reload = value == "true";
+        }
+        if let Ok(value) = env::var("APP_GRAPHIQL") {
+            config.graphql.graphiql = value == "true";
+        }
+        if let Ok(value) = env::var("APP_DATABASE_URL") {
+            config.database_url = Some(value);
+        }
+        if let Ok(value) = env::var("APP_STORAGE_DIR") {
+            config.storage = StorageConfig::Local {
+                dir: value.into(),
+                base_url: env::var("APP_PUBLIC_URL").unwrap_or_default(),
+            };
+        }
+        if let (Ok(endpoint), Ok(bucket)) = (env::var("APP_S3_ENDPOINT"), env::var("APP_S3_BUCKET"))
+        {
+            config.storage = StorageConfig::S3(S3Config {
+                endpoint,
+                bucket,
+                region: env::var("APP_S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+                access_key: env::var("APP_S3_ACCESS_KEY").unwrap_or_default(),
+                secret_key: env::var("APP_S3_SECRET_KEY").unwrap_or_default(),
+            });
+        }
+        if let (Ok(cert), Ok(key)) = (env::var("APP_TLS_CERT"), env::var("APP_TLS_KEY")) {
+            let mut tls = TlsConfig::new(cert, key);
+            tls.client_ca_path = env::var("APP_TLS_CLIENT_CA").ok().map(Into::into);
+            tls.require_client_cert =
+                env::var("APP_TLS_REQUIRE_CLIENT_CERT").is_ok_and(|value| value == "true");
+            tls.redirect_address = env::var("APP_HTTP_REDIRECT_ADDRESS").ok();
+            config.tls = Some(tls);
+        }
+        config
+    }
+}
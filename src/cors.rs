@@ -0,0 +1,224 @@
+use std::time::Duration;
+
+use http::{HeaderName, HeaderValue, Method, header, request::Parts};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    // Exact origins (`https://app.example.com`) or wildcard subdomains
+    // (`https://*.example.com`).
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<Method>,
+    pub allowed_headers: Vec<HeaderName>,
+    pub exposed_headers: Vec<HeaderName>,
+    pub allow_credentials: bool,
+    pub max_age: Duration,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec![
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ],
+            allowed_headers: vec![
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                header::IF_MATCH,
+                header::IF_NONE_MATCH,
+                HeaderName::from_static("x-csrf-token"),
+                HeaderName::from_static("idempotency-key"),
+                HeaderName::from_static("tus-resumable"),
+                HeaderName::from_static("upload-length"),
+                HeaderName::from_static("upload-offset"),
+                HeaderName::from_static("upload-metadata"),
+            ],
+            exposed_headers: vec![
+                header::RETRY_AFTER,
+                header::ETAG,
+                header::LINK,
+                header::LOCATION,
+                header::CONTENT_LOCATION,
+                HeaderName::from_static("idempotent-replayed"),
+                HeaderName::from_static("ratelimit-limit"),
+                HeaderName::from_static("ratelimit-remaining"),
+                HeaderName::from_static("ratelimit-reset"),
+                HeaderName::from_static("tus-resumable"),
+                HeaderName::from_static("tus-version"),
+                HeaderName::from_static("tus-extension"),
+                HeaderName::from_static("tus-max-size"),
+                HeaderName::from_static("upload-length"),
+                HeaderName::from_static("upload-offset"),
+                HeaderName::from_static("upload-metadata"),
+                HeaderName::from_static("upload-expires"),
+            ],
+            allow_credentials: true,
+            max_age: Duration::from_secs(60 * 10),
+        }
+    }
+}
+
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    let Some((scheme, host)) = pattern.split_once("://*.") else {
+        return pattern == origin;
+    };
+    let Some(rest) = origin
+        .strip_prefix(scheme)
+        .and_then(|rest| rest.strip_prefix("://"))
+    else {
+        return false;
+    };
+    rest.strip_suffix(host)
+        .and_then(|subdomain| subdomain.strip_suffix('.'))
+        .is_some_and(|subdomain| {
+            !subdomain.is_empty()
+                && subdomain
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        })
+}
+
+pub fn cors_layer(config: &CorsConfig) -> CorsLayer {
+    let origins = config.allowed_origins.clone();
+    let allow_origin = AllowOrigin::predicate(move |origin: &HeaderValue, _parts: &Parts| {
+        origin.to_str().is_ok_and(|origin| {
+            origins
+                .iter()
+                .any(|pattern| origin_matches(pattern, origin))
+        })
+    });
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(config.allowed_methods.clone())
+        .allow_headers(config.allowed_headers.clone())
+        .expose_headers(config.exposed_headers.clone())
+        .allow_credentials(config.allow_credentials)
+        .max_age(config.max_age)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, routing::get};
+    use axum_test::TestServer;
+
+    #[test]
+    fn test_origin_matches() {
+        let cases = [
+            ("https://app.example.com", "https://app.example.com", true),
+            ("https://app.example.com", "http://app.example.com", false),
+            ("https://*.example.com", "https://app.example.com", true),
+            ("https://*.example.com", "https://a.b.example.com", true),
+            ("https://*.example.com", "https://example.com", false),
+            ("https://*.example.com", "https://evilexample.com", false),
+            (
+                "https://*.example.com",
+                "https://app.example.com.evil.io",
+                false,
+            ),
+        ];
+
+        for (pattern, origin, expected) in cases {
+            assert_eq!(
+                origin_matches(pattern, origin),
+                expected,
+                "{} {}",
+                pattern,
+                origin
+            );
+        }
+    }
+
+    fn app() -> Router {
+        async fn route(method: Method) -> String {
+            format!("Hello, {}", method)
+        }
+
+        let first = Router::new().route("/first", get(route));
+        let second = Router::new().route("/second", get(route).post(route));
+
+        let config = CorsConfig {
+            allowed_origins: vec![
+                "https://spa.example.com".to_string(),
+                "https://*.preview.example.com".to_string(),
+            ],
+            ..CorsConfig::default()
+        };
+
+        Router::new()
+            .nest("/api/users", first)
+            .nest("/api/posts", second)
+            .layer(cors_layer(&config))
+    }
+
+    #[tokio::test]
+    async fn test_preflight_nested_router() {
+        let server = TestServer::new(app()).unwrap();
+
+        let response = server
+            .method(Method::OPTIONS, "/api/posts/second")
+            .add_header("Origin", "https://pr-12.preview.example.com")
+            .add_header("Access-Control-Request-Method", "POST")
+            .add_header("Access-Control-Request-Headers", "content-type")
+            .await;
+        response.assert_status_ok();
+        response.assert_header(
+            "Access-Control-Allow-Origin",
+            "https://pr-12.preview.example.com",
+        );
+        response.assert_header("Access-Control-Allow-Credentials", "true");
+        response.assert_header("Access-Control-Max-Age", "600");
+        let methods = response.header("Access-Control-Allow-Methods");
+        assert!(methods.to_str().unwrap().contains("POST"));
+        let headers = response.header("Access-Control-Allow-Headers");
+        assert!(headers.to_str().unwrap().contains("tus-resumable"));
+        assert!(headers.to_str().unwrap().contains("idempotency-key"));
+
+        let response = server
+            .method(Method::OPTIONS, "/api/users/first")
+            .add_header("Origin", "https://spa.example.com")
+            .add_header("Access-Control-Request-Method", "GET")
+            .await;
+        response.assert_status_ok();
+        response.assert_header("Access-Control-Allow-Origin", "https://spa.example.com");
+    }
+
+    #[tokio::test]
+    async fn test_simple_request() {
+        let server = TestServer::new(app()).unwrap();
+
+        let response = server
+            .get("/api/users/first")
+            .add_header("Origin", "https://spa.example.com")
+            .await;
+        response.assert_status_ok();
+        response.assert_text("Hello, GET");
+        response.assert_header("Access-Control-Allow-Origin", "https://spa.example.com");
+        let exposed = response.header("Access-Control-Expose-Headers");
+        assert!(exposed.to_str().unwrap().contains("ratelimit-remaining"));
+        assert!(exposed.to_str().unwrap().contains("upload-offset"));
+        assert!(exposed.to_str().unwrap().contains("etag"));
+    }
+
+    #[tokio::test]
+    async fn test_disallowed_origin() {
+        let server = TestServer::new(app()).unwrap();
+
+        let response = server
+            .get("/api/users/first")
+            .add_header("Origin", "https://evil.example")
+            .await;
+        response.assert_status_ok();
+        assert!(
+            response
+                .maybe_header("Access-Control-Allow-Origin")
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,541 @@
+use std::{collections::HashMap, convert::Infallible, sync::Arc};
+
+use axum::{
+    BoxError,
+    body::{Body, Bytes, to_bytes},
+    extract::{FromRequestParts, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::{
+    CookieJar,
+    cookie::{Cookie, SameSite},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use futures_util::{StreamExt, stream};
+use hmac::{Hmac, KeyInit, Mac};
+use http::{HeaderMap, Method, StatusCode, Uri, header, request::Parts};
+use http_body_util::{BodyExt, LengthLimitError};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+use crate::{auth::AuthUser, error::AppError};
+
+// Most of a form body read while looking for the token. Multipart bodies are
+// only scanned this far, so the field has to come before any large file.
+const MAX_FORM_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct CsrfConfig {
+    pub cookie_name: String,
+    pub header_name: String,
+    pub field_name: String,
+    // Origins allowed to submit unsafe requests. When empty, the request's
+    // own `Host` is the only trusted origin.
+    pub trusted_origins: Vec<String>,
+    pub secure_cookie: bool,
+}
+
+impl Default for CsrfConfig {
+    fn default() -> Self {
+        Self {
+            cookie_name: "csrf_token".to_string(),
+            header_name: "X-CSRF-Token".to_string(),
+            field_name: "_csrf".to_string(),
+            trusted_origins: Vec::new(),
+            secure_cookie: false,
+        }
+    }
+}
+
+// Signed double-submit cookie: the cookie carries `nonce.signature`, and
+// unsafe requests must echo the same value in a header or form field.
+pub struct Csrf {
+    config: CsrfConfig,
+    key: Vec<u8>,
+}
+
+impl Csrf {
+    pub fn new(config: CsrfConfig, secret: &str) -> Self {
+        Self {
+            config,
+            key: secret.as_bytes().to_vec(),
+        }
+    }
+
+    fn mac(&self, nonce: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).unwrap();
+        mac.update(b"csrf:");
+        mac.update(nonce.as_bytes());
+        mac
+    }
+
+    pub fn generate(&self) -> String {
+        let nonce = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&nonce).finalize().into_bytes());
+        format!("{}.{}", nonce, signature)
+    }
+
+    pub fn is_valid(&self, token: &str) -> bool {
+        let Some((nonce, signature)) = token.split_once('.') else {
+            return false;
+        };
+        let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else {
+            return false;
+        };
+        self.mac(nonce).verify_slice(&signature).is_ok()
+    }
+
+    fn origin_allowed(&self, headers: &HeaderMap) -> bool {
+        let source = headers
+            .get(header::ORIGIN)
+            .or_else(|| headers.get(header::REFERER))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<Uri>().ok());
+
+        // Without Origin or Referer the token check alone decides.
+        let Some(source) = source else {
+            return true;
+        };
+        let (Some(scheme), Some(authority)) = (source.scheme_str(), source.authority()) else {
+            return false;
+        };
+        let origin = format!("{}://{}", scheme, authority);
+
+        if self.config.trusted_origins.is_empty() {
+            headers
+                .get(header::HOST)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|host| host == authority.as_str())
+        } else {
+            self.config.trusted_origins.contains(&origin)
+        }
+    }
+}
+
+// Current CSRF token, for embedding in rendered forms.
+#[derive(Debug, Clone)]
+pub struct CsrfToken(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for CsrfToken {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<CsrfToken>()
+            .cloned()
+            .unwrap_or(CsrfToken(String::new())))
+    }
+}
+
+fn forbidden(message: &str) -> Response {
+    AppError::new(StatusCode::FORBIDDEN, message).into_response()
+}
+
+fn read_error(err: axum::Error) -> Response {
+    let err: BoxError = err.into_inner();
+    if err.is::<LengthLimitError>() {
+        return AppError::new(StatusCode::PAYLOAD_TOO_LARGE, "Form body too large").into_response();
+    }
+    AppError::new(StatusCode::BAD_REQUEST, "Failed to read request body").into_response()
+}
+
+// Reads up to `MAX_FORM_SIZE` of a multipart body and looks for `field` in it.
+// Returns the token with a body that replays what was read, followed by the
+// rest of the stream.
+async fn multipart_field(
+    mut body: Body,
+    boundary: String,
+    field: &str,
+) -> Result<(Option<String>, Body), Response> {
+    let mut prefix = Vec::new();
+    let mut finished = false;
+    while prefix.len() < MAX_FORM_SIZE {
+        match body.frame().await {
+            Some(Ok(frame)) => {
+                if let Ok(data) = frame.into_data() {
+                    prefix.extend_from_slice(&data);
+                }
+            }
+            Some(Err(err)) => return Err(read_error(err)),
+            None => {
+                finished = true;
+                break;
+            }
+        }
+    }
+    let prefix = Bytes::from(prefix);
+
+    let chunk = prefix.clone();
+    let mut multipart = multer::Multipart::new(
+        stream::once(async move { Ok::<_, Infallible>(chunk) }),
+        boundary,
+    );
+    let mut submitted = None;
+    // A field cut off at the end of the prefix ends the search with an error.
+    while let Ok(Some(next)) = multipart.next_field().await {
+        if next.name() == Some(field) {
+            submitted = next.text().await.ok();
+            break;
+        }
+    }
+
+    let body = if finished {
+        Body::from(prefix)
+    } else {
+        Body::from_stream(
+            stream::once(async move { Ok::<_, axum::Error>(prefix) })
+                .chain(body.into_data_stream()),
+        )
+    };
+    Ok((submitted, body))
+}
+
+pub async fn csrf(State(csrf): State<Arc<Csrf>>, request: Request, next: Next) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let jar = CookieJar::from_headers(&parts.headers);
+
+    let existing = jar
+        .get(&csrf.config.cookie_name)
+        .map(|cookie| cookie.value().to_string())
+        .filter(|token| csrf.is_valid(token));
+    let token = existing.clone().unwrap_or_else(|| csrf.generate());
+    parts.extensions.insert(CsrfToken(token.clone()));
+
+    let is_safe = matches!(
+        parts.method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    );
+    let is_bearer = AuthUser::bearer_token(&parts).is_some();
+    // Browsers can only send JSON cross-origin after a CORS preflight.
+    let is_json = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    // The same goes for the `Tus-Resumable` header every tus request carries.
+    let is_tus = parts.headers.contains_key("tus-resumable");
+
+    let body = if is_safe || is_bearer || is_json || is_tus {
+        body
+    } else {
+        if !csrf.origin_allowed(&parts.headers) {
+            return forbidden("Cross-origin request rejected");
+        }
+
+        let mut submitted = parts
+            .headers
+            .get(csrf.config.header_name.as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        let content_type = parts
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let is_form = content_type.starts_with("application/x-www-form-urlencoded");
+        let boundary = multer::parse_boundary(content_type).ok();
+
+        let body = match (submitted.is_some(), is_form, boundary) {
+            (false, true, _) => {
+                let bytes = match to_bytes(body, MAX_FORM_SIZE).await {
+                    Ok(bytes) => bytes,
+                    Err(err) => return read_error(err),
+                };
+                submitted = serde_urlencoded::from_bytes::<HashMap<String, String>>(&bytes)
+                    .ok()
+                    .and_then(|mut fields| fields.remove(&csrf.config.field_name));
+                Body::from(bytes)
+            }
+            (false, false, Some(boundary)) => {
+                match multipart_field(body, boundary, &csrf.config.field_name).await {
+                    Ok((field, body)) => {
+                        submitted = field;
+                        body
+                    }
+                    Err(response) => return response,
+                }
+            }
+            _ => body,
+        };
+
+        let matches = match (&existing, &submitted) {
+            (Some(expected), Some(submitted)) => {
+                expected.as_bytes().ct_eq(submitted.as_bytes()).into()
+            }
+            _ => false,
+        };
+        if !matches {
+            return forbidden("CSRF token mismatch");
+        }
+        body
+    };
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+    if existing.is_some() {
+        return response;
+    }
+
+    let cookie = Cookie::build((csrf.config.cookie_name.clone(), token))
+        .path("/")
+        .same_site(SameSite::Strict)
+        .secure(csrf.config.secure_cookie)
+        .build();
+    (jar.add(cookie), response).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::try_form::LoginFormRequest;
+    use axum::{
+        Form, Router,
+        extract::Multipart,
+        middleware::from_fn_with_state,
+        routing::{get, post},
+    };
+    use axum_test::{
+        TestServer,
+        multipart::{MultipartForm, Part},
+    };
+
+    fn app() -> (Router, Arc<Csrf>) {
+        async fn form(token: CsrfToken) -> String {
+            format!("<input name=\"_csrf\" value=\"{}\">", token.0)
+        }
+
+        async fn login(Form(form): Form<LoginFormRequest>) -> String {
+            format!("Hello, {}!", form.username)
+        }
+
+        async fn upload(mut multipart: Multipart) -> String {
+            let mut count = 0;
+            while let Some(_field) = multipart.next_field().await.unwrap() {
+                count += 1;
+            }
+            format!("Fields: {}", count)
+        }
+
+        let csrf = Arc::new(Csrf::new(CsrfConfig::default(), "secret"));
+        let app = Router::new()
+            .route("/login", get(form).post(login))
+            .route("/upload", post(upload))
+            .layer(from_fn_with_state(csrf.clone(), super::csrf));
+        (app, csrf)
+    }
+
+    #[derive(serde::Serialize)]
+    struct LoginWithToken<'a> {
+        username: &'a str,
+        password: &'a str,
+        _csrf: &'a str,
+    }
+
+    #[tokio::test]
+    async fn test_safe_method_issues_token() {
+        let (app, csrf) = app();
+        let server = TestServer::new(app).unwrap();
+
+        let response = server.get("/login").await;
+        response.assert_status_ok();
+        let cookie = response.cookie("csrf_token");
+        assert!(csrf.is_valid(cookie.value()));
+        response.assert_text_contains(cookie.value());
+    }
+
+    #[tokio::test]
+    async fn test_form_requires_matching_token() {
+        let (app, csrf) = app();
+        let server = TestServer::new(app).unwrap();
+        let token = csrf.generate();
+
+        let response = server
+            .post("/login")
+            .form(&LoginFormRequest {
+                username: "hadi".to_string(),
+                password: "password".to_string(),
+            })
+            .await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let response = server
+            .post("/login")
+            .add_cookie(Cookie::new("csrf_token", token.clone()))
+            .form(&LoginWithToken {
+                username: "hadi",
+                password: "password",
+                _csrf: &csrf.generate(),
+            })
+            .await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let response = server
+            .post("/login")
+            .add_cookie(Cookie::new("csrf_token", token.clone()))
+            .form(&LoginWithToken {
+                username: "hadi",
+                password: "password",
+                _csrf: &token,
+            })
+            .await;
+        response.assert_status_ok();
+        response.assert_text("Hello, hadi!");
+    }
+
+    #[tokio::test]
+    async fn test_multipart_uses_header() {
+        let (app, csrf) = app();
+        let server = TestServer::new(app).unwrap();
+        let token = csrf.generate();
+
+        let request = MultipartForm::new()
+            .add_text("username", "hadi")
+            .add_part("profile", Part::bytes("profile".as_bytes().to_vec()));
+        let response = server
+            .post("/upload")
+            .add_cookie(Cookie::new("csrf_token", token.clone()))
+            .add_header("X-CSRF-Token", token)
+            .multipart(request)
+            .await;
+        response.assert_status_ok();
+        response.assert_text("Fields: 2");
+    }
+
+    #[tokio::test]
+    async fn test_multipart_field() {
+        let (app, csrf) = app();
+        let server = TestServer::new(app).unwrap();
+        let token = csrf.generate();
+
+        // Large enough that only the start of the body is scanned.
+        let profile = vec![b'x'; MAX_FORM_SIZE + 64 * 1024];
+        let request = MultipartForm::new()
+            .add_text("_csrf", token.clone())
+            .add_text("username", "hadi")
+            .add_part("profile", Part::bytes(profile));
+        let response = server
+            .post("/upload")
+            .add_cookie(Cookie::new("csrf_token", token.clone()))
+            .multipart(request)
+            .await;
+        response.assert_status_ok();
+        response.assert_text("Fields: 3");
+
+        let request = MultipartForm::new()
+            .add_text("_csrf", csrf.generate())
+            .add_text("username", "hadi");
+        let response = server
+            .post("/upload")
+            .add_cookie(Cookie::new("csrf_token", token))
+            .multipart(request)
+            .await;
+        response.assert_status(StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_form_rejected() {
+        let (app, csrf) = app();
+        let server = TestServer::new(app).unwrap();
+        let token = csrf.generate();
+
+        let response = server
+            .post("/login")
+            .add_cookie(Cookie::new("csrf_token", token))
+            .content_type("application/x-www-form-urlencoded")
+            .bytes(vec![b'a'; MAX_FORM_SIZE + 1].into())
+            .await;
+        response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_bearer_requests_skip_check() {
+        let (app, _) = app();
+        let server = TestServer::new(app).unwrap();
+
+        let response = server
+            .post("/login")
+            .authorization_bearer("token")
+            .form(&LoginFormRequest {
+                username: "hadi".to_string(),
+                password: "password".to_string(),
+            })
+            .await;
+        response.assert_status_ok();
+    }
+
+    #[tokio::test]
+    async fn test_json_skips_check() {
+        async fn route(body: String) -> String {
+            body
+        }
+
+        let csrf = Arc::new(Csrf::new(CsrfConfig::default(), "secret"));
+        let app = Router::new()
+            .route("/login", post(route))
+            .layer(from_fn_with_state(csrf, super::csrf));
+        let server = TestServer::new(app).unwrap();
+
+        let response = server
+            .post("/login")
+            .json(&serde_json::json!({ "username": "hadi" }))
+            .await;
+        response.assert_status_ok();
+    }
+
+    #[tokio::test]
+    async fn test_tus_skips_check() {
+        async fn route(body: String) -> String {
+            body
+        }
+
+        let csrf = Arc::new(Csrf::new(CsrfConfig::default(), "secret"));
+        let app = Router::new()
+            .route("/uploads/tus", post(route))
+            .layer(from_fn_with_state(csrf, super::csrf));
+        let server = TestServer::new(app).unwrap();
+
+        let response = server
+            .post("/uploads/tus")
+            .add_header("Tus-Resumable", "1.0.0")
+            .content_type("application/offset+octet-stream")
+            .bytes("chunk".into())
+            .await;
+        response.assert_status_ok();
+    }
+
+    #[tokio::test]
+    async fn test_cross_origin_rejected() {
+        let (app, csrf) = app();
+        let server = TestServer::new(app).unwrap();
+        let token = csrf.generate();
+
+        let response = server
+            .post("/login")
+            .add_header("Host", "localhost")
+            .add_header("Origin", "https://evil.example")
+            .add_cookie(Cookie::new("csrf_token", token.clone()))
+            .add_header("X-CSRF-Token", token.clone())
+            .form(&LoginFormRequest {
+                username: "hadi".to_string(),
+                password: "password".to_string(),
+            })
+            .await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        response.assert_text_contains("Cross-origin request rejected");
+
+        let response = server
+            .post("/login")
+            .add_header("Host", "localhost")
+            .add_header("Referer", "http://localhost/login")
+            .add_cookie(Cookie::new("csrf_token", token.clone()))
+            .add_header("X-CSRF-Token", token)
+            .form(&LoginFormRequest {
+                username: "hadi".to_string(),
+                password: "password".to_string(),
+            })
+            .await;
+        response.assert_status_ok();
+    }
+}
@@ -0,0 +1,117 @@
+use axum::{Json, response::{IntoResponse, Response}};
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    pub code: u16,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub struct AppError {
+    pub status: StatusCode,
+    pub message: String,
+    pub headers: HeaderMap,
+}
+
+impl AppError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            headers: HeaderMap::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, message)
+    }
+
+    // The detail is logged; it may name paths or other internals, so clients
+    // only get a generic message.
+    pub fn internal(err: impl std::fmt::Display) -> Self {
+        tracing::error!("{}", err);
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error")
+    }
+
+    pub fn too_many_requests(retry_after_secs: u64) -> Self {
+        Self::new(StatusCode::TOO_MANY_REQUESTS, "Too Many Requests")
+            .with_header(http::header::RETRY_AFTER, HeaderValue::from(retry_after_secs))
+    }
+}
+
+// Marks responses built from an `AppError`, so `templates::html_errors` can
+// swap the JSON for an error page.
+#[derive(Debug, Clone)]
+pub struct ErrorMessage(pub String);
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        // 304 responses must not carry a body.
+        if self.status == StatusCode::NOT_MODIFIED {
+            return (self.status, self.headers).into_response();
+        }
+        let marker = ErrorMessage(self.message.clone());
+        let body = ErrorBody {
+            code: self.status.as_u16(),
+            message: self.message,
+        };
+        let mut response = (self.status, self.headers, Json(body)).into_response();
+        response.extensions_mut().insert(marker);
+        response
+    }
+}
+
+// For requests no route matched.
+pub async fn fallback(uri: Uri) -> AppError {
+    AppError::new(
+        StatusCode::NOT_FOUND,
+        format!("No route for {}", uri.path()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, routing::get};
+    use axum_test::TestServer;
+
+    #[tokio::test]
+    async fn test_app_error_json_body() {
+        async fn route() -> Result<String, AppError> {
+            Err(AppError::too_many_requests(30))
+        }
+
+        let app = Router::new().route("/", get(route));
+
+        let server = TestServer::new(app).unwrap();
+        let response = server.get("/").await;
+        response.assert_status(StatusCode::TOO_MANY_REQUESTS);
+        response.assert_header("Retry-After", "30");
+        response.assert_json(&serde_json::json!({
+            "code": 429,
+            "message": "Too Many Requests",
+        }));
+    }
+    #[tokio::test]
+    async fn test_internal_error_hides_detail() {
+        async fn route() -> Result<String, AppError> {
+            Err(AppError::internal("open /var/uploads/x.part: permission denied"))
+        }
+
+        let server = TestServer::new(Router::new().route("/", get(route))).unwrap();
+        let response = server.get("/").await;
+        response.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+        response.assert_json(&serde_json::json!({
+            "code": 500,
+            "message": "Internal Server Error",
+        }));
+    }
+}
@@ -0,0 +1,403 @@
+use std::{convert::Infallible, fmt};
+
+use axum::{
+    Json,
+    extract::FromRequestParts,
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use http::{HeaderMap, HeaderValue, Method, StatusCode, header, request::Parts};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::AppError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ETag {
+    pub tag: String,
+    pub weak: bool,
+}
+
+impl ETag {
+    pub fn strong(tag: impl Into<String>) -> Self {
+        Self {
+            tag: tag.into(),
+            weak: false,
+        }
+    }
+
+    pub fn weak(tag: impl Into<String>) -> Self {
+        Self {
+            tag: tag.into(),
+            weak: true,
+        }
+    }
+
+    // Strong tag over the exact bytes of a representation.
+    pub fn for_bytes(bytes: &[u8]) -> Self {
+        Self::for_sha256(&Sha256::digest(bytes))
+    }
+
+    // Same tag as `for_bytes`, from a digest computed elsewhere.
+    pub fn for_sha256(digest: &[u8]) -> Self {
+        Self::strong(URL_SAFE_NO_PAD.encode(&digest[..16]))
+    }
+
+    // Tag of `value` as serialized by `Json`, so handlers can compute the
+    // current tag of a resource without building a response.
+    pub fn for_json<T: Serialize>(value: &T) -> Self {
+        Self::for_bytes(&serde_json::to_vec(value).unwrap_or_default())
+    }
+
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+
+    pub fn header_value(&self) -> HeaderValue {
+        HeaderValue::try_from(self.to_string()).unwrap()
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/\"{}\"", self.tag)
+        } else {
+            write!(f, "\"{}\"", self.tag)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntityTags {
+    Any,
+    List(Vec<ETag>),
+}
+
+impl EntityTags {
+    // `*` or a comma separated list of `"tag"` / `W/"tag"`. Tags may contain
+    // commas, so this scans quotes rather than splitting.
+    pub fn parse(value: &str) -> Option<Self> {
+        if value.trim() == "*" {
+            return Some(EntityTags::Any);
+        }
+        let mut tags = Vec::new();
+        let mut rest = value;
+        loop {
+            rest = rest.trim_start_matches([' ', '\t', ',']);
+            if rest.is_empty() {
+                break;
+            }
+            let (weak, quoted) = match rest.strip_prefix("W/") {
+                Some(quoted) => (true, quoted),
+                None => (false, rest),
+            };
+            let (tag, remainder) = quoted.strip_prefix('"')?.split_once('"')?;
+            tags.push(ETag {
+                tag: tag.to_string(),
+                weak,
+            });
+            rest = remainder;
+        }
+        Some(EntityTags::List(tags))
+    }
+
+    fn from_headers(headers: &HeaderMap, name: header::HeaderName) -> Option<Self> {
+        let values: Vec<_> = headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        if values.is_empty() {
+            return None;
+        }
+        Self::parse(&values.join(","))
+    }
+
+    fn matches(&self, current: Option<&ETag>, strong: bool) -> bool {
+        match (self, current) {
+            (_, None) => false,
+            (EntityTags::Any, Some(_)) => true,
+            (EntityTags::List(tags), Some(current)) => tags.iter().any(|tag| {
+                if strong {
+                    tag.strong_eq(current)
+                } else {
+                    tag.weak_eq(current)
+                }
+            }),
+        }
+    }
+}
+
+// Why a conditional request was not carried out.
+#[derive(Debug)]
+pub struct Unsatisfied {
+    pub status: StatusCode,
+    pub etag: Option<ETag>,
+}
+
+impl From<Unsatisfied> for AppError {
+    fn from(unsatisfied: Unsatisfied) -> Self {
+        let message = if unsatisfied.status == StatusCode::NOT_MODIFIED {
+            "Not Modified"
+        } else {
+            "Precondition Failed"
+        };
+        let error = AppError::new(unsatisfied.status, message);
+        match unsatisfied.etag {
+            Some(etag) => error.with_header(header::ETAG, etag.header_value()),
+            None => error,
+        }
+    }
+}
+
+impl IntoResponse for Unsatisfied {
+    fn into_response(self) -> Response {
+        AppError::from(self).into_response()
+    }
+}
+
+// `If-Match` / `If-None-Match` of the current request.
+#[derive(Debug, Clone)]
+pub struct Preconditions {
+    pub method: Method,
+    pub if_match: Option<EntityTags>,
+    pub if_none_match: Option<EntityTags>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Preconditions {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self {
+            method: parts.method.clone(),
+            if_match: EntityTags::from_headers(&parts.headers, header::IF_MATCH),
+            if_none_match: EntityTags::from_headers(&parts.headers, header::IF_NONE_MATCH),
+        })
+    }
+}
+
+impl Preconditions {
+    pub fn is_conditional(&self) -> bool {
+        self.if_match.is_some() || self.if_none_match.is_some()
+    }
+
+    fn is_read(&self) -> bool {
+        self.method == Method::GET || self.method == Method::HEAD
+    }
+
+    // Evaluates the conditions against the resource's current tag (`None`
+    // when it does not exist), in the order of RFC 9110 section 13.2.2.
+    // Call this before applying an update or delete.
+    pub fn check(&self, current: Option<&ETag>) -> Result<(), Unsatisfied> {
+        let unsatisfied = |status| Unsatisfied {
+            status,
+            etag: current.cloned(),
+        };
+
+        if let Some(if_match) = &self.if_match
+            && !if_match.matches(current, true)
+        {
+            return Err(unsatisfied(StatusCode::PRECONDITION_FAILED));
+        }
+        if let Some(if_none_match) = &self.if_none_match
+            && if_none_match.matches(current, false)
+        {
+            return Err(unsatisfied(if self.is_read() {
+                StatusCode::NOT_MODIFIED
+            } else {
+                StatusCode::PRECONDITION_FAILED
+            }));
+        }
+        Ok(())
+    }
+
+    // Wraps `value` in a JSON response carrying its ETag, answering 304 when
+    // a GET or HEAD already has the same representation.
+    pub fn json<T: Serialize>(&self, value: T) -> Conditional<T> {
+        Conditional {
+            preconditions: self.clone(),
+            value,
+        }
+    }
+}
+
+pub struct Conditional<T> {
+    preconditions: Preconditions,
+    value: T,
+}
+
+impl<T: Serialize> IntoResponse for Conditional<T> {
+    fn into_response(self) -> Response {
+        let body = match serde_json::to_vec(&self.value) {
+            Ok(body) => body,
+            Err(err) => return AppError::internal(err).into_response(),
+        };
+        let etag = ETag::for_bytes(&body);
+
+        if self.preconditions.is_read()
+            && let Some(if_none_match) = &self.preconditions.if_none_match
+            && if_none_match.matches(Some(&etag), false)
+        {
+            return Unsatisfied {
+                status: StatusCode::NOT_MODIFIED,
+                etag: Some(etag),
+            }
+            .into_response();
+        }
+
+        let mut response = Json(self.value).into_response();
+        response
+            .headers_mut()
+            .insert(header::ETAG, etag.header_value());
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use axum::{Router, extract::State, routing::get};
+    use axum_test::TestServer;
+    use serde::Deserialize;
+
+    #[test]
+    fn test_parse_entity_tags() {
+        assert_eq!(EntityTags::parse("*"), Some(EntityTags::Any));
+        assert_eq!(
+            EntityTags::parse(r#""a", W/"b,c" ,"d""#),
+            Some(EntityTags::List(vec![
+                ETag::strong("a"),
+                ETag::weak("b,c"),
+                ETag::strong("d"),
+            ]))
+        );
+        assert_eq!(EntityTags::parse("unquoted"), None);
+
+        assert!(ETag::strong("a").strong_eq(&ETag::strong("a")));
+        assert!(!ETag::weak("a").strong_eq(&ETag::strong("a")));
+        assert!(ETag::weak("a").weak_eq(&ETag::strong("a")));
+        assert_eq!(ETag::weak("a").to_string(), r#"W/"a""#);
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct Profile {
+        name: String,
+    }
+
+    fn app() -> Router {
+        async fn show(
+            State(profile): State<Arc<Mutex<Profile>>>,
+            preconditions: Preconditions,
+        ) -> Conditional<Profile> {
+            preconditions.json(profile.lock().unwrap().clone())
+        }
+
+        async fn update(
+            State(profile): State<Arc<Mutex<Profile>>>,
+            preconditions: Preconditions,
+            Json(update): Json<Profile>,
+        ) -> Result<Conditional<Profile>, AppError> {
+            let mut profile = profile.lock().unwrap();
+            preconditions.check(Some(&ETag::for_json(&*profile)))?;
+            *profile = update;
+            Ok(preconditions.json(profile.clone()))
+        }
+
+        let profile = Profile {
+            name: "hadi".to_string(),
+        };
+        Router::new()
+            .route("/profile", get(show).put(update))
+            .with_state(Arc::new(Mutex::new(profile)))
+    }
+
+    #[tokio::test]
+    async fn test_if_none_match() {
+        let server = TestServer::new(app()).unwrap();
+
+        let response = server.get("/profile").await;
+        response.assert_status_ok();
+        let etag = response.header("ETag").to_str().unwrap().to_string();
+        assert_eq!(
+            etag,
+            ETag::for_json(&Profile {
+                name: "hadi".to_string()
+            })
+            .to_string()
+        );
+
+        let response = server
+            .get("/profile")
+            .add_header("If-None-Match", &etag)
+            .await;
+        response.assert_status(StatusCode::NOT_MODIFIED);
+        response.assert_header("ETag", &etag);
+        assert!(response.as_bytes().is_empty());
+
+        // Weak comparison applies to If-None-Match.
+        let weak = format!("W/{}", etag);
+        let response = server
+            .get("/profile")
+            .add_header("If-None-Match", &weak)
+            .await;
+        response.assert_status(StatusCode::NOT_MODIFIED);
+
+        let response = server
+            .get("/profile")
+            .add_header("If-None-Match", r#""stale""#)
+            .await;
+        response.assert_status_ok();
+    }
+
+    #[tokio::test]
+    async fn test_if_match_lost_update() {
+        let server = TestServer::new(app()).unwrap();
+        let etag = server
+            .get("/profile")
+            .await
+            .header("ETag")
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let response = server
+            .put("/profile")
+            .add_header("If-Match", &etag)
+            .json(&serde_json::json!({"name": "first"}))
+            .await;
+        response.assert_status_ok();
+        assert_ne!(response.header("ETag").to_str().unwrap(), etag);
+
+        // A second writer still holding the old tag is refused.
+        let response = server
+            .put("/profile")
+            .add_header("If-Match", &etag)
+            .json(&serde_json::json!({"name": "second"}))
+            .await;
+        response.assert_status(StatusCode::PRECONDITION_FAILED);
+        response.assert_json(&serde_json::json!({
+            "code": 412,
+            "message": "Precondition Failed",
+        }));
+
+        // If-Match uses strong comparison, so weak tags never match.
+        let response = server
+            .put("/profile")
+            .add_header("If-Match", format!("W/{}", etag))
+            .json(&serde_json::json!({"name": "third"}))
+            .await;
+        response.assert_status(StatusCode::PRECONDITION_FAILED);
+
+        server
+            .get("/profile")
+            .await
+            .assert_json(&serde_json::json!({"name": "first"}));
+    }
+}
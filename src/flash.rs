@@ -0,0 +1,236 @@
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    middleware::Next,
+    response::{IntoResponse, IntoResponseParts, Response, ResponseParts},
+};
+use axum_extra::extract::{
+    CookieJar, SignedCookieJar,
+    cookie::{Cookie, Key, SameSite},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use http::request::Parts;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+
+#[derive(Debug, Clone)]
+pub struct FlashConfig {
+    pub cookie_name: String,
+    pub secure_cookie: bool,
+}
+
+impl Default for FlashConfig {
+    fn default() -> Self {
+        Self {
+            cookie_name: "flash".to_string(),
+            secure_cookie: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Info,
+    Success,
+    Warning,
+    Error,
+}
+
+// A one-time message shown by the layout.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlashMessage {
+    pub level: Level,
+    pub message: String,
+}
+
+// Messages that arrived with the current request, put in the request
+// extensions by `flash`.
+#[derive(Debug, Clone, Default)]
+pub struct FlashMessages(pub Vec<FlashMessage>);
+
+// Messages a handler queued for the next request.
+#[derive(Debug, Clone)]
+struct Outgoing(Vec<FlashMessage>);
+
+pub struct Flashes {
+    config: FlashConfig,
+    key: Key,
+}
+
+impl Flashes {
+    pub fn new(config: FlashConfig, secret: &str) -> Self {
+        // `Key` takes exactly 64 bytes of key material.
+        let mut master = Sha512::new();
+        master.update(b"flash:");
+        master.update(secret.as_bytes());
+        Self {
+            config,
+            key: Key::from(&master.finalize()),
+        }
+    }
+
+    fn decode(&self, jar: &SignedCookieJar) -> Option<Vec<FlashMessage>> {
+        let cookie = jar.get(&self.config.cookie_name)?;
+        let json = URL_SAFE_NO_PAD.decode(cookie.value()).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    fn cookie(&self, value: String) -> Cookie<'static> {
+        Cookie::build((self.config.cookie_name.clone(), value))
+            .path("/")
+            .http_only(true)
+            .secure(self.config.secure_cookie)
+            .same_site(SameSite::Lax)
+            .build()
+    }
+}
+
+// Reads flash messages from the signed cookie and clears it, so each message
+// is shown on exactly one request. Messages a handler queued with `Flash`
+// replace the cookie instead.
+pub async fn flash(
+    State(flashes): State<Arc<Flashes>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let jar = SignedCookieJar::from_headers(request.headers(), flashes.key.clone());
+    // Also holds cookies that failed verification, so they can be removed.
+    let plain = CookieJar::from_headers(request.headers());
+    let has_cookie = plain.get(&flashes.config.cookie_name).is_some();
+    let incoming = flashes.decode(&jar).unwrap_or_default();
+    request.extensions_mut().insert(FlashMessages(incoming));
+
+    let mut response = next.run(request).await;
+    match response.extensions_mut().remove::<Outgoing>() {
+        Some(Outgoing(messages)) => {
+            let value = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&messages).unwrap());
+            (jar.add(flashes.cookie(value)), response).into_response()
+        }
+        // Consumed, or tampered with.
+        None if has_cookie => {
+            (plain.remove(flashes.cookie(String::new())), response).into_response()
+        }
+        None => response,
+    }
+}
+
+// As a response part, queues messages for the next request, typically
+// together with a redirect. The current request's messages are in
+// `FlashMessages`.
+#[derive(Debug, Clone, Default)]
+pub struct Flash {
+    outgoing: Vec<FlashMessage>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Flash {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(_parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::default())
+    }
+}
+
+impl Flash {
+    pub fn push(mut self, level: Level, message: impl Into<String>) -> Self {
+        self.outgoing.push(FlashMessage {
+            level,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn success(self, message: impl Into<String>) -> Self {
+        self.push(Level::Success, message)
+    }
+
+    pub fn error(self, message: impl Into<String>) -> Self {
+        self.push(Level::Error, message)
+    }
+}
+
+impl IntoResponseParts for Flash {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        if !self.outgoing.is_empty() {
+            res.extensions_mut().insert(Outgoing(self.outgoing));
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Extension, Router,
+        middleware::from_fn_with_state,
+        response::Redirect,
+        routing::{get, post},
+    };
+    use axum_test::TestServer;
+    use http::StatusCode;
+
+    fn server() -> TestServer {
+        async fn save(flash: Flash) -> (Flash, Redirect) {
+            (
+                flash
+                    .success("Saved")
+                    .push(Level::Warning, "Check <the> price"),
+                Redirect::to("/show"),
+            )
+        }
+
+        async fn show(Extension(FlashMessages(messages)): Extension<FlashMessages>) -> String {
+            messages
+                .iter()
+                .map(|flash| format!("{:?}: {}", flash.level, flash.message))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+
+        let app = Router::new()
+            .route("/save", post(save))
+            .route("/show", get(show))
+            .layer(from_fn_with_state(
+                Arc::new(Flashes::new(FlashConfig::default(), "secret")),
+                flash,
+            ));
+        TestServer::new(app).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_flash_across_redirect() {
+        let mut server = server();
+        server.save_cookies();
+
+        let response = server.post("/save").await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        assert!(response.cookie("flash").http_only().unwrap());
+
+        let response = server.get("/show").await;
+        response.assert_text("Success: Saved\nWarning: Check <the> price");
+
+        // Shown once.
+        server.get("/show").await.assert_text("");
+    }
+
+    #[tokio::test]
+    async fn test_tampered_flash_is_dropped() {
+        let server = server();
+        let response = server.post("/save").await;
+        // A valid signature in front of different messages.
+        let cookie = response.cookie("flash");
+        let signature = &cookie.value()[..44];
+        let forged = URL_SAFE_NO_PAD.encode(br#"[{"level":"error","message":"forged"}]"#);
+
+        let response = server
+            .get("/show")
+            .add_cookie(Cookie::new("flash", format!("{}{}", signature, forged)))
+            .await;
+        response.assert_text("");
+        assert_eq!(response.cookie("flash").value(), "");
+    }
+}
@@ -0,0 +1,772 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Error, ErrorExtensions, ID, Object, Schema,
+    SimpleObject,
+    dataloader::{DataLoader, Loader},
+    http::parse_query_string,
+};
+use axum::{
+    Json, Router,
+    extract::{Path, RawQuery, State},
+    response::{Html, IntoResponse, Response},
+    routing::get,
+};
+use futures_util::future::BoxFuture;
+use http::{HeaderMap, StatusCode, header};
+use rust_embed::RustEmbed;
+use utoipa::OpenApi;
+
+use crate::{
+    auth::{AuthUser, TokenService},
+    error::{AppError, ErrorBody},
+    list_params::{ListParams, ListSpec, Page},
+    negotiate::accepts_html,
+    products::{
+        CatalogRepository, Category, CategoryId, Product, ProductId, ProductList, RepositoryError,
+    },
+    security_headers::CspNonce,
+};
+
+// The query editor served by `graphiql()`.
+#[derive(RustEmbed)]
+#[folder = "assets/graphql/"]
+struct Explorer;
+
+#[derive(Debug, Clone)]
+pub struct GraphqlConfig {
+    // Deepest nesting of selections a query may use.
+    pub max_depth: usize,
+    // Every field costs 1, lists cost their limit times their selection.
+    pub max_complexity: usize,
+    // Serve a query editor to browsers on `GET /graphql`.
+    pub graphiql: bool,
+}
+
+impl Default for GraphqlConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: 10,
+            max_complexity: 500,
+            graphiql: cfg!(debug_assertions),
+        }
+    }
+}
+
+// Object-safe view of a `CatalogRepository`, so the schema types don't have
+// to be generic over it.
+trait Catalog: Send + Sync {
+    fn categories(&self) -> BoxFuture<'_, Result<Vec<Category>, RepositoryError>>;
+    fn categories_by_id<'a>(
+        &'a self,
+        ids: &'a [CategoryId],
+    ) -> BoxFuture<'a, Result<Vec<Category>, RepositoryError>>;
+    fn products(
+        &self,
+        params: ListParams<ProductList>,
+    ) -> BoxFuture<'_, Result<Page<Product>, RepositoryError>>;
+    fn product(&self, id: ProductId) -> BoxFuture<'_, Result<Product, RepositoryError>>;
+    fn products_by_category<'a>(
+        &'a self,
+        ids: &'a [CategoryId],
+        limit: usize,
+    ) -> BoxFuture<'a, Result<Vec<Product>, RepositoryError>>;
+}
+
+impl<R: CatalogRepository> Catalog for R {
+    fn categories(&self) -> BoxFuture<'_, Result<Vec<Category>, RepositoryError>> {
+        Box::pin(self.list_categories())
+    }
+
+    fn categories_by_id<'a>(
+        &'a self,
+        ids: &'a [CategoryId],
+    ) -> BoxFuture<'a, Result<Vec<Category>, RepositoryError>> {
+        Box::pin(self.get_categories(ids))
+    }
+
+    fn products(
+        &self,
+        params: ListParams<ProductList>,
+    ) -> BoxFuture<'_, Result<Page<Product>, RepositoryError>> {
+        Box::pin(async move { self.list_products(&params).await })
+    }
+
+    fn product(&self, id: ProductId) -> BoxFuture<'_, Result<Product, RepositoryError>> {
+        Box::pin(self.get_product(id))
+    }
+
+    fn products_by_category<'a>(
+        &'a self,
+        ids: &'a [CategoryId],
+        limit: usize,
+    ) -> BoxFuture<'a, Result<Vec<Product>, RepositoryError>> {
+        Box::pin(self.products_in_categories(ids, limit))
+    }
+}
+
+// GraphQL errors carry the status the REST API would have answered with.
+fn graphql_error(error: AppError) -> Error {
+    let code = error.status.as_u16();
+    Error::new(error.message).extend_with(|_, extensions| extensions.set("code", code))
+}
+
+fn repository_error(error: RepositoryError) -> Error {
+    graphql_error(error.into())
+}
+
+fn parse_id(id: &ID) -> Result<i64, Error> {
+    id.parse()
+        .map_err(|_| graphql_error(AppError::new(StatusCode::BAD_REQUEST, "Invalid ID")))
+}
+
+// Page sizes are checked like the `limit` of `GET /products`.
+fn page_size(limit: Option<usize>) -> Result<usize, Error> {
+    let max = <ProductList as ListSpec>::MAX_LIMIT;
+    match limit {
+        None => Ok(<ProductList as ListSpec>::DEFAULT_LIMIT),
+        Some(limit) if (1..=max).contains(&limit) => Ok(limit),
+        Some(_) => Err(graphql_error(AppError::new(
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {}", max),
+        ))),
+    }
+}
+
+// A list costs its page size times its selection. Out of range limits are
+// rejected when the field runs, so they are costed as the largest page.
+fn list_complexity(limit: Option<usize>, child_complexity: usize) -> usize {
+    limit
+        .unwrap_or(<ProductList as ListSpec>::DEFAULT_LIMIT)
+        .min(<ProductList as ListSpec>::MAX_LIMIT)
+        .saturating_mul(child_complexity)
+}
+
+fn require_user<'a>(ctx: &Context<'a>) -> Result<&'a AuthUser, Error> {
+    ctx.data_opt::<AuthUser>()
+        .ok_or_else(|| graphql_error(AppError::unauthorized("Missing credentials")))
+}
+
+// Batches the categories of every product in a response into one lookup.
+struct CategoryLoader(Arc<dyn Catalog>);
+
+impl Loader<CategoryId> for CategoryLoader {
+    type Value = Category;
+    type Error = Error;
+
+    async fn load(&self, ids: &[CategoryId]) -> Result<HashMap<CategoryId, Category>, Error> {
+        let categories = self
+            .0
+            .categories_by_id(ids)
+            .await
+            .map_err(repository_error)?;
+        Ok(categories
+            .into_iter()
+            .map(|category| (category.id, category))
+            .collect())
+    }
+}
+
+// Batches the products of every category in a response into one lookup,
+// fetching at most the largest page of each.
+struct ProductsLoader(Arc<dyn Catalog>);
+
+impl Loader<CategoryId> for ProductsLoader {
+    type Value = Vec<Product>;
+    type Error = Error;
+
+    async fn load(&self, ids: &[CategoryId]) -> Result<HashMap<CategoryId, Vec<Product>>, Error> {
+        let products = self
+            .0
+            .products_by_category(ids, <ProductList as ListSpec>::MAX_LIMIT)
+            .await
+            .map_err(repository_error)?;
+        let mut by_category: HashMap<CategoryId, Vec<Product>> = HashMap::new();
+        for product in products {
+            by_category
+                .entry(product.category_id)
+                .or_default()
+                .push(product);
+        }
+        Ok(by_category)
+    }
+}
+
+struct CategoryObject(Category);
+
+#[Object(name = "Category")]
+impl CategoryObject {
+    async fn id(&self) -> ID {
+        self.0.id.0.into()
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    #[graphql(complexity = "list_complexity(limit, child_complexity)")]
+    async fn products(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ProductObject>, Error> {
+        let limit = page_size(limit)?;
+        let products = ctx
+            .data_unchecked::<DataLoader<ProductsLoader>>()
+            .load_one(self.0.id)
+            .await?;
+        Ok(products
+            .unwrap_or_default()
+            .into_iter()
+            .take(limit)
+            .map(ProductObject)
+            .collect())
+    }
+}
+
+struct ProductObject(Product);
+
+#[Object(name = "Product")]
+impl ProductObject {
+    async fn id(&self) -> ID {
+        self.0.id.0.into()
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn price_cents(&self) -> i64 {
+        self.0.price_cents
+    }
+
+    async fn category(&self, ctx: &Context<'_>) -> Result<Option<CategoryObject>, Error> {
+        let category = ctx
+            .data_unchecked::<DataLoader<CategoryLoader>>()
+            .load_one(self.0.category_id)
+            .await?;
+        Ok(category.map(CategoryObject))
+    }
+}
+
+#[derive(SimpleObject)]
+struct ProductPage {
+    items: Vec<ProductObject>,
+    // Only reported for offset pagination.
+    total: Option<u64>,
+    has_more: bool,
+    next_cursor: Option<String>,
+}
+
+#[derive(SimpleObject)]
+struct User {
+    username: String,
+}
+
+struct Query;
+
+#[Object]
+impl Query {
+    async fn categories(&self, ctx: &Context<'_>) -> Result<Vec<CategoryObject>, Error> {
+        let categories = ctx
+            .data_unchecked::<Arc<dyn Catalog>>()
+            .categories()
+            .await
+            .map_err(repository_error)?;
+        Ok(categories.into_iter().map(CategoryObject).collect())
+    }
+
+    async fn category(&self, ctx: &Context<'_>, id: ID) -> Result<Option<CategoryObject>, Error> {
+        let category = ctx
+            .data_unchecked::<DataLoader<CategoryLoader>>()
+            .load_one(CategoryId(parse_id(&id)?))
+            .await?;
+        Ok(category.map(CategoryObject))
+    }
+
+    // Takes the same paging and sorting as `GET /products`.
+    #[graphql(complexity = "list_complexity(limit, child_complexity)")]
+    async fn products(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<usize>,
+        offset: Option<u64>,
+        cursor: Option<String>,
+        sort: Option<String>,
+        category_id: Option<ID>,
+    ) -> Result<ProductPage, Error> {
+        let mut query = Vec::new();
+        if let Some(limit) = limit {
+            query.push(("limit", limit.to_string()));
+        }
+        if let Some(offset) = offset {
+            query.push(("offset", offset.to_string()));
+        }
+        if let Some(cursor) = cursor {
+            query.push(("cursor", cursor));
+        }
+        if let Some(sort) = sort {
+            query.push(("sort", sort));
+        }
+        if let Some(category_id) = category_id {
+            query.push(("category_id", parse_id(&category_id)?.to_string()));
+        }
+        let uri = format!("/products?{}", serde_urlencoded::to_string(query).unwrap());
+        let params = ListParams::<ProductList>::parse(uri.parse().unwrap())
+            .map_err(|message| graphql_error(AppError::new(StatusCode::BAD_REQUEST, message)))?;
+
+        let page = ctx
+            .data_unchecked::<Arc<dyn Catalog>>()
+            .products(params)
+            .await
+            .map_err(repository_error)?;
+        Ok(ProductPage {
+            items: page.data.into_iter().map(ProductObject).collect(),
+            total: page.page.total,
+            has_more: page.page.has_more,
+            next_cursor: page.page.next_cursor,
+        })
+    }
+
+    async fn product(&self, ctx: &Context<'_>, id: ID) -> Result<Option<ProductObject>, Error> {
+        match ctx
+            .data_unchecked::<Arc<dyn Catalog>>()
+            .product(ProductId(parse_id(&id)?))
+            .await
+        {
+            Ok(product) => Ok(Some(ProductObject(product))),
+            Err(RepositoryError::NotFound(_)) => Ok(None),
+            Err(err) => Err(repository_error(err)),
+        }
+    }
+
+    // The caller, from a bearer token or the session cookie.
+    async fn me(&self, ctx: &Context<'_>) -> Result<User, Error> {
+        Ok(User {
+            username: require_user(ctx)?.username.clone(),
+        })
+    }
+}
+
+type CatalogSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+#[derive(Clone)]
+pub struct GraphqlState {
+    config: Arc<GraphqlConfig>,
+    schema: CatalogSchema,
+    catalog: Arc<dyn Catalog>,
+    tokens: Arc<TokenService>,
+}
+
+impl GraphqlState {
+    pub fn new<R: CatalogRepository>(
+        config: GraphqlConfig,
+        catalog: Arc<R>,
+        tokens: Arc<TokenService>,
+    ) -> Self {
+        Self::with_catalog(config, catalog, tokens)
+    }
+
+    fn with_catalog(
+        config: GraphqlConfig,
+        catalog: Arc<dyn Catalog>,
+        tokens: Arc<TokenService>,
+    ) -> Self {
+        let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+            .limit_depth(config.max_depth)
+            .limit_complexity(config.max_complexity)
+            .data(catalog.clone())
+            .finish();
+        Self {
+            config: Arc::new(config),
+            schema,
+            catalog,
+            tokens,
+        }
+    }
+
+    // Loaders are per request, so nothing is cached across callers.
+    async fn execute(
+        &self,
+        request: async_graphql::Request,
+        user: Option<AuthUser>,
+    ) -> async_graphql::Response {
+        let mut request = request
+            .data(DataLoader::new(
+                CategoryLoader(self.catalog.clone()),
+                tokio::spawn,
+            ))
+            .data(DataLoader::new(
+                ProductsLoader(self.catalog.clone()),
+                tokio::spawn,
+            ));
+        if let Some(user) = user {
+            request = request.data(user);
+        }
+        self.schema.execute(request).await
+    }
+}
+
+impl axum::extract::FromRef<GraphqlState> for Arc<TokenService> {
+    fn from_ref(state: &GraphqlState) -> Self {
+        state.tokens.clone()
+    }
+}
+
+// Anonymous callers can still read the catalog, so a missing or invalid
+// token only matters to the fields that need a user.
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    tag = "graphql",
+    request_body(
+        content_type = "application/json",
+        description = "`query`, `variables` and `operationName`",
+    ),
+    responses(
+        (status = 200, description = "`data` and `errors`", content_type = "application/json"),
+    )
+)]
+async fn execute(
+    State(state): State<GraphqlState>,
+    user: Result<AuthUser, AppError>,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    Json(state.execute(request, user.ok()).await)
+}
+
+#[utoipa::path(
+    get,
+    path = "/graphql",
+    tag = "graphql",
+    params(
+        ("query" = Option<String>, Query, description = "Without it, browsers get a query editor"),
+        ("variables" = Option<String>, Query, description = "JSON object"),
+        ("operationName" = Option<String>, Query),
+    ),
+    responses(
+        (status = 200, description = "`data` and `errors`, or the query editor", content_type = "application/json"),
+        (status = 400, description = "Malformed query string", body = ErrorBody),
+    )
+)]
+async fn execute_get(
+    State(state): State<GraphqlState>,
+    headers: HeaderMap,
+    nonce: CspNonce,
+    user: Result<AuthUser, AppError>,
+    RawQuery(query): RawQuery,
+) -> Result<Response, AppError> {
+    if state.config.graphiql && query.is_none() && accepts_html(&headers) {
+        return Ok(graphiql(nonce).into_response());
+    }
+    let request = parse_query_string(query.as_deref().unwrap_or_default())
+        .map_err(|err| AppError::new(StatusCode::BAD_REQUEST, err.to_string()))?;
+    Ok(Json(state.execute(request, user.ok()).await).into_response())
+}
+
+// A small query editor instead of GraphiQL, whose React bundle would have
+// to come from a CDN. Its assets are served from `/graphql/assets` so the
+// page runs under the default CSP.
+fn graphiql(CspNonce(nonce): CspNonce) -> Html<String> {
+    Html(format!(
+        r##"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>GraphQL</title>
+<link rel="stylesheet" href="/graphql/assets/explorer.css">
+</head>
+<body>
+<header><h1>GraphQL</h1></header>
+<main>
+<form id="explorer" action="/graphql">
+<label for="query">Query</label>
+<textarea id="query" name="query" spellcheck="false">{{ categories {{ id name }} }}</textarea>
+<label for="variables">Variables</label>
+<textarea id="variables" name="variables" spellcheck="false" placeholder="{{}}"></textarea>
+<label for="token">Bearer token</label>
+<input id="token" name="token" autocomplete="off" placeholder="Optional; the session cookie is sent too">
+<button type="submit">Run</button>
+</form>
+<pre id="result" aria-live="polite"></pre>
+</main>
+<script nonce="{nonce}" src="/graphql/assets/explorer.js"></script>
+</body>
+</html>"##
+    ))
+}
+
+async fn graphiql_asset(
+    State(state): State<GraphqlState>,
+    Path(file): Path<String>,
+) -> Result<Response, AppError> {
+    let asset = Explorer::get(&file)
+        .filter(|_| state.config.graphiql)
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "Asset not found"))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, asset.metadata.mimetype().to_string()),
+            (header::CACHE_CONTROL, "public, max-age=86400".to_string()),
+        ],
+        asset.data,
+    )
+        .into_response())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(execute, execute_get))]
+pub struct GraphqlApi;
+
+pub fn router(state: GraphqlState) -> Router {
+    Router::new()
+        .route("/graphql", get(execute_get).post(execute))
+        .route("/graphql/assets/{file}", get(graphiql_asset))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        products::{MemoryCatalog, NewCategory, NewProduct},
+        security_headers::{SecurityHeadersConfig, security_headers},
+    };
+    use axum::middleware::from_fn_with_state;
+    use axum_test::TestServer;
+    use serde_json::{Value, json};
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    // Counts the batched lookups made by the loaders.
+    #[derive(Default)]
+    struct Counting {
+        catalog: MemoryCatalog,
+        lookups: AtomicUsize,
+    }
+
+    impl Catalog for Counting {
+        fn categories(&self) -> BoxFuture<'_, Result<Vec<Category>, RepositoryError>> {
+            self.catalog.categories()
+        }
+
+        fn categories_by_id<'a>(
+            &'a self,
+            ids: &'a [CategoryId],
+        ) -> BoxFuture<'a, Result<Vec<Category>, RepositoryError>> {
+            self.lookups.fetch_add(1, Ordering::Relaxed);
+            self.catalog.categories_by_id(ids)
+        }
+
+        fn products(
+            &self,
+            params: ListParams<ProductList>,
+        ) -> BoxFuture<'_, Result<Page<Product>, RepositoryError>> {
+            self.catalog.products(params)
+        }
+
+        fn product(&self, id: ProductId) -> BoxFuture<'_, Result<Product, RepositoryError>> {
+            self.catalog.product(id)
+        }
+
+        fn products_by_category<'a>(
+            &'a self,
+            ids: &'a [CategoryId],
+            limit: usize,
+        ) -> BoxFuture<'a, Result<Vec<Product>, RepositoryError>> {
+            self.lookups.fetch_add(1, Ordering::Relaxed);
+            self.catalog.products_by_category(ids, limit)
+        }
+    }
+
+    async fn setup(config: GraphqlConfig) -> (TestServer, Arc<Counting>, String) {
+        let counting = Arc::new(Counting::default());
+        for category in ["Books", "Games"] {
+            let category = counting
+                .catalog
+                .create_category(NewCategory {
+                    name: category.to_string(),
+                })
+                .await
+                .unwrap();
+            for name in ["First", "Second"] {
+                counting
+                    .catalog
+                    .create_product(NewProduct {
+                        category_id: category.id,
+                        name: name.to_string(),
+                        price_cents: 1000,
+                    })
+                    .await
+                    .unwrap();
+            }
+        }
+        let tokens = Arc::new(TokenService::new("secret", Duration::from_secs(60)));
+        let token = tokens.issue("hadi");
+        let state = GraphqlState::with_catalog(config, counting.clone(), tokens);
+        let app = router(state).layer(from_fn_with_state(
+            Arc::new(SecurityHeadersConfig::default()),
+            security_headers,
+        ));
+        (TestServer::new(app).unwrap(), counting, token)
+    }
+
+    async fn query(server: &TestServer, query: &str) -> Value {
+        server
+            .post("/graphql")
+            .json(&json!({ "query": query }))
+            .await
+            .json()
+    }
+
+    #[tokio::test]
+    async fn test_batched_queries() {
+        let (server, counting, _) = setup(GraphqlConfig::default()).await;
+
+        let response = query(
+            &server,
+            "{ products(sort: \"-id\", limit: 3) { items { name category { name } } hasMore } }",
+        )
+        .await;
+        assert_eq!(
+            response["data"]["products"],
+            json!({
+                "items": [
+                    {"name": "Second", "category": {"name": "Games"}},
+                    {"name": "First", "category": {"name": "Games"}},
+                    {"name": "Second", "category": {"name": "Books"}},
+                ],
+                "hasMore": true,
+            })
+        );
+        // One lookup for the categories of all three products.
+        assert_eq!(counting.lookups.swap(0, Ordering::Relaxed), 1);
+
+        let response = query(&server, "{ categories { name products { id } } }").await;
+        assert_eq!(
+            response["data"]["categories"][1],
+            json!({"name": "Games", "products": [{"id": "3"}, {"id": "4"}]})
+        );
+        assert_eq!(counting.lookups.swap(0, Ordering::Relaxed), 1);
+        let response = query(&server, "{ categories { products(limit: 1) { id } } }").await;
+        assert_eq!(
+            response["data"]["categories"],
+            json!([{"products": [{"id": "1"}]}, {"products": [{"id": "3"}]}])
+        );
+
+        let response = query(&server, "{ product(id: \"99\") { id } }").await;
+        assert_eq!(response["data"]["product"], Value::Null);
+        let response = query(&server, "{ products(limit: 0) { hasMore } }").await;
+        assert_eq!(
+            response["errors"][0]["message"],
+            "limit must be between 1 and 100"
+        );
+        assert_eq!(response["errors"][0]["extensions"]["code"], 400);
+    }
+
+    #[tokio::test]
+    async fn test_user_context() {
+        let (server, _, token) = setup(GraphqlConfig::default()).await;
+
+        let response = query(&server, "{ me { username } }").await;
+        assert_eq!(response["errors"][0]["extensions"]["code"], 401);
+
+        let response: Value = server
+            .post("/graphql")
+            .authorization_bearer(&token)
+            .json(&json!({ "query": "{ me { username } }" }))
+            .await
+            .json();
+        assert_eq!(response["data"], json!({"me": {"username": "hadi"}}));
+
+        // Other users cannot be looked up.
+        let response = query(&server, "{ user(username: \"hadi\") { username } }").await;
+        assert!(response["data"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let (server, _, _) = setup(GraphqlConfig {
+            max_depth: 4,
+            max_complexity: 50,
+            graphiql: false,
+        })
+        .await;
+
+        let response = query(
+            &server,
+            "{ categories { products(limit: 1) { category { products(limit: 1) { name } } } } }",
+        )
+        .await;
+        assert_eq!(
+            response["errors"][0]["message"],
+            "Query is nested too deep."
+        );
+
+        let response = query(&server, "{ products(limit: 100) { items { name } } }").await;
+        assert_eq!(response["errors"][0]["message"], "Query is too complex.");
+        let response = query(&server, "{ products(limit: 10) { items { name } } }").await;
+        assert!(response["errors"].is_null());
+
+        // The products of a category are costed by their limit too, and huge
+        // limits cannot overflow the cost.
+        let response = query(&server, "{ categories { products(limit: 60) { name } } }").await;
+        assert_eq!(response["errors"][0]["message"], "Query is too complex.");
+        let response = query(
+            &server,
+            &format!("{{ products(limit: {}) {{ items {{ name }} }} }}", usize::MAX),
+        )
+        .await;
+        assert_eq!(response["errors"][0]["message"], "Query is too complex.");
+    }
+
+    #[tokio::test]
+    async fn test_get_and_graphiql() {
+        let (server, _, _) = setup(GraphqlConfig {
+            graphiql: true,
+            ..GraphqlConfig::default()
+        })
+        .await;
+
+        let response = server
+            .get("/graphql")
+            .add_query_param("query", "{ categories { name } }")
+            .await;
+        response.assert_json(&json!({
+            "data": {"categories": [{"name": "Books"}, {"name": "Games"}]}
+        }));
+
+        let response = server
+            .get("/graphql")
+            .add_header("Accept", "text/html")
+            .await;
+        response.assert_status_ok();
+        response.assert_text_contains("/graphql/assets/explorer.js");
+        // Everything comes from this origin, under the default policy.
+        let csp = response.header("Content-Security-Policy");
+        let csp = csp.to_str().unwrap();
+        assert!(!csp.contains("https:"));
+        assert!(!csp.contains("unsafe-inline"));
+        let response = server.get("/graphql/assets/explorer.js").await;
+        response.assert_status_ok();
+        response.assert_header("Content-Type", "text/javascript");
+
+        let (server, _, _) = setup(GraphqlConfig {
+            graphiql: false,
+            ..GraphqlConfig::default()
+        })
+        .await;
+        // Without the editor it is just a query without a document.
+        let response: Value = server
+            .get("/graphql")
+            .add_header("Accept", "text/html")
+            .await
+            .json();
+        assert!(response["errors"].is_array());
+        server
+            .get("/graphql/assets/explorer.js")
+            .await
+            .assert_status_not_found();
+    }
+}
@@ -0,0 +1,398 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    Router,
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{HeaderMap, StatusCode, header};
+use tonic::{Status, body::Body, service::Routes};
+use tower::ServiceExt;
+
+use crate::{
+    auth::AuthState,
+    client_ip::ClientIp,
+    error::AppError,
+    rate_limit::{RateLimitStore, RateLimiter, rate_limit},
+};
+use pb::auth_server::{Auth, AuthServer};
+
+#[derive(Debug, Clone)]
+pub struct GrpcConfig {
+    // Largest request message accepted, in bytes.
+    pub max_message_size: usize,
+    // Serve the reflection service, for tools like grpcurl.
+    pub reflection: bool,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            max_message_size: 4 * 1024 * 1024,
+            reflection: true,
+        }
+    }
+}
+
+// Messages and the `Auth` service trait, generated from
+// `proto/axum_rs/auth/v1/auth.proto` by `build.rs`.
+pub mod pb {
+    tonic::include_proto!("axum_rs.auth.v1");
+
+    // The compiled proto, served by reflection.
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("auth_descriptor");
+}
+
+// Same mapping a REST client sees as status codes.
+fn status(error: AppError) -> Status {
+    let mut status = match error.status {
+        StatusCode::UNAUTHORIZED => Status::unauthenticated(error.message),
+        StatusCode::TOO_MANY_REQUESTS => Status::resource_exhausted(error.message),
+        _ => Status::internal(error.message),
+    };
+    if let Some(retry_after) = error
+        .headers
+        .get(header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+    {
+        status.metadata_mut().insert("retry-after", retry_after);
+    }
+    status
+}
+
+// `axum_rs.auth.v1.Auth`, over the same users, tokens and login guard as
+// `POST /login`.
+pub struct AuthService {
+    state: AuthState,
+}
+
+#[tonic::async_trait]
+impl Auth for AuthService {
+    async fn login(
+        &self,
+        request: tonic::Request<pb::LoginRequest>,
+    ) -> Result<tonic::Response<pb::AuthResponse>, Status> {
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let ClientIp(ip) = ClientIp::new(peer, request.metadata().as_ref());
+        let state = &self.state;
+        let login = request.into_inner();
+        if let Err(wait) = state.guard.check(&login.username, ip) {
+            return Err(status(AppError::too_many_requests(
+                wait.as_secs_f64().ceil() as u64,
+            )));
+        }
+        if !state.users.verify(&login.username, &login.password) {
+            state.guard.record_failure(&login.username, ip);
+            return Err(status(AppError::unauthorized(
+                "Invalid username or password",
+            )));
+        }
+        state.guard.record_success(&login.username, ip);
+        Ok(tonic::Response::new(pb::AuthResponse {
+            token: state.tokens.issue(&login.username),
+        }))
+    }
+
+    // Trades a token that is still valid for a fresh one.
+    async fn refresh(
+        &self,
+        request: tonic::Request<pb::RefreshRequest>,
+    ) -> Result<tonic::Response<pb::AuthResponse>, Status> {
+        let state = &self.state;
+        let claims = state
+            .tokens
+            .verify(&request.into_inner().token)
+            .filter(|claims| state.users.contains(&claims.sub))
+            .ok_or_else(|| Status::unauthenticated("Invalid token"))?;
+        Ok(tonic::Response::new(pb::AuthResponse {
+            token: state.tokens.issue(&claims.sub),
+        }))
+    }
+
+    async fn verify(
+        &self,
+        request: tonic::Request<pb::VerifyRequest>,
+    ) -> Result<tonic::Response<pb::VerifyResponse>, Status> {
+        let claims = self
+            .state
+            .tokens
+            .verify(&request.into_inner().token)
+            .ok_or_else(|| Status::unauthenticated("Invalid token"))?;
+        Ok(tonic::Response::new(pb::VerifyResponse {
+            username: claims.sub,
+            expires_at: claims.exp,
+        }))
+    }
+}
+
+// The gRPC services as a router of their own, for `multiplex`.
+pub async fn router(config: &GrpcConfig, auth: AuthState) -> Router {
+    let (reporter, health) = tonic_health::server::health_reporter();
+    reporter.set_serving::<AuthServer<AuthService>>().await;
+
+    let auth = AuthServer::new(AuthService { state: auth })
+        .max_decoding_message_size(config.max_message_size);
+    let mut routes = Routes::new(auth).add_service(health);
+    if config.reflection {
+        let reflection = || {
+            tonic_reflection::server::Builder::configure()
+                .register_encoded_file_descriptor_set(pb::FILE_DESCRIPTOR_SET)
+                .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        };
+        // grpcurl still asks v1alpha first.
+        routes = routes
+            .add_service(reflection().build_v1().unwrap())
+            .add_service(reflection().build_v1alpha().unwrap());
+    }
+    routes.into_axum_router()
+}
+
+// The REST rate limiter in front of the gRPC services; calls are matched
+// against the route quotas by their `/package.Service/Method` path. A denied
+// call fails with RESOURCE_EXHAUSTED instead of an HTTP 429.
+pub async fn grpc_rate_limit<S: RateLimitStore>(
+    State(limiter): State<Arc<RateLimiter<S>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response = rate_limit(State(limiter), request, next).await;
+    if response.status() != StatusCode::TOO_MANY_REQUESTS {
+        return response;
+    }
+    let retry_after = response
+        .headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_default();
+    let mut denied = status(AppError::too_many_requests(retry_after))
+        .into_http::<Body>()
+        .into_response();
+    for (name, value) in response.headers() {
+        if name.as_str().starts_with("ratelimit-") {
+            denied.headers_mut().insert(name, value.clone());
+        }
+    }
+    denied
+}
+
+fn is_grpc(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value == "application/grpc" || value.starts_with("application/grpc+"))
+}
+
+// Routes gRPC calls to `grpc` by content type, so both share one listener.
+// They skip the REST middleware below this layer.
+pub async fn multiplex(State(grpc): State<Router>, request: Request, next: Next) -> Response {
+    if !is_grpc(request.headers()) {
+        return next.run(request).await;
+    }
+    let Ok(response) = grpc.oneshot(request).await;
+    response.into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audit::MemoryAuditLog,
+        auth::{TokenService, UserStore},
+        login_guard::{LoginGuard, LoginGuardConfig},
+        rate_limit::{MemoryStore, Quota, RateLimitConfig, RouteQuota},
+    };
+    use axum::{middleware::from_fn_with_state, routing::get};
+    use axum_test::TestServer;
+    use futures_util::StreamExt;
+    use prost::Message;
+    use prost_types::{FileDescriptorProto, FileDescriptorSet};
+    use std::time::Duration;
+    use tonic::{Code, transport::Channel};
+    use tonic_prost::ProstCodec;
+    use tonic_health::pb::{
+        HealthCheckRequest, health_check_response::ServingStatus, health_client::HealthClient,
+    };
+    use tonic_reflection::pb::v1::{
+        ServerReflectionRequest, server_reflection_client::ServerReflectionClient,
+        server_reflection_request::MessageRequest, server_reflection_response::MessageResponse,
+    };
+
+    // A REST route and the gRPC services on one real listener.
+    async fn setup(rate_limit: RateLimitConfig) -> (TestServer, Channel) {
+        let auth = AuthState {
+            users: Arc::new(UserStore::default().with_user("hadi", "password")),
+            tokens: Arc::new(TokenService::new("secret", Duration::from_secs(60))),
+            guard: Arc::new(LoginGuard::new(
+                LoginGuardConfig::default(),
+                Arc::new(MemoryAuditLog::default()),
+            )),
+        };
+        let limiter = Arc::new(RateLimiter::new(rate_limit, MemoryStore::default()));
+        let grpc = router(&GrpcConfig::default(), auth)
+            .await
+            .layer(from_fn_with_state(limiter, grpc_rate_limit::<MemoryStore>));
+        let app = Router::new()
+            .route("/", get(|| async { "rest" }))
+            .layer(from_fn_with_state(grpc, multiplex));
+        let server = TestServer::builder().http_transport().build(app).unwrap();
+        let url = server.server_address().unwrap().to_string();
+        let channel = Channel::from_shared(url).unwrap().connect().await.unwrap();
+        (server, channel)
+    }
+
+    async fn call<Req, Res>(channel: &Channel, method: &str, message: Req) -> Result<Res, Status>
+    where
+        Req: Message + Send + Sync + 'static,
+        Res: Message + Default + Send + Sync + 'static,
+    {
+        let mut grpc = tonic::client::Grpc::new(channel.clone());
+        grpc.ready().await.unwrap();
+        let path = format!("/{}/{}", pb::auth_server::SERVICE_NAME, method)
+            .parse()
+            .unwrap();
+        let response = grpc
+            .unary(tonic::Request::new(message), path, ProstCodec::default())
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    #[tokio::test]
+    async fn test_auth_service() {
+        let (server, channel) = setup(RateLimitConfig::default()).await;
+        server.get("/").await.assert_text("rest");
+
+        let login = |password: &str| pb::LoginRequest {
+            username: "hadi".to_string(),
+            password: password.to_string(),
+        };
+        let error = call::<_, pb::AuthResponse>(&channel, "Login", login("wrong"))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::Unauthenticated);
+        assert_eq!(error.message(), "Invalid username or password");
+
+        let pb::AuthResponse { token } = call(&channel, "Login", login("password")).await.unwrap();
+        let verified: pb::VerifyResponse = call(
+            &channel,
+            "Verify",
+            pb::VerifyRequest {
+                token: token.clone(),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(verified.username, "hadi");
+
+        let refreshed: pb::AuthResponse = call(&channel, "Refresh", pb::RefreshRequest { token })
+            .await
+            .unwrap();
+        assert!(!refreshed.token.is_empty());
+        let error = call::<_, pb::VerifyResponse>(
+            &channel,
+            "Verify",
+            pb::VerifyRequest {
+                token: "forged".to_string(),
+            },
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.code(), Code::Unauthenticated);
+
+        let error = call::<_, pb::AuthResponse>(&channel, "Logout", pb::RefreshRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::Unimplemented);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let (_server, channel) = setup(RateLimitConfig {
+            routes: vec![RouteQuota::new(
+                "/axum_rs.auth.v1.Auth/Login",
+                Quota::per_minute(1),
+            )],
+            ..RateLimitConfig::default()
+        })
+        .await;
+
+        let login = pb::LoginRequest {
+            username: "hadi".to_string(),
+            password: "wrong".to_string(),
+        };
+        let error = call::<_, pb::AuthResponse>(&channel, "Login", login.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::Unauthenticated);
+        let error = call::<_, pb::AuthResponse>(&channel, "Login", login)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::ResourceExhausted);
+        assert!(error.metadata().get("retry-after").is_some());
+        assert_eq!(error.metadata().get("ratelimit-remaining").unwrap(), "0");
+
+        // Other methods fall under the default quota.
+        let error = call::<_, pb::VerifyResponse>(&channel, "Verify", pb::VerifyRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_health_and_reflection() {
+        let (_server, channel) = setup(RateLimitConfig::default()).await;
+
+        let health = HealthClient::new(channel.clone())
+            .check(HealthCheckRequest {
+                service: pb::auth_server::SERVICE_NAME.to_string(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(health.status(), ServingStatus::Serving);
+
+        let request = |message| ServerReflectionRequest {
+            host: String::new(),
+            message_request: Some(message),
+        };
+        let requests = futures_util::stream::iter([
+            request(MessageRequest::ListServices(String::new())),
+            request(MessageRequest::FileContainingSymbol(
+                "axum_rs.auth.v1.Auth".to_string(),
+            )),
+        ]);
+        let mut responses = ServerReflectionClient::new(channel)
+            .server_reflection_info(requests)
+            .await
+            .unwrap()
+            .into_inner();
+
+        let Some(MessageResponse::ListServicesResponse(list)) =
+            responses.next().await.unwrap().unwrap().message_response
+        else {
+            panic!("expected a service list");
+        };
+        let names: Vec<_> = list
+            .service
+            .into_iter()
+            .map(|service| service.name)
+            .collect();
+        assert!(names.contains(&pb::auth_server::SERVICE_NAME.to_string()));
+        assert!(names.contains(&"grpc.health.v1.Health".to_string()));
+
+        let Some(MessageResponse::FileDescriptorResponse(files)) =
+            responses.next().await.unwrap().unwrap().message_response
+        else {
+            panic!("expected a file descriptor");
+        };
+        let file = FileDescriptorProto::decode(files.file_descriptor_proto[0].as_slice()).unwrap();
+        assert_eq!(file.name(), "axum_rs/auth/v1/auth.proto");
+        let compiled = FileDescriptorSet::decode(pb::FILE_DESCRIPTOR_SET).unwrap();
+        assert_eq!(file, compiled.file[0]);
+    }
+}
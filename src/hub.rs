@@ -0,0 +1,292 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone)]
+pub struct HubConfig {
+    // Events buffered per connection before it counts as a slow consumer.
+    pub send_buffer: usize,
+    pub max_subscriptions: usize,
+    // Recent events kept for clients resuming after a disconnect.
+    pub replay_buffer: usize,
+}
+
+impl Default for HubConfig {
+    fn default() -> Self {
+        Self {
+            send_buffer: 64,
+            max_subscriptions: 32,
+            replay_buffer: 256,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    // Increases with every published event.
+    pub id: u64,
+    pub channel: String,
+    pub data: Value,
+}
+
+struct Connection {
+    username: String,
+    sender: mpsc::Sender<Arc<Event>>,
+    channels: HashSet<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SubscribeError {
+    Forbidden,
+    TooManySubscriptions,
+}
+
+// In-process fan-out of server-side events to connected clients. Every
+// connection has a bounded buffer; a connection whose buffer is full when an
+// event arrives is dropped rather than slowing down the publisher.
+pub struct Hub {
+    config: HubConfig,
+    state: Mutex<State>,
+    next_id: AtomicU64,
+}
+
+#[derive(Default)]
+struct State {
+    connections: HashMap<u64, Connection>,
+    history: VecDeque<Arc<Event>>,
+    last_event_id: u64,
+}
+
+impl Hub {
+    pub fn new(config: HubConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State::default()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    // `user:<name>` channels are private to that user.
+    fn may_subscribe(username: &str, channel: &str) -> bool {
+        match channel.strip_prefix("user:") {
+            Some(owner) => owner == username,
+            None => true,
+        }
+    }
+
+    // Registers a connection. The receiver ends when the hub drops the
+    // connection for falling behind.
+    pub fn connect(self: &Arc<Self>, username: &str) -> (Subscriber, mpsc::Receiver<Arc<Event>>) {
+        let (sender, receiver) = mpsc::channel(self.config.send_buffer.max(1));
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.state.lock().unwrap().connections.insert(
+            id,
+            Connection {
+                username: username.to_string(),
+                sender,
+                channels: HashSet::new(),
+            },
+        );
+        let subscriber = Subscriber {
+            hub: self.clone(),
+            id,
+        };
+        (subscriber, receiver)
+    }
+
+    // Returns the number of connections the event was queued for.
+    pub fn publish(&self, channel: &str, data: Value) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.last_event_id += 1;
+        let event = Arc::new(Event {
+            id: state.last_event_id,
+            channel: channel.to_string(),
+            data,
+        });
+        if self.config.replay_buffer > 0 {
+            if state.history.len() == self.config.replay_buffer {
+                state.history.pop_front();
+            }
+            state.history.push_back(event.clone());
+        }
+
+        let mut delivered = 0;
+        state.connections.retain(|_, connection| {
+            if !connection.channels.contains(channel) {
+                return true;
+            }
+            match connection.sender.try_send(event.clone()) {
+                Ok(()) => {
+                    delivered += 1;
+                    true
+                }
+                // Full or already gone.
+                Err(_) => false,
+            }
+        });
+        delivered
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.state.lock().unwrap().connections.len()
+    }
+}
+
+// A registered connection, removed from the hub when dropped.
+pub struct Subscriber {
+    hub: Arc<Hub>,
+    id: u64,
+}
+
+impl Subscriber {
+    pub fn subscribe(&self, channel: &str) -> Result<(), SubscribeError> {
+        self.resume(&[channel], None).map(|_| ())
+    }
+
+    // Subscribes to all of `channels`, or none of them on error. With the ID
+    // of the last event a client saw, also returns the buffered events it
+    // missed on those channels; anything newer arrives on the receiver, so
+    // nothing is lost or repeated in between. Events older than the buffer
+    // are gone.
+    pub fn resume(
+        &self,
+        channels: &[&str],
+        last_event_id: Option<u64>,
+    ) -> Result<Vec<Arc<Event>>, SubscribeError> {
+        let mut state = self.hub.state.lock().unwrap();
+        let State {
+            connections,
+            history,
+            ..
+        } = &mut *state;
+        let Some(connection) = connections.get_mut(&self.id) else {
+            return Ok(Vec::new());
+        };
+        if !channels
+            .iter()
+            .all(|channel| Hub::may_subscribe(&connection.username, channel))
+        {
+            return Err(SubscribeError::Forbidden);
+        }
+        let added = channels
+            .iter()
+            .filter(|channel| !connection.channels.contains(**channel))
+            .collect::<HashSet<_>>()
+            .len();
+        if connection.channels.len() + added > self.hub.config.max_subscriptions {
+            return Err(SubscribeError::TooManySubscriptions);
+        }
+        connection
+            .channels
+            .extend(channels.iter().map(|channel| channel.to_string()));
+
+        let Some(last_event_id) = last_event_id else {
+            return Ok(Vec::new());
+        };
+        Ok(history
+            .iter()
+            .filter(|event| event.id > last_event_id && channels.contains(&event.channel.as_str()))
+            .cloned()
+            .collect())
+    }
+
+    pub fn unsubscribe(&self, channel: &str) {
+        if let Some(connection) = self.hub.state.lock().unwrap().connections.get_mut(&self.id) {
+            connection.channels.remove(channel);
+        }
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.hub.state.lock().unwrap().connections.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_publish_to_subscribers() {
+        let hub = Arc::new(Hub::new(HubConfig::default()));
+        let (first, mut first_events) = hub.connect("hadi");
+        let (second, mut second_events) = hub.connect("other");
+        first.subscribe("products").unwrap();
+        first.subscribe("user:hadi").unwrap();
+        assert_eq!(
+            second.subscribe("user:hadi"),
+            Err(SubscribeError::Forbidden)
+        );
+
+        assert_eq!(hub.publish("products", json!({"id": 1})), 1);
+        assert_eq!(hub.publish("user:hadi", json!("private")), 1);
+        assert_eq!(first_events.try_recv().unwrap().data, json!({"id": 1}));
+        assert_eq!(first_events.try_recv().unwrap().data, json!("private"));
+        assert!(second_events.try_recv().is_err());
+
+        first.unsubscribe("products");
+        assert_eq!(hub.publish("products", json!({"id": 2})), 0);
+
+        drop(second);
+        assert_eq!(hub.connection_count(), 1);
+    }
+
+    #[test]
+    fn test_slow_consumer_is_dropped() {
+        let hub = Arc::new(Hub::new(HubConfig {
+            send_buffer: 2,
+            max_subscriptions: 1,
+            ..HubConfig::default()
+        }));
+        let (subscriber, mut events) = hub.connect("hadi");
+        subscriber.subscribe("products").unwrap();
+        assert_eq!(
+            subscriber.subscribe("categories"),
+            Err(SubscribeError::TooManySubscriptions)
+        );
+
+        for id in 0..3 {
+            hub.publish("products", json!(id));
+        }
+        assert_eq!(hub.connection_count(), 0);
+        // What was buffered is still delivered, then the stream ends.
+        assert_eq!(events.try_recv().unwrap().data, json!(0));
+        assert_eq!(events.try_recv().unwrap().data, json!(1));
+        assert!(matches!(
+            events.try_recv(),
+            Err(mpsc::error::TryRecvError::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn test_resume_replays_missed_events() {
+        let hub = Arc::new(Hub::new(HubConfig {
+            replay_buffer: 3,
+            ..HubConfig::default()
+        }));
+        for id in 1..=5 {
+            hub.publish(if id % 2 == 0 { "even" } else { "odd" }, json!(id));
+        }
+
+        let (subscriber, _events) = hub.connect("hadi");
+        // 1 and 2 were pushed out of the buffer.
+        let missed = subscriber.resume(&["odd", "user:hadi"], Some(0)).unwrap();
+        let ids: Vec<_> = missed.iter().map(|event| event.id).collect();
+        assert_eq!(ids, [3, 5]);
+        assert!(subscriber.resume(&["even"], None).unwrap().is_empty());
+        assert_eq!(
+            subscriber.resume(&["user:other"], Some(0)).unwrap_err(),
+            SubscribeError::Forbidden
+        );
+    }
+}
@@ -0,0 +1,580 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes, to_bytes},
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::CookieJar;
+use futures_util::{StreamExt, stream};
+use http::{HeaderMap, HeaderValue, Method, StatusCode, request::Parts};
+use http_body_util::BodyExt;
+use sha2::{Digest, Sha256};
+use tokio::{sync::watch, time::Instant};
+
+use crate::{
+    auth::{AuthUser, TOKEN_COOKIE, TokenService},
+    client_ip::ClientIp,
+    error::AppError,
+};
+
+pub const IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+pub const REPLAYED: &str = "Idempotent-Replayed";
+
+#[derive(Debug, Clone)]
+pub struct IdempotencyConfig {
+    // How long a completed response is replayed for.
+    pub ttl: Duration,
+    // Keyed requests are buffered to fingerprint them; larger bodies get 413.
+    pub max_body: usize,
+    // Larger responses are passed through without being stored, so a retry
+    // runs the request again.
+    pub max_response: usize,
+    // How long a duplicate waits for the original request before giving up with 409.
+    pub wait: Duration,
+    // Stored responses kept in total, in bytes; the oldest are evicted first.
+    pub max_stored: usize,
+    // Keys tracked at once. Once every one of them is in flight, new keys
+    // get a 503.
+    pub max_entries: usize,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(24 * 60 * 60),
+            max_body: 1024 * 1024,
+            max_response: 1024 * 1024,
+            wait: Duration::from_secs(5),
+            max_stored: 64 * 1024 * 1024,
+            max_entries: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl StoredResponse {
+    fn size(&self) -> usize {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        headers + self.body.len()
+    }
+}
+
+impl IntoResponse for StoredResponse {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, self.headers, self.body).into_response();
+        response
+            .headers_mut()
+            .insert(REPLAYED, HeaderValue::from_static("true"));
+        response
+    }
+}
+
+enum Progress {
+    // The receiver reports closed once the original request finishes or is dropped.
+    InFlight(watch::Receiver<()>),
+    Done(StoredResponse),
+}
+
+struct Entry {
+    fingerprint: [u8; 32],
+    progress: Progress,
+    expires: Instant,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<String, Entry>,
+    // Completed keys, oldest first. Every response is kept for the same ttl,
+    // so this is also the order they expire in.
+    done: VecDeque<String>,
+    stored: usize,
+}
+
+impl Entries {
+    fn evict_oldest(&mut self) -> bool {
+        let Some(key) = self.done.pop_front() else {
+            return false;
+        };
+        if let Some(Entry {
+            progress: Progress::Done(stored),
+            ..
+        }) = self.map.get(&key)
+        {
+            self.stored -= stored.size();
+            self.map.remove(&key);
+        }
+        true
+    }
+
+    // Only looks at the front of `done`, so it is cheap enough to run on
+    // every call.
+    fn expire(&mut self, now: Instant) {
+        while let Some(key) = self.done.front() {
+            if self.map.get(key).is_some_and(|entry| entry.expires > now) {
+                break;
+            }
+            self.evict_oldest();
+        }
+    }
+}
+
+enum Begin {
+    Proceed(Claim),
+    Replay(StoredResponse),
+    Mismatch,
+    Wait(watch::Receiver<()>),
+    Full,
+}
+
+pub struct Idempotency {
+    config: IdempotencyConfig,
+    entries: Mutex<Entries>,
+    tokens: Option<Arc<TokenService>>,
+}
+
+impl Idempotency {
+    pub fn new(config: IdempotencyConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(Entries::default()),
+            tokens: None,
+        }
+    }
+
+    // Needed to scope keys by user; without it keys are scoped by client IP.
+    pub fn with_tokens(mut self, tokens: Arc<TokenService>) -> Self {
+        self.tokens = Some(tokens);
+        self
+    }
+
+    fn caller(&self, parts: &Parts) -> String {
+        let jar = CookieJar::from_headers(&parts.headers);
+        AuthUser::bearer_token(parts)
+            .or_else(|| jar.get(TOKEN_COOKIE).map(|cookie| cookie.value()))
+            .zip(self.tokens.as_ref())
+            .and_then(|(token, tokens)| tokens.verify(token))
+            .map(|claims| format!("user:{}", claims.sub))
+            .unwrap_or_else(|| format!("ip:{}", ClientIp::from_parts(parts).0))
+    }
+
+    fn begin(self: &Arc<Self>, key: &str, fingerprint: [u8; 32]) -> Begin {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.expire(now);
+
+        match entries.map.get(key) {
+            Some(entry) if entry.fingerprint != fingerprint => Begin::Mismatch,
+            Some(Entry {
+                progress: Progress::Done(stored),
+                ..
+            }) => Begin::Replay(stored.clone()),
+            Some(Entry {
+                progress: Progress::InFlight(receiver),
+                ..
+            }) => Begin::Wait(receiver.clone()),
+            None => {
+                while entries.map.len() >= self.config.max_entries {
+                    if !entries.evict_oldest() {
+                        return Begin::Full;
+                    }
+                }
+                let (sender, receiver) = watch::channel(());
+                entries.map.insert(
+                    key.to_string(),
+                    Entry {
+                        fingerprint,
+                        progress: Progress::InFlight(receiver),
+                        expires: now + self.config.ttl,
+                    },
+                );
+                Begin::Proceed(Claim {
+                    idempotency: self.clone(),
+                    key: key.to_string(),
+                    _sender: sender,
+                    completed: false,
+                })
+            }
+        }
+    }
+}
+
+// Ownership of an in-flight key. Dropping it without completing (the handler
+// failed or the client went away) releases the key for a retry.
+struct Claim {
+    idempotency: Arc<Idempotency>,
+    key: String,
+    _sender: watch::Sender<()>,
+    completed: bool,
+}
+
+impl Claim {
+    // Responses that don't fit in `max_stored` at all are not kept; dropping
+    // the claim releases the key instead.
+    fn complete(mut self, stored: StoredResponse) {
+        let size = stored.size();
+        let max_stored = self.idempotency.config.max_stored;
+        if size > max_stored {
+            return;
+        }
+        let mut entries = self.idempotency.entries.lock().unwrap();
+        while entries.stored + size > max_stored && entries.evict_oldest() {}
+        if let Some(entry) = entries.map.get_mut(&self.key) {
+            entry.progress = Progress::Done(stored);
+            entry.expires = Instant::now() + self.idempotency.config.ttl;
+            entries.stored += size;
+            entries.done.push_back(self.key.clone());
+        }
+        self.completed = true;
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.idempotency
+                .entries
+                .lock()
+                .unwrap()
+                .map
+                .remove(&self.key);
+        }
+    }
+}
+
+fn fingerprint(parts: &Parts, body: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(parts.method.as_str());
+    hasher.update(b"\n");
+    hasher.update(parts.uri.path());
+    hasher.update(b"\n");
+    hasher.update(parts.uri.query().unwrap_or_default());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hasher.finalize().into()
+}
+
+// Replays the first response of a POST carrying an `Idempotency-Key`. Keys
+// are scoped to the caller, and reusing one for a different request is a 422.
+// Server errors are not stored so the client can retry them.
+pub async fn idempotency(
+    State(idempotency): State<Arc<Idempotency>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+    let Some(key) = request.headers().get(IDEMPOTENCY_KEY) else {
+        return next.run(request).await;
+    };
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= 255 => key.to_string(),
+        _ => {
+            return AppError::new(StatusCode::BAD_REQUEST, "Invalid Idempotency-Key")
+                .into_response();
+        }
+    };
+
+    let (parts, body) = request.into_parts();
+    let Ok(body) = to_bytes(body, idempotency.config.max_body).await else {
+        return AppError::new(StatusCode::PAYLOAD_TOO_LARGE, "Request body too large")
+            .into_response();
+    };
+    let key = format!("{}|{}", idempotency.caller(&parts), key);
+    let fingerprint = fingerprint(&parts, &body);
+
+    let deadline = Instant::now() + idempotency.config.wait;
+    let claim = loop {
+        match idempotency.begin(&key, fingerprint) {
+            Begin::Proceed(claim) => break claim,
+            Begin::Replay(stored) => return stored.into_response(),
+            Begin::Mismatch => {
+                return AppError::new(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "Idempotency-Key was used with a different request",
+                )
+                .into_response();
+            }
+            Begin::Full => {
+                return AppError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Too many requests with an Idempotency-Key are in progress",
+                )
+                .into_response();
+            }
+            Begin::Wait(mut receiver) => {
+                // The sender never sends, so this only returns once it is dropped.
+                if tokio::time::timeout_at(deadline, receiver.changed())
+                    .await
+                    .is_err()
+                {
+                    return AppError::new(
+                        StatusCode::CONFLICT,
+                        "A request with this Idempotency-Key is still in progress",
+                    )
+                    .into_response();
+                }
+            }
+        }
+    };
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    if response.status().is_server_error() {
+        return response;
+    }
+    let (response_parts, mut body) = response.into_parts();
+    let mut buffered = Vec::new();
+    loop {
+        match body.frame().await {
+            Some(Ok(frame)) => {
+                if let Ok(data) = frame.into_data() {
+                    buffered.extend_from_slice(&data);
+                }
+            }
+            Some(Err(_)) => {
+                return AppError::internal("Failed to read response body").into_response();
+            }
+            None => break,
+        }
+        if buffered.len() > idempotency.config.max_response {
+            drop(claim);
+            let prefix = Bytes::from(buffered);
+            let body = Body::from_stream(
+                stream::once(async move { Ok::<_, axum::Error>(prefix) })
+                    .chain(body.into_data_stream()),
+            );
+            return Response::from_parts(response_parts, body);
+        }
+    }
+    let body = Bytes::from(buffered);
+    claim.complete(StoredResponse {
+        status: response_parts.status,
+        headers: response_parts.headers.clone(),
+        body: body.clone(),
+    });
+    Response::from_parts(response_parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use axum::{Json, Router, middleware::from_fn_with_state, routing::post};
+    use axum_test::TestServer;
+    use serde_json::{Value, json};
+
+    fn create_app(config: IdempotencyConfig) -> (Router, Arc<AtomicU32>) {
+        let created = Arc::new(AtomicU32::new(0));
+        let counter = created.clone();
+        let route = move |Json(body): Json<Value>| {
+            let counter = counter.clone();
+            async move {
+                if body["slow"] == true {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                }
+                if body["fail"] == true {
+                    return Err(AppError::internal("boom"));
+                }
+                let id = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                Ok((StatusCode::CREATED, Json(json!({"id": id}))))
+            }
+        };
+        let app = Router::new()
+            .route("/create", post(route))
+            .layer(from_fn_with_state(
+                Arc::new(Idempotency::new(config)),
+                idempotency,
+            ));
+        (app, created)
+    }
+
+    #[tokio::test]
+    async fn test_replay_and_mismatch() {
+        let (app, created) = create_app(IdempotencyConfig::default());
+        let server = TestServer::new(app).unwrap();
+
+        let first = server
+            .post("/create")
+            .add_header(IDEMPOTENCY_KEY, "abc")
+            .json(&json!({"name": "a"}))
+            .await;
+        first.assert_status(StatusCode::CREATED);
+        first.assert_json(&json!({"id": 1}));
+        assert!(first.maybe_header(REPLAYED).is_none());
+
+        let retry = server
+            .post("/create")
+            .add_header(IDEMPOTENCY_KEY, "abc")
+            .json(&json!({"name": "a"}))
+            .await;
+        retry.assert_status(StatusCode::CREATED);
+        retry.assert_json(&json!({"id": 1}));
+        retry.assert_header(REPLAYED, "true");
+        retry.assert_header("Content-Type", "application/json");
+
+        let response = server
+            .post("/create")
+            .add_header(IDEMPOTENCY_KEY, "abc")
+            .json(&json!({"name": "b"}))
+            .await;
+        response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+
+        // Keys are scoped to the caller.
+        server
+            .post("/create")
+            .add_header(IDEMPOTENCY_KEY, "abc")
+            .add_header("X-Forwarded-For", "10.0.0.9")
+            .json(&json!({"name": "a"}))
+            .await
+            .assert_json(&json!({"id": 2}));
+
+        // Requests without a key are never deduplicated.
+        server.post("/create").json(&json!({"name": "a"})).await;
+        assert_eq!(created.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_not_stored() {
+        let (app, created) = create_app(IdempotencyConfig::default());
+        let server = TestServer::new(app).unwrap();
+
+        let request = || {
+            server
+                .post("/create")
+                .add_header(IDEMPOTENCY_KEY, "retry-me")
+                .json(&json!({"fail": true}))
+        };
+        request()
+            .await
+            .assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+        let response = request().await;
+        response.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(response.maybe_header(REPLAYED).is_none());
+        assert_eq!(created.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_large_responses_are_not_stored() {
+        let (app, created) = create_app(IdempotencyConfig {
+            max_response: 4,
+            ..IdempotencyConfig::default()
+        });
+        let server = TestServer::new(app).unwrap();
+
+        let request = || {
+            server
+                .post("/create")
+                .add_header(IDEMPOTENCY_KEY, "large")
+                .json(&json!({"name": "a"}))
+        };
+        request().await.assert_json(&json!({"id": 1}));
+        let response = request().await;
+        response.assert_json(&json!({"id": 2}));
+        assert!(response.maybe_header(REPLAYED).is_none());
+        assert_eq!(created.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_store_limits() {
+        let request = |server: &TestServer, key: &'static str| {
+            server
+                .post("/create")
+                .add_header(IDEMPOTENCY_KEY, key)
+                .json(&json!({"name": "a"}))
+        };
+
+        // Room for one key: a second one evicts the first.
+        let (app, created) = create_app(IdempotencyConfig {
+            max_entries: 1,
+            ..IdempotencyConfig::default()
+        });
+        let server = TestServer::new(app).unwrap();
+        request(&server, "a").await.assert_json(&json!({"id": 1}));
+        request(&server, "a").await.assert_header(REPLAYED, "true");
+        request(&server, "b").await.assert_json(&json!({"id": 2}));
+        request(&server, "a").await.assert_json(&json!({"id": 3}));
+        assert_eq!(created.load(Ordering::SeqCst), 3);
+
+        // Room for one response's bytes.
+        let (app, _) = create_app(IdempotencyConfig {
+            max_stored: 50,
+            ..IdempotencyConfig::default()
+        });
+        let server = TestServer::new(app).unwrap();
+        request(&server, "a").await.assert_json(&json!({"id": 1}));
+        request(&server, "b").await.assert_json(&json!({"id": 2}));
+        request(&server, "b").await.assert_header(REPLAYED, "true");
+        request(&server, "a").await.assert_json(&json!({"id": 3}));
+
+        // Every tracked key in flight: new ones are turned away.
+        let (app, _) = create_app(IdempotencyConfig {
+            max_entries: 1,
+            ..IdempotencyConfig::default()
+        });
+        let server = TestServer::new(app).unwrap();
+        let slow = server
+            .post("/create")
+            .add_header(IDEMPOTENCY_KEY, "slow")
+            .json(&json!({"slow": true}));
+        let (first, second) = tokio::join!(slow, async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            request(&server, "other").await
+        });
+        first.assert_status(StatusCode::CREATED);
+        second.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_duplicates() {
+        let (app, created) = create_app(IdempotencyConfig::default());
+        let server = TestServer::new(app).unwrap();
+        let request = || {
+            server
+                .post("/create")
+                .add_header(IDEMPOTENCY_KEY, "slow")
+                .json(&json!({"slow": true}))
+        };
+
+        // The duplicate waits for the original and gets its response.
+        let (first, second) = tokio::join!(request(), request());
+        first.assert_json(&json!({"id": 1}));
+        second.assert_json(&json!({"id": 1}));
+        assert_eq!(created.load(Ordering::SeqCst), 1);
+
+        let (app, created) = create_app(IdempotencyConfig {
+            wait: Duration::from_millis(20),
+            ..IdempotencyConfig::default()
+        });
+        let server = TestServer::new(app).unwrap();
+        let request = || {
+            server
+                .post("/create")
+                .add_header(IDEMPOTENCY_KEY, "slow")
+                .json(&json!({"slow": true}))
+        };
+        let (first, second) = tokio::join!(request(), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            request().await
+        });
+        first.assert_status(StatusCode::CREATED);
+        second.assert_status(StatusCode::CONFLICT);
+        assert_eq!(created.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,798 @@
+use std::{cmp::Ordering, fmt::Write, marker::PhantomData};
+
+use axum::{
+    Json,
+    extract::{FromRequestParts, OriginalUri},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use http::{HeaderValue, StatusCode, Uri, header, request::Parts};
+use serde::{Deserialize, Serialize};
+use sqlx::{QueryBuilder, Sqlite};
+use utoipa::{
+    IntoParams, ToSchema,
+    openapi::{
+        Required, Type,
+        path::{Parameter, ParameterBuilder, ParameterIn},
+        schema::ObjectBuilder,
+    },
+};
+
+use crate::error::AppError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOp {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    // Substring match on text fields, ignoring ASCII case like SQLite's LIKE.
+    Like,
+}
+
+impl FilterOp {
+    pub const COMPARISONS: &'static [FilterOp] = &[
+        FilterOp::Eq,
+        FilterOp::Ne,
+        FilterOp::Gt,
+        FilterOp::Gte,
+        FilterOp::Lt,
+        FilterOp::Lte,
+    ];
+
+    fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "eq" => FilterOp::Eq,
+            "ne" => FilterOp::Ne,
+            "gt" => FilterOp::Gt,
+            "gte" => FilterOp::Gte,
+            "lt" => FilterOp::Lt,
+            "lte" => FilterOp::Lte,
+            "like" => FilterOp::Like,
+            _ => return None,
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            FilterOp::Eq => "eq",
+            FilterOp::Ne => "ne",
+            FilterOp::Gt => "gt",
+            FilterOp::Gte => "gte",
+            FilterOp::Lt => "lt",
+            FilterOp::Lte => "lte",
+            FilterOp::Like => "like",
+        }
+    }
+
+    fn sql(&self) -> &'static str {
+        match self {
+            FilterOp::Eq => " = ",
+            FilterOp::Ne => " <> ",
+            FilterOp::Gt => " > ",
+            FilterOp::Gte => " >= ",
+            FilterOp::Lt => " < ",
+            FilterOp::Lte => " <= ",
+            FilterOp::Like => " LIKE ",
+        }
+    }
+
+    fn matches(&self, value: &Value, operand: &Value) -> bool {
+        match (self, value, operand) {
+            (FilterOp::Like, Value::Text(value), Value::Text(operand)) => value
+                .to_ascii_lowercase()
+                .contains(&operand.to_ascii_lowercase()),
+            (FilterOp::Like, _, _) => false,
+            (FilterOp::Eq, _, _) => value == operand,
+            (FilterOp::Ne, _, _) => value != operand,
+            (FilterOp::Gt, _, _) => value > operand,
+            (FilterOp::Gte, _, _) => value >= operand,
+            (FilterOp::Lt, _, _) => value < operand,
+            (FilterOp::Lte, _, _) => value <= operand,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    Int,
+    Text,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Int(i64),
+    Text(String),
+}
+
+impl FieldType {
+    fn parse(&self, value: &str) -> Option<Value> {
+        match self {
+            FieldType::Int => value.parse().ok().map(Value::Int),
+            FieldType::Text => Some(Value::Text(value.to_string())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FieldSpec {
+    // Used verbatim as the SQL column, so it must never come from input.
+    pub name: &'static str,
+    pub ty: FieldType,
+    pub sortable: bool,
+    pub ops: &'static [FilterOp],
+}
+
+// Describes what a list endpoint accepts. Every spec needs an integer `id`
+// field, which breaks ties so cursors are stable.
+pub trait ListSpec: Send + Sync + 'static {
+    const FIELDS: &'static [FieldSpec];
+    const DEFAULT_LIMIT: usize = 20;
+    const MAX_LIMIT: usize = 100;
+}
+
+// Implemented by items listed in memory, to read the fields named in a spec.
+pub trait Listable {
+    fn field(&self, name: &str) -> Value;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sort {
+    pub field: &'static str,
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub field: &'static str,
+    pub op: FilterOp,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pagination {
+    Offset(u64),
+    // Sort key of the last item already seen; `None` starts from the top.
+    Cursor(Option<Vec<Value>>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CursorToken {
+    sort: String,
+    key: Vec<Value>,
+}
+
+#[derive(Debug)]
+pub struct ListParams<S> {
+    pub sort: Vec<Sort>,
+    pub filters: Vec<Filter>,
+    pub limit: usize,
+    pub pagination: Pagination,
+    uri: Uri,
+    spec: PhantomData<S>,
+}
+
+fn bad_request(message: String) -> AppError {
+    AppError::new(StatusCode::BAD_REQUEST, message)
+}
+
+impl<S: ListSpec> ListParams<S> {
+    fn field(name: &str) -> Option<&'static FieldSpec> {
+        S::FIELDS.iter().find(|field| field.name == name)
+    }
+
+    pub fn parse(uri: Uri) -> Result<Self, String> {
+        let pairs: Vec<(String, String)> =
+            serde_urlencoded::from_str(uri.query().unwrap_or_default())
+                .map_err(|_| "Invalid query string".to_string())?;
+
+        let mut params = Self {
+            sort: Vec::new(),
+            filters: Vec::new(),
+            limit: S::DEFAULT_LIMIT,
+            pagination: Pagination::Offset(0),
+            uri,
+            spec: PhantomData,
+        };
+        let mut cursor = None;
+
+        for (key, value) in pairs {
+            match key.as_str() {
+                "limit" => {
+                    params.limit = value
+                        .parse()
+                        .ok()
+                        .filter(|limit| (1..=S::MAX_LIMIT).contains(limit))
+                        .ok_or_else(|| format!("limit must be between 1 and {}", S::MAX_LIMIT))?;
+                }
+                "offset" => {
+                    let offset = value.parse().map_err(|_| "Invalid offset".to_string())?;
+                    params.pagination = Pagination::Offset(offset);
+                }
+                "cursor" => cursor = Some(value),
+                "sort" => {
+                    for item in value.split(',').filter(|item| !item.is_empty()) {
+                        let (name, descending) = match item.strip_prefix('-') {
+                            Some(name) => (name, true),
+                            None => (item, false),
+                        };
+                        let field = Self::field(name)
+                            .filter(|field| field.sortable)
+                            .ok_or_else(|| format!("Cannot sort by {}", name))?;
+                        params.sort.push(Sort {
+                            field: field.name,
+                            descending,
+                        });
+                    }
+                }
+                _ => {
+                    // `price[gte]=10`, or `price=10` for equality.
+                    let (name, op) = match key.strip_suffix(']').and_then(|key| key.split_once('['))
+                    {
+                        Some((name, op)) => (
+                            name,
+                            FilterOp::parse(op)
+                                .ok_or_else(|| format!("Unknown operator {}", op))?,
+                        ),
+                        None => (key.as_str(), FilterOp::Eq),
+                    };
+                    let field = Self::field(name)
+                        .filter(|field| !field.ops.is_empty())
+                        .ok_or_else(|| format!("Cannot filter by {}", name))?;
+                    if !field.ops.contains(&op) {
+                        return Err(format!("Operator not allowed for {}", name));
+                    }
+                    let value = field
+                        .ty
+                        .parse(&value)
+                        .ok_or_else(|| format!("Invalid value for {}", name))?;
+                    params.filters.push(Filter {
+                        field: field.name,
+                        op,
+                        value,
+                    });
+                }
+            }
+        }
+
+        if !params.sort.iter().any(|sort| sort.field == "id") {
+            params.sort.push(Sort {
+                field: "id",
+                descending: false,
+            });
+        }
+
+        if let Some(cursor) = cursor {
+            let key = match cursor.as_str() {
+                "" => None,
+                cursor => Some(params.decode_cursor(cursor).ok_or("Invalid cursor")?),
+            };
+            params.pagination = Pagination::Cursor(key);
+        }
+        Ok(params)
+    }
+
+    fn sort_signature(&self) -> String {
+        self.sort
+            .iter()
+            .map(|sort| format!("{}{}", if sort.descending { "-" } else { "" }, sort.field))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    // Cursors remember the sort they were issued for, so they can't be
+    // replayed against a different ordering.
+    fn encode_cursor(&self, key: Vec<Value>) -> String {
+        let token = CursorToken {
+            sort: self.sort_signature(),
+            key,
+        };
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(&token).unwrap())
+    }
+
+    fn decode_cursor(&self, cursor: &str) -> Option<Vec<Value>> {
+        let token: CursorToken =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        let types_match = token.key.len() == self.sort.len()
+            && self.sort.iter().zip(&token.key).all(|(sort, value)| {
+                matches!(
+                    (Self::field(sort.field).map(|field| field.ty), value),
+                    (Some(FieldType::Int), Value::Int(_)) | (Some(FieldType::Text), Value::Text(_))
+                )
+            });
+        (token.sort == self.sort_signature() && types_match).then_some(token.key)
+    }
+
+    fn compare_keys(&self, a: &[Value], b: &[Value]) -> Ordering {
+        self.sort
+            .iter()
+            .zip(a.iter().zip(b))
+            .map(|(sort, (a, b))| {
+                let ordering = a.cmp(b);
+                if sort.descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+
+    pub fn sort_key<T: Listable>(&self, item: &T) -> Vec<Value> {
+        self.sort
+            .iter()
+            .map(|sort| item.field(sort.field))
+            .collect()
+    }
+
+    pub fn matches<T: Listable>(&self, item: &T) -> bool {
+        self.filters
+            .iter()
+            .all(|filter| filter.op.matches(&item.field(filter.field), &filter.value))
+    }
+
+    // Filters, sorts and pages items held in memory.
+    pub fn apply<T: Listable>(&self, items: impl IntoIterator<Item = T>) -> Page<T> {
+        let mut items: Vec<_> = items
+            .into_iter()
+            .filter(|item| self.matches(item))
+            .collect();
+        items.sort_by(|a, b| self.compare_keys(&self.sort_key(a), &self.sort_key(b)));
+        let total = items.len() as u64;
+
+        let items: Vec<_> = match &self.pagination {
+            Pagination::Offset(offset) => items
+                .into_iter()
+                .skip(*offset as usize)
+                .take(self.limit + 1)
+                .collect(),
+            Pagination::Cursor(after) => items
+                .into_iter()
+                .filter(|item| {
+                    after
+                        .as_ref()
+                        .is_none_or(|after| self.compare_keys(&self.sort_key(item), after).is_gt())
+                })
+                .take(self.limit + 1)
+                .collect(),
+        };
+        self.page(items, Some(total))
+    }
+
+    // `items` should hold up to `limit + 1` rows; the extra one only signals
+    // that another page exists.
+    pub fn page<T: Listable>(&self, mut items: Vec<T>, total: Option<u64>) -> Page<T> {
+        let has_more = items.len() > self.limit;
+        items.truncate(self.limit);
+
+        let (offset, next_cursor, total) = match &self.pagination {
+            Pagination::Offset(offset) => (Some(*offset), None, total),
+            Pagination::Cursor(_) => {
+                let next = items
+                    .last()
+                    .filter(|_| has_more)
+                    .map(|item| self.encode_cursor(self.sort_key(item)));
+                (None, next, None)
+            }
+        };
+        Page {
+            data: items,
+            page: PageInfo {
+                limit: self.limit,
+                offset,
+                total,
+                has_more,
+                next_cursor,
+            },
+        }
+    }
+
+    // Appends ` WHERE ... ORDER BY ... LIMIT ...` for this listing. Column
+    // names come from the static spec; every value is bound.
+    pub fn push_sql(&self, query: &mut QueryBuilder<'_, Sqlite>) {
+        self.push_where(query, true);
+        query.push(" ORDER BY ");
+        for (index, sort) in self.sort.iter().enumerate() {
+            if index > 0 {
+                query.push(", ");
+            }
+            query.push(sort.field);
+            query.push(if sort.descending { " DESC" } else { " ASC" });
+        }
+        query.push(" LIMIT ");
+        query.push_bind((self.limit + 1) as i64);
+        if let Pagination::Offset(offset) = self.pagination {
+            query.push(" OFFSET ");
+            query.push_bind(offset as i64);
+        }
+    }
+
+    // Appends ` WHERE ...` with the filters only, e.g. for a `COUNT(*)`.
+    pub fn push_filters(&self, query: &mut QueryBuilder<'_, Sqlite>) {
+        self.push_where(query, false);
+    }
+
+    fn push_where(&self, query: &mut QueryBuilder<'_, Sqlite>, with_cursor: bool) {
+        query.push(" WHERE 1 = 1");
+        for filter in &self.filters {
+            query.push(" AND ");
+            query.push(filter.field);
+            query.push(filter.op.sql());
+            match (&filter.op, &filter.value) {
+                (FilterOp::Like, Value::Text(text)) => {
+                    let escaped = text
+                        .replace('\\', "\\\\")
+                        .replace('%', "\\%")
+                        .replace('_', "\\_");
+                    query.push_bind(format!("%{}%", escaped));
+                    query.push(" ESCAPE '\\'");
+                }
+                (_, Value::Int(value)) => {
+                    query.push_bind(*value);
+                }
+                (_, Value::Text(value)) => {
+                    query.push_bind(value.clone());
+                }
+            }
+        }
+
+        // Keyset condition: (a > x) OR (a = x AND b > y) OR ...
+        let Pagination::Cursor(Some(after)) = &self.pagination else {
+            return;
+        };
+        if !with_cursor {
+            return;
+        }
+        query.push(" AND (");
+        for (index, sort) in self.sort.iter().enumerate() {
+            if index > 0 {
+                query.push(" OR ");
+            }
+            query.push("(");
+            for (previous, value) in self.sort[..index].iter().zip(after) {
+                query.push(previous.field);
+                query.push(" = ");
+                push_value(query, value);
+                query.push(" AND ");
+            }
+            query.push(sort.field);
+            query.push(if sort.descending { " < " } else { " > " });
+            push_value(query, &after[index]);
+            query.push(")");
+        }
+        query.push(")");
+    }
+
+    fn link(&self, replace: &str, value: &str) -> String {
+        let mut pairs: Vec<(String, String)> =
+            serde_urlencoded::from_str(self.uri.query().unwrap_or_default()).unwrap_or_default();
+        pairs.retain(|(key, _)| key != "offset" && key != "cursor");
+        pairs.push((replace.to_string(), value.to_string()));
+        format!(
+            "{}?{}",
+            self.uri.path(),
+            serde_urlencoded::to_string(&pairs).unwrap()
+        )
+    }
+
+    fn links<T>(&self, page: &Page<T>) -> String {
+        let mut links = Vec::new();
+        match self.pagination {
+            Pagination::Offset(offset) => {
+                links.push((self.link("offset", "0"), "first"));
+                if offset > 0 {
+                    let previous = offset.saturating_sub(self.limit as u64);
+                    links.push((self.link("offset", &previous.to_string()), "prev"));
+                }
+                if page.page.has_more {
+                    let next = offset + self.limit as u64;
+                    links.push((self.link("offset", &next.to_string()), "next"));
+                }
+            }
+            Pagination::Cursor(_) => {
+                links.push((self.link("cursor", ""), "first"));
+                if let Some(cursor) = &page.page.next_cursor {
+                    links.push((self.link("cursor", cursor), "next"));
+                }
+            }
+        }
+
+        let mut header = String::new();
+        for (url, rel) in links {
+            if !header.is_empty() {
+                header.push_str(", ");
+            }
+            let _ = write!(header, "<{}>; rel=\"{}\"", url, rel);
+        }
+        header
+    }
+
+    // JSON page envelope with a `Link` header for navigation.
+    pub fn respond<T: Serialize>(&self, page: Page<T>) -> Response {
+        let links = self.links(&page);
+        let mut response = Json(page).into_response();
+        if let Ok(value) = HeaderValue::try_from(links) {
+            response.headers_mut().insert(header::LINK, value);
+        }
+        response
+    }
+}
+
+fn push_value(query: &mut QueryBuilder<'_, Sqlite>, value: &Value) {
+    match value {
+        Value::Int(value) => query.push_bind(*value),
+        Value::Text(value) => query.push_bind(value.clone()),
+    };
+}
+
+impl<S: ListSpec, St: Send + Sync> FromRequestParts<St> for ListParams<S> {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &St) -> Result<Self, Self::Rejection> {
+        // Links point at the full path even when the router is nested.
+        let uri = parts
+            .extensions
+            .get::<OriginalUri>()
+            .map(|uri| uri.0.clone())
+            .unwrap_or_else(|| parts.uri.clone());
+        Self::parse(uri).map_err(bad_request)
+    }
+}
+
+// Documents the query string accepted for `S`, one parameter per filter.
+impl<S: ListSpec> IntoParams for ListParams<S> {
+    fn into_params(_: impl Fn() -> Option<ParameterIn>) -> Vec<Parameter> {
+        let param = |name: String, ty: Type, description: String| {
+            ParameterBuilder::new()
+                .name(name)
+                .parameter_in(ParameterIn::Query)
+                .required(Required::False)
+                .description(Some(description))
+                .schema(Some(ObjectBuilder::new().schema_type(ty)))
+                .build()
+        };
+        let sortable: Vec<_> = S::FIELDS
+            .iter()
+            .filter(|field| field.sortable)
+            .map(|field| field.name)
+            .collect();
+
+        let mut params = vec![
+            param(
+                "limit".to_string(),
+                Type::Integer,
+                format!(
+                    "Page size, 1 to {} (default {})",
+                    S::MAX_LIMIT,
+                    S::DEFAULT_LIMIT
+                ),
+            ),
+            param(
+                "offset".to_string(),
+                Type::Integer,
+                "Items to skip".to_string(),
+            ),
+            param(
+                "cursor".to_string(),
+                Type::String,
+                "`next_cursor` of the previous page; empty for the first page".to_string(),
+            ),
+            param(
+                "sort".to_string(),
+                Type::String,
+                format!(
+                    "Comma separated fields, `-` prefix for descending: {}",
+                    sortable.join(", ")
+                ),
+            ),
+        ];
+        for field in S::FIELDS {
+            let ty = match field.ty {
+                FieldType::Int => Type::Integer,
+                FieldType::Text => Type::String,
+            };
+            for op in field.ops {
+                let name = match op {
+                    FilterOp::Eq => field.name.to_string(),
+                    op => format!("{}[{}]", field.name, op.name()),
+                };
+                params.push(param(
+                    name,
+                    ty.clone(),
+                    format!("Filter on {} ({})", field.name, op.name()),
+                ));
+            }
+        }
+        params
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PageInfo {
+    pub limit: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    pub has_more: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub page: PageInfo,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Items;
+
+    impl ListSpec for Items {
+        const FIELDS: &'static [FieldSpec] = &[
+            FieldSpec {
+                name: "id",
+                ty: FieldType::Int,
+                sortable: true,
+                ops: FilterOp::COMPARISONS,
+            },
+            FieldSpec {
+                name: "name",
+                ty: FieldType::Text,
+                sortable: true,
+                ops: &[FilterOp::Eq, FilterOp::Like],
+            },
+            FieldSpec {
+                name: "price",
+                ty: FieldType::Int,
+                sortable: true,
+                ops: FilterOp::COMPARISONS,
+            },
+        ];
+        const DEFAULT_LIMIT: usize = 2;
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Item(i64, &'static str, i64);
+
+    impl Listable for Item {
+        fn field(&self, name: &str) -> Value {
+            match name {
+                "id" => Value::Int(self.0),
+                "name" => Value::Text(self.1.to_string()),
+                _ => Value::Int(self.2),
+            }
+        }
+    }
+
+    fn items() -> Vec<Item> {
+        vec![
+            Item(1, "apple", 30),
+            Item(2, "banana", 10),
+            Item(3, "cherry", 30),
+            Item(4, "date", 20),
+            Item(5, "elderberry", 50),
+        ]
+    }
+
+    fn params(query: &str) -> Result<ListParams<Items>, String> {
+        ListParams::parse(format!("/items?{}", query).parse().unwrap())
+    }
+
+    fn ids(page: &Page<Item>) -> Vec<i64> {
+        page.data.iter().map(|item| item.0).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let parsed = params("sort=-price,name&price[gte]=10&name[like]=rr&limit=5").unwrap();
+        assert_eq!(
+            parsed.sort,
+            vec![
+                Sort {
+                    field: "price",
+                    descending: true
+                },
+                Sort {
+                    field: "name",
+                    descending: false
+                },
+                Sort {
+                    field: "id",
+                    descending: false
+                },
+            ]
+        );
+        assert_eq!(parsed.filters.len(), 2);
+        assert_eq!(parsed.limit, 5);
+
+        let errors = [
+            ("sort=secret", "Cannot sort by secret"),
+            ("secret=1", "Cannot filter by secret"),
+            ("name[gt]=a", "Operator not allowed for name"),
+            ("price[between]=1", "Unknown operator between"),
+            ("price=cheap", "Invalid value for price"),
+            ("limit=1000", "limit must be between 1 and 100"),
+            ("cursor=garbage", "Invalid cursor"),
+        ];
+        for (query, message) in errors {
+            assert_eq!(params(query).unwrap_err(), message, "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_offset_pagination() {
+        let params = params("sort=-price&offset=2").unwrap();
+        let page = params.apply(items());
+        // 50, 30 (1), 30 (3), 20, 10 with ties broken by id.
+        assert_eq!(ids(&page), vec![3, 4]);
+        assert_eq!(page.page.total, Some(5));
+        assert!(page.page.has_more);
+        assert_eq!(
+            params.links(&page),
+            "</items?sort=-price&offset=0>; rel=\"first\", \
+             </items?sort=-price&offset=0>; rel=\"prev\", \
+             </items?sort=-price&offset=4>; rel=\"next\""
+        );
+    }
+
+    #[test]
+    fn test_cursor_pagination() {
+        let first = params("sort=-price&cursor=").unwrap();
+        let page = first.apply(items());
+        assert_eq!(ids(&page), vec![5, 1]);
+
+        let cursor = page.page.next_cursor.clone().unwrap();
+        let second = params(&format!("sort=-price&cursor={}", cursor)).unwrap();
+        let page = second.apply(items());
+        assert_eq!(ids(&page), vec![3, 4]);
+
+        // The cursor is bound to the sort it came from.
+        assert!(params(&format!("sort=price&cursor={}", cursor)).is_err());
+
+        let cursor = page.page.next_cursor.clone().unwrap();
+        let page = params(&format!("sort=-price&cursor={}", cursor))
+            .unwrap()
+            .apply(items());
+        assert_eq!(ids(&page), vec![2]);
+        assert!(!page.page.has_more);
+        assert!(page.page.next_cursor.is_none());
+    }
+
+    #[test]
+    fn test_filters() {
+        let page = params("price[gte]=20&price[lt]=50&limit=10")
+            .unwrap()
+            .apply(items());
+        assert_eq!(ids(&page), vec![1, 3, 4]);
+
+        let page = params("name[like]=rr").unwrap().apply(items());
+        assert_eq!(ids(&page), vec![3, 5]);
+    }
+
+    #[test]
+    fn test_sql() {
+        let params = params(&format!(
+            "sort=-price&name[like]=a%&cursor={}",
+            params("sort=-price")
+                .unwrap()
+                .encode_cursor(vec![Value::Int(30), Value::Int(1)])
+        ))
+        .unwrap();
+        let mut query = QueryBuilder::new("SELECT * FROM items");
+        params.push_sql(&mut query);
+        assert_eq!(
+            query.sql(),
+            "SELECT * FROM items WHERE 1 = 1 AND name LIKE ? ESCAPE '\\' \
+             AND ((price < ?) OR (price = ? AND id > ?)) ORDER BY price DESC, id ASC LIMIT ?"
+        );
+    }
+}
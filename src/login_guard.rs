@@ -0,0 +1,315 @@
+use std::{collections::HashMap, net::IpAddr, sync::{Arc, Mutex}, time::Duration};
+
+use tokio::time::Instant;
+
+use crate::audit::{AuditAction, AuditEvent, AuditLog};
+
+#[derive(Debug, Clone)]
+pub struct LoginGuardConfig {
+    // Failures allowed before backoff kicks in.
+    pub free_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // Failures that trigger a full lockout.
+    pub lockout_threshold: u32,
+    pub lockout_duration: Duration,
+    // Failure history is forgotten after this much quiet time.
+    pub window: Duration,
+}
+
+impl Default for LoginGuardConfig {
+    fn default() -> Self {
+        Self {
+            free_attempts: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            lockout_threshold: 10,
+            lockout_duration: Duration::from_secs(15 * 60),
+            window: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+// How often `check` drops the history of keys that have gone quiet.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+struct State {
+    attempts: HashMap<String, Attempts>,
+    next_sweep: Instant,
+}
+
+pub struct LoginGuard {
+    config: LoginGuardConfig,
+    state: Mutex<State>,
+    audit: Arc<dyn AuditLog>,
+}
+
+impl LoginGuard {
+    pub fn new(config: LoginGuardConfig, audit: Arc<dyn AuditLog>) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State {
+                attempts: HashMap::new(),
+                next_sweep: Instant::now() + SWEEP_INTERVAL,
+            }),
+            audit,
+        }
+    }
+
+    fn keys(username: &str, ip: IpAddr) -> [String; 2] {
+        [format!("user:{}", username), format!("ip:{}", ip)]
+    }
+
+    fn is_stale(&self, entry: &Attempts, now: Instant) -> bool {
+        now.duration_since(entry.last_failure) > self.config.window
+            && entry.locked_until.is_none_or(|until| until <= now)
+    }
+
+    // Returns how long the caller has to wait before another attempt is allowed.
+    // An allowed attempt is counted as a failure straight away, under the same
+    // lock, so parallel guesses can't all slip through before any of them is
+    // recorded; `record_success` gives it back.
+    pub fn check(&self, username: &str, ip: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if now >= state.next_sweep {
+            state.attempts.retain(|_, entry| !self.is_stale(entry, now));
+            state.next_sweep = now + SWEEP_INTERVAL;
+        }
+        let attempts = &mut state.attempts;
+
+        let keys = Self::keys(username, ip);
+        let mut wait = Duration::ZERO;
+        for key in &keys {
+            let Some(entry) = attempts.get(key).copied() else {
+                continue;
+            };
+            if self.is_stale(&entry, now) {
+                attempts.remove(key);
+                continue;
+            }
+            if let Some(until) = entry.locked_until.filter(|until| *until > now) {
+                wait = wait.max(until - now);
+                continue;
+            }
+            let ready_at = entry.last_failure + self.backoff(entry.failures);
+            if ready_at > now {
+                wait = wait.max(ready_at - now);
+            }
+        }
+
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        for key in keys {
+            let entry = attempts.entry(key).or_insert(Attempts {
+                failures: 0,
+                last_failure: now,
+                locked_until: None,
+            });
+            entry.failures += 1;
+            entry.last_failure = now;
+        }
+        Ok(())
+    }
+
+    // The failure itself was counted by `check`; this audits it and arms the
+    // lockout, again once any earlier lockout has run out.
+    pub fn record_failure(&self, username: &str, ip: IpAddr) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        self.audit.record(AuditEvent::new(
+            AuditAction::LoginFailed,
+            format!("user:{}", username),
+            format!("from {}", ip),
+        ));
+
+        for key in Self::keys(username, ip) {
+            let Some(entry) = state.attempts.get_mut(&key) else {
+                continue;
+            };
+            if entry.failures >= self.config.lockout_threshold
+                && entry.locked_until.is_none_or(|until| until <= now)
+            {
+                entry.locked_until = Some(now + self.config.lockout_duration);
+                self.audit.record(AuditEvent::new(
+                    AuditAction::LockedOut,
+                    key,
+                    format!(
+                        "{} failed attempts, locked for {}s",
+                        entry.failures,
+                        self.config.lockout_duration.as_secs()
+                    ),
+                ));
+            }
+        }
+    }
+
+    // Only the username counter is cleared; a valid login for one account
+    // must not reset the budget of an IP that is guessing other accounts. The
+    // IP just gets back the attempt `check` reserved.
+    pub fn record_success(&self, username: &str, ip: IpAddr) {
+        let mut state = self.state.lock().unwrap();
+        state.attempts.remove(&format!("user:{}", username));
+        if let Some(entry) = state.attempts.get_mut(&format!("ip:{}", ip)) {
+            entry.failures = entry.failures.saturating_sub(1);
+        }
+        drop(state);
+        self.audit.record(AuditEvent::new(
+            AuditAction::LoginSucceeded,
+            format!("user:{}", username),
+            format!("from {}", ip),
+        ));
+    }
+
+    fn backoff(&self, failures: u32) -> Duration {
+        if failures < self.config.free_attempts {
+            return Duration::ZERO;
+        }
+        let exponent = (failures - self.config.free_attempts).min(16);
+        self.config
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.config.max_delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::audit::MemoryAuditLog;
+
+    fn guard(audit: Arc<MemoryAuditLog>) -> LoginGuard {
+        LoginGuard::new(
+            LoginGuardConfig {
+                free_attempts: 2,
+                base_delay: Duration::from_secs(1),
+                max_delay: Duration::from_secs(8),
+                lockout_threshold: 5,
+                lockout_duration: Duration::from_secs(300),
+                window: Duration::from_secs(3600),
+            },
+            audit,
+        )
+    }
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+    // A wrong password, the way the login handlers report it.
+    fn fail(guard: &LoginGuard, username: &str, ip: IpAddr) {
+        assert!(guard.check(username, ip).is_ok());
+        guard.record_failure(username, ip);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_exponential_backoff() {
+        let guard = guard(Arc::new(MemoryAuditLog::default()));
+
+        fail(&guard, "hadi", IP);
+        fail(&guard, "hadi", IP);
+        assert_eq!(guard.check("hadi", IP), Err(Duration::from_secs(1)));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        fail(&guard, "hadi", IP);
+        assert_eq!(guard.check("hadi", IP), Err(Duration::from_secs(2)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_parallel_attempts_are_reserved() {
+        let guard = guard(Arc::new(MemoryAuditLog::default()));
+
+        // Nothing has failed yet, but only the free attempts get through.
+        assert!(guard.check("hadi", IP).is_ok());
+        assert!(guard.check("hadi", IP).is_ok());
+        assert_eq!(guard.check("hadi", IP), Err(Duration::from_secs(1)));
+
+        // A success hands back what it reserved.
+        guard.record_success("hadi", IP);
+        assert!(guard.check("other", IP).is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lockout_is_audited() {
+        let audit = Arc::new(MemoryAuditLog::default());
+        let guard = guard(audit.clone());
+
+        for _ in 0..5 {
+            tokio::time::advance(Duration::from_secs(8)).await;
+            fail(&guard, "hadi", IP);
+        }
+        assert_eq!(guard.check("hadi", IP), Err(Duration::from_secs(300)));
+
+        let locked = || -> Vec<String> {
+            audit
+                .events()
+                .into_iter()
+                .filter(|event| event.action == AuditAction::LockedOut)
+                .map(|event| event.subject)
+                .collect()
+        };
+        assert_eq!(
+            locked(),
+            vec!["user:hadi".to_string(), "ip:10.0.0.1".to_string()]
+        );
+
+        // Failing again once the lockout is over locks again.
+        tokio::time::advance(Duration::from_secs(300)).await;
+        fail(&guard, "hadi", IP);
+        assert_eq!(guard.check("hadi", IP), Err(Duration::from_secs(300)));
+        assert_eq!(locked().len(), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ip_tracked_across_usernames() {
+        let guard = guard(Arc::new(MemoryAuditLog::default()));
+
+        fail(&guard, "alice", IP);
+        fail(&guard, "bob", IP);
+
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        assert!(guard.check("carol", IP).is_err());
+        assert!(guard.check("carol", other).is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_success_resets_username_only() {
+        let guard = guard(Arc::new(MemoryAuditLog::default()));
+
+        fail(&guard, "hadi", IP);
+        fail(&guard, "hadi", IP);
+        assert!(guard.check("hadi", IP).is_err());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(guard.check("hadi", IP).is_ok());
+        guard.record_success("hadi", IP);
+
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        assert!(guard.check("hadi", other).is_ok());
+        assert!(guard.check("hadi", IP).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_quiet_keys_are_evicted() {
+        let guard = guard(Arc::new(MemoryAuditLog::default()));
+
+        for n in 0..3 {
+            fail(
+                &guard,
+                &format!("user{}", n),
+                Ipv4Addr::new(10, 0, 1, n).into(),
+            );
+        }
+        tokio::time::advance(Duration::from_secs(3601)).await;
+        assert!(guard.check("hadi", Ipv4Addr::LOCALHOST.into()).is_ok());
+        assert_eq!(guard.state.lock().unwrap().attempts.len(), 2);
+    }
+}
@@ -1,22 +1,255 @@
 mod login_request;
 mod try_response;
+// Its tests predate the clippy gate.
+#[allow(clippy::len_zero)]
 mod try_form;
 mod try_cookie;
+// Examples that are never mounted.
+#[allow(dead_code)]
 mod try_middleware;
+#[allow(dead_code)]
 mod try_error_handler;
+#[allow(dead_code)]
 mod try_state_extractor;
 mod try_multiple_router;
-
-use axum::{Router, routing::get, serve};
+mod audit;
+mod auth;
+mod batch;
+mod blob_store;
+mod client_ip;
+mod compression;
+mod config;
+mod cors;
+mod csrf;
+mod error;
+mod etag;
+mod flash;
+mod graphql;
+mod grpc;
+mod hub;
+mod idempotency;
+mod list_params;
+mod login_guard;
+mod negotiate;
+mod openapi;
+mod products;
+mod profile_image;
+mod rate_limit;
+mod security_headers;
+mod sse;
+mod static_files;
+mod templates;
+mod tls;
+mod tus;
+mod upload;
+mod websocket;
+
+use std::{env, net::SocketAddr, sync::Arc};
+
+use axum::{
+    Extension, Router,
+    middleware::{from_fn, from_fn_with_state},
+    routing::get,
+    serve,
+};
 use tokio::net::TcpListener;
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    audit::TracingAuditLog,
+    auth::{AuthState, TokenService, UserStore},
+    batch::BatchState,
+    blob_store::{FileState, UrlSigner},
+    compression::{compression_layer, decompress_request},
+    config::AppConfig,
+    cors::cors_layer,
+    csrf::{Csrf, csrf},
+    error::fallback,
+    flash::{Flashes, flash},
+    graphql::GraphqlState,
+    grpc::{grpc_rate_limit, multiplex},
+    hub::Hub,
+    idempotency::{Idempotency, idempotency},
+    login_guard::LoginGuard,
+    products::{CatalogState, MemoryCatalog, SqliteCatalog},
+    profile_image::ImagePipeline,
+    rate_limit::{RateLimiter, Store, rate_limit},
+    security_headers::security_headers,
+    sse::SseState,
+    templates::{Templates, html_errors},
+    tus::{Tus, TusState},
+    upload::UploadState,
+    websocket::WebSocketState,
+};
+
+async fn app(config: &AppConfig) -> Router {
+    let users = config
+        .users
+        .iter()
+        .fold(UserStore::default(), |users, (username, password)| {
+            users.with_user(username, password)
+        });
+    let tokens = Arc::new(TokenService::new(&config.token_secret, config.token_ttl));
+    let limiter = Arc::new(
+        RateLimiter::new(
+            config.rate_limit.clone(),
+            Store::from_config(&config.rate_limit),
+        )
+        .with_tokens(tokens.clone()),
+    );
+    let csrf_state = Arc::new(Csrf::new(config.csrf.clone(), &config.token_secret));
+    let templates = Arc::new(
+        Templates::new(config.templates.clone()).with_global("csrf_field", &config.csrf.field_name),
+    );
+    let flashes = Arc::new(Flashes::new(config.flash.clone(), &config.token_secret));
+    let idempotency_state =
+        Arc::new(Idempotency::new(config.idempotency.clone()).with_tokens(tokens.clone()));
+    let hub = Arc::new(Hub::new(config.hub.clone()));
+    let websocket_state = WebSocketState {
+        config: Arc::new(config.websocket.clone()),
+        hub: hub.clone(),
+        tokens: tokens.clone(),
+    };
+    let sse_state = SseState {
+        config: Arc::new(config.sse.clone()),
+        hub: hub.clone(),
+        tokens: tokens.clone(),
+    };
+    let users = Arc::new(users);
+    let auth_state = AuthState {
+        users: users.clone(),
+        tokens: tokens.clone(),
+        guard: Arc::new(LoginGuard::new(
+            config.login_guard.clone(),
+            Arc::new(TracingAuditLog),
+        )),
+    };
+    let signer = Arc::new(UrlSigner::new(&config.token_secret));
+    let store = config
+        .storage
+        .build(signer.clone())
+        .expect("failed to configure blob storage");
+    let images = Arc::new(ImagePipeline::new(config.images.clone(), store.clone()));
+    let tus = Arc::new(Tus::new(config.tus.clone(), store.clone()));
+    tus.clone().watch();
+    let graphql_config = config.graphql.clone();
+    let (catalog, graphql) = match &config.database_url {
+        Some(url) => {
+            let repo = Arc::new(
+                SqliteCatalog::connect(url)
+                    .await
+                    .expect("failed to connect to the database"),
+            );
+            (
+                products::router(CatalogState {
+                    repo: repo.clone(),
+                    tokens: tokens.clone(),
+                    hub: hub.clone(),
+                }),
+                GraphqlState::new(graphql_config, repo, tokens.clone()),
+            )
+        }
+        None => {
+            let repo = Arc::new(MemoryCatalog::default());
+            (
+                products::router(CatalogState {
+                    repo: repo.clone(),
+                    tokens: tokens.clone(),
+                    hub: hub.clone(),
+                }),
+                GraphqlState::new(graphql_config, repo, tokens.clone()),
+            )
+        }
+    };
+
+    let api = Router::new()
+        .merge(auth::router(auth_state.clone()))
+        .merge(upload::router(UploadState {
+            config: Arc::new(config.upload.clone()),
+            store: store.clone(),
+            images: Some(images.clone()),
+            tokens: tokens.clone(),
+        }))
+        .merge(profile_image::router(images))
+        .merge(tus::router(TusState {
+            tus,
+            tokens: tokens.clone(),
+        }))
+        .merge(catalog)
+        .merge(graphql::router(graphql))
+        .merge(blob_store::router(FileState { store, signer }))
+        .merge(websocket::router(websocket_state))
+        .merge(sse::router(sse_state))
+        .merge(openapi::router());
+
+    // Everything but the gRPC multiplexer. Batch sub-requests go through the
+    // same stack, so each one is rate limited and checked like a direct call.
+    let layers = |router: Router| {
+        router
+            .layer(from_fn(html_errors))
+            .layer(Extension(templates.clone()))
+            .layer(from_fn_with_state(flashes.clone(), flash))
+            .layer(from_fn_with_state(idempotency_state.clone(), idempotency))
+            .layer(from_fn_with_state(csrf_state.clone(), csrf))
+            .layer(from_fn_with_state(
+                Arc::new(config.compression.clone()),
+                decompress_request,
+            ))
+            .layer(from_fn_with_state(limiter.clone(), rate_limit::<Store>))
+            .layer(from_fn_with_state(
+                Arc::new(config.security_headers.clone()),
+                security_headers,
+            ))
+            .layer(compression_layer(&config.compression))
+            .layer(cors_layer(&config.cors))
+    };
+
+    let app = api.clone().merge(batch::router(BatchState {
+        config: Arc::new(config.batch.clone()),
+        router: layers(api),
+    }));
+    // The frontend, when there is one, takes over `/` and unmatched paths.
+    let app = match &config.static_files {
+        Some(static_config) => app.merge(static_files::router(static_config.clone())),
+        None => app
+            .route("/", get(|| async { "Hello, World!" }))
+            .fallback(fallback),
+    };
+
+    let grpc = grpc::router(&config.grpc, auth_state)
+        .await
+        .layer(from_fn_with_state(limiter.clone(), grpc_rate_limit::<Store>));
+
+    layers(app).layer(from_fn_with_state(grpc, multiplex))
+}
 
 #[tokio::main]
 async fn main() {
-    let app = Router::new()
-        .route("/", get(|| async { "Hello, World!" }));
+    // `axum-rs openapi` prints the API document instead of serving it.
+    if env::args().nth(1).as_deref() == Some("openapi") {
+        println!("{}", openapi::spec().to_pretty_json().unwrap());
+        return;
+    }
+
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    let config = AppConfig::from_env();
+    let app = app(&config).await;
+
+    if let Some(tls_config) = config.tls.clone() {
+        let address = config.bind_address.parse().unwrap();
+        tls::run(tls_config, address, app).await.unwrap();
+        return;
+    }
 
-    let listener = TcpListener::bind("127.0.0.1:3000").await.unwrap();
-    serve(listener, app).await.unwrap();
+    let listener = TcpListener::bind(&config.bind_address).await.unwrap();
+    serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
 }
 
 #[cfg(test)]
@@ -0,0 +1,341 @@
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Request},
+    response::{IntoResponse, Response},
+};
+use http::{HeaderMap, HeaderValue, StatusCode, header, request::Parts};
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::error::AppError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MessagePack,
+    Cbor,
+    Xml,
+}
+
+impl Format {
+    // In order of preference when the client has none.
+    pub const ALL: [Format; 4] = [Format::Json, Format::MessagePack, Format::Cbor, Format::Xml];
+
+    // The first media type is the one sent in `Content-Type`.
+    pub fn media_types(&self) -> &'static [&'static str] {
+        match self {
+            Format::Json => &["application/json"],
+            Format::MessagePack => &[
+                "application/msgpack",
+                "application/vnd.msgpack",
+                "application/x-msgpack",
+            ],
+            Format::Cbor => &["application/cbor"],
+            Format::Xml => &["application/xml", "text/xml"],
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        self.media_types()[0]
+    }
+
+    pub fn from_content_type(headers: &HeaderMap) -> Option<Self> {
+        let value = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+        let essence = value.split(';').next()?.trim().to_ascii_lowercase();
+        if essence.starts_with("application/") && essence.ends_with("+json") {
+            return Some(Format::Json);
+        }
+        Self::ALL
+            .into_iter()
+            .find(|format| format.media_types().contains(&essence.as_str()))
+    }
+
+    // Picks the format with the highest `q` in `Accept`, where each format
+    // takes the `q` of its most specific matching range. A missing header
+    // accepts anything.
+    pub fn from_accept(headers: &HeaderMap) -> Option<Self> {
+        let values: Vec<_> = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        if values.is_empty() {
+            return Some(Format::Json);
+        }
+
+        let ranges: Vec<(String, f32)> = values
+            .join(",")
+            .split(',')
+            .filter_map(|range| {
+                let mut params = range.split(';');
+                let media = params.next()?.trim().to_ascii_lowercase();
+                if media.is_empty() {
+                    return None;
+                }
+                let q = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                Some((media, q))
+            })
+            .collect();
+
+        let quality = |format: &Format| {
+            format
+                .media_types()
+                .iter()
+                .filter_map(|media_type| {
+                    let (kind, _) = media_type.split_once('/').unwrap();
+                    ranges
+                        .iter()
+                        .filter_map(|(range, q)| {
+                            let specificity = if range == media_type {
+                                2
+                            } else if range.strip_suffix("/*") == Some(kind) {
+                                1
+                            } else if range == "*/*" {
+                                0
+                            } else {
+                                return None;
+                            };
+                            Some((specificity, *q))
+                        })
+                        .max_by_key(|(specificity, _)| *specificity)
+                })
+                .map(|(_, q)| q)
+                .fold(0.0, f32::max)
+        };
+
+        let mut best: Option<(Format, f32)> = None;
+        for format in Self::ALL {
+            let q = quality(&format);
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((format, q));
+            }
+        }
+        best.map(|(format, _)| format)
+    }
+
+    pub fn encode<T: Serialize>(&self, value: &T) -> anyhow::Result<Vec<u8>> {
+        Ok(match self {
+            Format::Json => serde_json::to_vec(value)?,
+            // Structs as maps, so field names survive like in the other formats.
+            Format::MessagePack => rmp_serde::to_vec_named(value)?,
+            Format::Cbor => {
+                let mut buffer = Vec::new();
+                ciborium::into_writer(value, &mut buffer)?;
+                buffer
+            }
+            Format::Xml => quick_xml::se::to_string_with_root("response", value)?.into_bytes(),
+        })
+    }
+
+    // Errors are messages for a 400 response.
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            Format::Json => serde_json::from_slice(bytes).map_err(|err| err.to_string()),
+            Format::MessagePack => rmp_serde::from_slice(bytes).map_err(|err| err.to_string()),
+            Format::Cbor => ciborium::from_reader(bytes).map_err(|err| err.to_string()),
+            Format::Xml => std::str::from_utf8(bytes)
+                .map_err(|err| err.to_string())
+                .and_then(|xml| quick_xml::de::from_str(xml).map_err(|err| err.to_string())),
+        }
+    }
+}
+
+// Whether the client is a browser asking for a page rather than data.
+pub fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("text/html"))
+}
+
+fn not_acceptable() -> AppError {
+    let supported: Vec<_> = Format::ALL
+        .iter()
+        .map(|format| format.content_type())
+        .collect();
+    AppError::new(
+        StatusCode::NOT_ACCEPTABLE,
+        format!("Supported response types: {}", supported.join(", ")),
+    )
+}
+
+// The response format asked for in `Accept`; 406 when none is supported.
+impl<S: Send + Sync> FromRequestParts<S> for Format {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Format::from_accept(&parts.headers).ok_or_else(not_acceptable)
+    }
+}
+
+// A body decoded according to `Content-Type` (415 when unsupported), paired
+// with the response format from `Accept`. As a response, `T` is encoded in
+// that format.
+#[derive(Debug)]
+pub struct Negotiated<T>(pub Format, pub T);
+
+impl<T, S> FromRequest<S> for Negotiated<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let accept = Format::from_accept(request.headers()).ok_or_else(not_acceptable)?;
+        let Some(format) = Format::from_content_type(request.headers()) else {
+            return Err(AppError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Unsupported Content-Type",
+            ));
+        };
+
+        // JSON keeps axum's rejections, e.g. 422 for well-formed but invalid data.
+        if format == Format::Json {
+            let Json(value) = Json::<T>::from_request(request, state)
+                .await
+                .map_err(|err| AppError::new(err.status(), err.body_text()))?;
+            return Ok(Negotiated(accept, value));
+        }
+
+        let bytes = Bytes::from_request(request, state)
+            .await
+            .map_err(|err| AppError::new(err.status(), err.body_text()))?;
+        let value = format
+            .decode(&bytes)
+            .map_err(|message| AppError::new(StatusCode::BAD_REQUEST, message))?;
+        Ok(Negotiated(accept, value))
+    }
+}
+
+impl<T: Serialize> IntoResponse for Negotiated<T> {
+    fn into_response(self) -> Response {
+        let Negotiated(format, value) = self;
+        match format.encode(&value) {
+            Ok(body) => (
+                [
+                    (
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static(format.content_type()),
+                    ),
+                    (header::VARY, HeaderValue::from_static("accept")),
+                ],
+                body,
+            )
+                .into_response(),
+            Err(err) => AppError::internal(err).into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{login_request::LoginRequest, try_response::AuthResponse};
+    use axum::{
+        Router,
+        routing::{get, post},
+    };
+    use axum_test::TestServer;
+
+    fn accept(value: &str) -> Option<Format> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_str(value).unwrap());
+        Format::from_accept(&headers)
+    }
+
+    #[test]
+    fn test_accept() {
+        assert_eq!(Format::from_accept(&HeaderMap::new()), Some(Format::Json));
+        assert_eq!(accept("*/*"), Some(Format::Json));
+        assert_eq!(accept("application/cbor"), Some(Format::Cbor));
+        assert_eq!(
+            accept("application/json;q=0.5, application/msgpack"),
+            Some(Format::MessagePack)
+        );
+        assert_eq!(accept("text/*"), Some(Format::Xml));
+        // The most specific range decides, so JSON is excluded here.
+        assert_eq!(
+            accept("application/*, application/json;q=0"),
+            Some(Format::MessagePack)
+        );
+        assert_eq!(accept("text/html"), None);
+    }
+
+    fn app() -> Router {
+        async fn login(Negotiated(format, request): Negotiated<LoginRequest>) -> impl IntoResponse {
+            Negotiated(
+                format,
+                AuthResponse {
+                    token: request.username,
+                },
+            )
+        }
+
+        async fn token(format: Format) -> Negotiated<AuthResponse> {
+            Negotiated(
+                format,
+                AuthResponse {
+                    token: "TOKEN".to_string(),
+                },
+            )
+        }
+
+        Router::new()
+            .route("/login", post(login))
+            .route("/token", get(token))
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_formats() {
+        let server = TestServer::new(app()).unwrap();
+        let request = LoginRequest {
+            username: "hadi".to_string(),
+            password: "password".to_string(),
+        };
+
+        for format in Format::ALL {
+            let response = server
+                .post("/login")
+                .content_type(format.content_type())
+                .add_header("Accept", format.content_type())
+                .bytes(format.encode(&request).unwrap().into())
+                .await;
+            response.assert_status_ok();
+            response.assert_header("Content-Type", format.content_type());
+            let body: AuthResponse = format.decode(response.as_bytes()).unwrap();
+            assert_eq!(body.token, "hadi");
+        }
+
+        let response = server
+            .get("/token")
+            .add_header("Accept", "application/xml")
+            .await;
+        response.assert_text("<response><token>TOKEN</token></response>");
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_formats() {
+        let server = TestServer::new(app()).unwrap();
+
+        let response = server.get("/token").add_header("Accept", "text/html").await;
+        response.assert_status(StatusCode::NOT_ACCEPTABLE);
+
+        let response = server
+            .post("/login")
+            .content_type("text/plain")
+            .text("hadi")
+            .await;
+        response.assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let response = server
+            .post("/login")
+            .content_type("application/cbor")
+            .bytes(vec![0xff].into())
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+}
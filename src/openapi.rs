@@ -0,0 +1,284 @@
+use std::sync::Arc;
+
+use axum::{
+    Json, Router,
+    extract::Path,
+    middleware::from_fn_with_state,
+    response::{Html, IntoResponse, Response},
+    routing::get,
+};
+use http::{StatusCode, header};
+use rust_embed::RustEmbed;
+use utoipa::OpenApi;
+
+use crate::{
+    auth::AuthApi,
+    batch::BatchApi,
+    blob_store::FileApi,
+    error::{AppError, ErrorBody},
+    graphql::GraphqlApi,
+    products::CatalogApi,
+    profile_image::ProfileImageApi,
+    security_headers::{CspNonce, SecurityHeadersConfig, override_security_headers},
+    sse::SseApi,
+    tus::TusApi,
+    upload::UploadApi,
+    websocket::WebSocketApi,
+};
+
+// Swagger UI 5.17.14, from the `swagger-ui-dist` package.
+#[derive(RustEmbed)]
+#[folder = "assets/swagger-ui/"]
+struct SwaggerUi;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "axum-rs"),
+    components(schemas(ErrorBody)),
+    tags(
+        (name = "auth", description = "Login and session tokens"),
+        (name = "categories", description = "Product categories"),
+        (name = "products", description = "Product catalog"),
+        (name = "files", description = "Uploads and processed images"),
+        (name = "batch", description = "Several requests in one round trip"),
+        (name = "graphql", description = "The catalog as a GraphQL schema"),
+        (name = "realtime", description = "Hub events over WebSocket and SSE"),
+    )
+)]
+struct ApiDoc;
+
+// The document is assembled from the `OpenApi` of every module, next to the
+// handlers and types it describes.
+pub fn spec() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+        .merge_from(AuthApi::openapi())
+        .merge_from(CatalogApi::openapi())
+        .merge_from(UploadApi::openapi())
+        .merge_from(ProfileImageApi::openapi())
+        .merge_from(BatchApi::openapi())
+        .merge_from(TusApi::openapi())
+        .merge_from(FileApi::openapi())
+        .merge_from(GraphqlApi::openapi())
+        .merge_from(WebSocketApi::openapi())
+        .merge_from(SseApi::openapi())
+}
+
+// The first segment of every documented path plus the docs themselves, i.e.
+// everything `app()` mounts besides a frontend.
+pub fn mount_points() -> Vec<String> {
+    let mut points: Vec<String> = spec()
+        .paths
+        .paths
+        .keys()
+        .filter_map(|path| path.split('/').nth(1))
+        .map(|segment| format!("/{}", segment))
+        .chain(["/docs".to_string(), "/openapi.json".to_string()])
+        .collect();
+    points.sort();
+    points.dedup();
+    points
+}
+
+async fn openapi_json() -> impl IntoResponse {
+    Json(spec())
+}
+
+async fn swagger_ui(CspNonce(nonce): CspNonce) -> Html<String> {
+    Html(format!(
+        r##"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>API docs</title>
+<link rel="stylesheet" href="/docs/assets/swagger-ui.css">
+</head>
+<body>
+<div id="swagger-ui"></div>
+<script nonce="{nonce}" src="/docs/assets/swagger-ui-bundle.js"></script>
+<script nonce="{nonce}">
+SwaggerUIBundle({{ url: "/openapi.json", dom_id: "#swagger-ui" }});
+</script>
+</body>
+</html>"##
+    ))
+}
+
+async fn swagger_asset(Path(file): Path<String>) -> Result<Response, AppError> {
+    let asset = SwaggerUi::get(&file)
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "Asset not found"))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, asset.metadata.mimetype().to_string()),
+            (header::CACHE_CONTROL, "public, max-age=86400".to_string()),
+        ],
+        asset.data,
+    )
+        .into_response())
+}
+
+// Swagger UI sets inline styles and shows data: images, so it gets a looser
+// CSP than the rest of the app.
+fn docs_security_headers() -> SecurityHeadersConfig {
+    SecurityHeadersConfig {
+        content_security_policy: Some(
+            "default-src 'self'; script-src 'self' 'nonce-{nonce}'; \
+             style-src 'self' 'unsafe-inline'; img-src 'self' data:; \
+             object-src 'none'; base-uri 'self'"
+                .to_string(),
+        ),
+        ..SecurityHeadersConfig::default()
+    }
+}
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/docs", get(swagger_ui))
+        .route("/docs/assets/{file}", get(swagger_asset))
+        .route_layer(from_fn_with_state(
+            Arc::new(docs_security_headers()),
+            override_security_headers,
+        ))
+        .route("/openapi.json", get(openapi_json))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app, blob_store::StorageConfig, config::AppConfig};
+    use axum_test::TestServer;
+    use http::{Method, StatusCode};
+    use serde_json::Value;
+
+    #[test]
+    fn test_spec() {
+        let spec = serde_json::to_value(spec()).unwrap();
+        assert_eq!(spec["openapi"], "3.1.0");
+        for path in [
+            "/uploads/tus",
+            "/uploads/tus/{id}",
+            "/files/{key}",
+            "/graphql",
+            "/ws",
+            "/events",
+        ] {
+            assert!(spec["paths"][path].is_object(), "{} is missing", path);
+        }
+
+        let login = &spec["paths"]["/login"]["post"];
+        assert!(login["requestBody"]["content"]["application/json"].is_object());
+        assert!(login["requestBody"]["content"]["application/x-www-form-urlencoded"].is_object());
+        assert_eq!(
+            login["responses"]["200"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/AuthResponse"
+        );
+        assert_eq!(
+            login["responses"]["401"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/ErrorBody"
+        );
+        for schema in [
+            "LoginRequest",
+            "LoginFormRequest",
+            "AuthResponse",
+            "ErrorBody",
+        ] {
+            assert!(
+                spec["components"]["schemas"][schema].is_object(),
+                "{} is missing",
+                schema
+            );
+        }
+
+        // List parameters come from the endpoint's `ListSpec`.
+        let params: Vec<_> = spec["paths"]["/products"]["get"]["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|param| param["name"].as_str().unwrap().to_string())
+            .collect();
+        for name in ["limit", "cursor", "sort", "name[like]", "price_cents[gte]"] {
+            assert!(params.contains(&name.to_string()), "{} is missing", name);
+        }
+    }
+
+    // Every documented operation must reach a handler of the real app, so
+    // renamed or removed routes cannot linger in the document.
+    #[tokio::test]
+    async fn test_documented_routes_exist() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.upload.dir = dir.path().join("uploads");
+        config.tus.dir = dir.path().join("tus");
+        config.storage = StorageConfig::Local {
+            dir: dir.path().join("blobs"),
+            base_url: String::new(),
+        };
+        let server = TestServer::new(app(&config).await).unwrap();
+
+        let spec = serde_json::to_value(spec()).unwrap();
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            let uri = path.replace("{id}", "1").replace("{id_category}", "1");
+            for method in item.as_object().unwrap().keys() {
+                let method = Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+                // A bearer token skips the CSRF check for unsafe methods.
+                let response = server
+                    .method(method.clone(), &uri)
+                    .add_header("Authorization", "Bearer documentation")
+                    .await;
+                let status = response.status_code();
+                assert_ne!(
+                    status,
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    "{} {}",
+                    method,
+                    path
+                );
+                // Handlers report missing resources, not missing routes.
+                assert!(
+                    status != StatusCode::NOT_FOUND || !response.text().contains("No route for"),
+                    "{} {} is not routed",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_docs_routes() {
+        let app = router().layer(from_fn_with_state(
+            Arc::new(SecurityHeadersConfig::default()),
+            crate::security_headers::security_headers,
+        ));
+        let server = TestServer::new(app).unwrap();
+
+        let response = server.get("/openapi.json").await;
+        response.assert_status_ok();
+        assert!(response.json::<Value>()["paths"]["/login"].is_object());
+
+        let response = server.get("/docs").await;
+        response.assert_status_ok();
+        response.assert_text_contains("swagger-ui-bundle.js");
+        let csp = response.header("Content-Security-Policy");
+        let csp = csp.to_str().unwrap();
+        assert!(!csp.contains("https:"));
+        // The nonce in the page is the one allowed by the policy.
+        let nonce = csp
+            .split("'nonce-")
+            .nth(1)
+            .unwrap()
+            .split('\'')
+            .next()
+            .unwrap();
+        response.assert_text_contains(format!("nonce=\"{}\"", nonce));
+
+        // The UI is served from the binary.
+        let response = server.get("/docs/assets/swagger-ui-bundle.js").await;
+        response.assert_status_ok();
+        response.assert_header("Content-Type", "text/javascript");
+        assert!(response.text().contains("SwaggerUIBundle"));
+        server
+            .get("/docs/assets/missing.js")
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,1517 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    Json, Router,
+    extract::{FromRef, Path, State},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use http::{HeaderValue, StatusCode, header};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{
+    QueryBuilder, SqlitePool,
+    error::ErrorKind,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthUser, TokenService},
+    error::{AppError, ErrorBody},
+    etag::{Conditional, ETag, Preconditions},
+    hub::Hub,
+    list_params::{
+        FieldSpec, FieldType, Filter, FilterOp, ListParams, ListSpec, Listable, Page, Pagination,
+        Value,
+    },
+};
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    ToSchema,
+)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct ProductId(pub i64);
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    ToSchema,
+)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct CategoryId(pub i64);
+
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow, ToSchema)]
+pub struct Category {
+    pub id: CategoryId,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct NewCategory {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow, ToSchema)]
+pub struct Product {
+    pub id: ProductId,
+    pub category_id: CategoryId,
+    pub name: String,
+    pub price_cents: i64,
+}
+
+// Fields accepted by `GET /products` for sorting and filtering.
+pub struct ProductList;
+
+impl ListSpec for ProductList {
+    const FIELDS: &'static [FieldSpec] = &[
+        FieldSpec {
+            name: "id",
+            ty: FieldType::Int,
+            sortable: true,
+            ops: &[],
+        },
+        FieldSpec {
+            name: "category_id",
+            ty: FieldType::Int,
+            sortable: false,
+            ops: &[FilterOp::Eq, FilterOp::Ne],
+        },
+        FieldSpec {
+            name: "name",
+            ty: FieldType::Text,
+            sortable: true,
+            ops: &[FilterOp::Eq, FilterOp::Like],
+        },
+        FieldSpec {
+            name: "price_cents",
+            ty: FieldType::Int,
+            sortable: true,
+            ops: FilterOp::COMPARISONS,
+        },
+    ];
+}
+
+impl Listable for Product {
+    fn field(&self, name: &str) -> Value {
+        match name {
+            "id" => Value::Int(self.id.0),
+            "category_id" => Value::Int(self.category_id.0),
+            "name" => Value::Text(self.name.clone()),
+            "price_cents" => Value::Int(self.price_cents),
+            _ => unreachable!("{} is not in ProductList", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct NewProduct {
+    pub category_id: CategoryId,
+    pub name: String,
+    pub price_cents: i64,
+}
+
+#[derive(Debug)]
+pub enum RepositoryError {
+    NotFound(String),
+    Conflict(String),
+    // A conditional write found the row changed since it was read.
+    Changed(String),
+    Database(anyhow::Error),
+}
+
+impl From<RepositoryError> for AppError {
+    fn from(err: RepositoryError) -> Self {
+        match err {
+            RepositoryError::NotFound(message) => AppError::new(StatusCode::NOT_FOUND, message),
+            RepositoryError::Conflict(message) => AppError::new(StatusCode::CONFLICT, message),
+            RepositoryError::Changed(message) => {
+                AppError::new(StatusCode::PRECONDITION_FAILED, message)
+            }
+            RepositoryError::Database(err) => AppError::internal(err),
+        }
+    }
+}
+
+type RepoResult<T> = Result<T, RepositoryError>;
+
+fn category_not_found(id: CategoryId) -> RepositoryError {
+    RepositoryError::NotFound(format!("Category {} not found", id.0))
+}
+
+fn product_not_found(id: ProductId) -> RepositoryError {
+    RepositoryError::NotFound(format!("Product {} not found", id.0))
+}
+
+fn category_exists(name: &str) -> RepositoryError {
+    RepositoryError::Conflict(format!("Category {} already exists", name))
+}
+
+fn product_exists(name: &str) -> RepositoryError {
+    RepositoryError::Conflict(format!("Product {} already exists in this category", name))
+}
+
+fn category_in_use(id: CategoryId) -> RepositoryError {
+    RepositoryError::Conflict(format!("Category {} still has products", id.0))
+}
+
+fn category_changed(id: CategoryId) -> RepositoryError {
+    RepositoryError::Changed(format!("Category {} was changed", id.0))
+}
+
+fn product_changed(id: ProductId) -> RepositoryError {
+    RepositoryError::Changed(format!("Product {} was changed", id.0))
+}
+
+pub trait CatalogRepository: Send + Sync + 'static {
+    fn list_categories(&self) -> impl Future<Output = RepoResult<Vec<Category>>> + Send;
+    fn get_category(&self, id: CategoryId) -> impl Future<Output = RepoResult<Category>> + Send;
+    // Batched lookup in ID order; unknown IDs are left out.
+    fn get_categories(
+        &self,
+        ids: &[CategoryId],
+    ) -> impl Future<Output = RepoResult<Vec<Category>>> + Send;
+    fn create_category(
+        &self,
+        new: NewCategory,
+    ) -> impl Future<Output = RepoResult<Category>> + Send;
+    // Writes given an `expected` row only go ahead while the stored row still
+    // equals it, and fail with `Changed` otherwise.
+    fn update_category(
+        &self,
+        id: CategoryId,
+        new: NewCategory,
+        expected: Option<&Category>,
+    ) -> impl Future<Output = RepoResult<Category>> + Send;
+    // Fails with a conflict while products still reference the category.
+    fn delete_category(
+        &self,
+        id: CategoryId,
+        expected: Option<&Category>,
+    ) -> impl Future<Output = RepoResult<()>> + Send;
+
+    fn list_products(
+        &self,
+        params: &ListParams<ProductList>,
+    ) -> impl Future<Output = RepoResult<Page<Product>>> + Send;
+    fn get_product(&self, id: ProductId) -> impl Future<Output = RepoResult<Product>> + Send;
+    // At most `limit` products of each category, lowest ids first.
+    fn products_in_categories(
+        &self,
+        ids: &[CategoryId],
+        limit: usize,
+    ) -> impl Future<Output = RepoResult<Vec<Product>>> + Send;
+    fn create_product(&self, new: NewProduct) -> impl Future<Output = RepoResult<Product>> + Send;
+    fn update_product(
+        &self,
+        id: ProductId,
+        new: NewProduct,
+        expected: Option<&Product>,
+    ) -> impl Future<Output = RepoResult<Product>> + Send;
+    fn delete_product(
+        &self,
+        id: ProductId,
+        expected: Option<&Product>,
+    ) -> impl Future<Output = RepoResult<()>> + Send;
+}
+
+// Separate counters, like SQLite's per-table rowids.
+#[derive(Default)]
+struct Catalog {
+    last_category_id: i64,
+    last_product_id: i64,
+    categories: BTreeMap<CategoryId, Category>,
+    products: BTreeMap<ProductId, Product>,
+}
+
+impl Catalog {
+    fn next_category_id(&mut self) -> CategoryId {
+        self.last_category_id += 1;
+        CategoryId(self.last_category_id)
+    }
+
+    fn next_product_id(&mut self) -> ProductId {
+        self.last_product_id += 1;
+        ProductId(self.last_product_id)
+    }
+
+    fn check_category_name(&self, name: &str, except: Option<CategoryId>) -> RepoResult<()> {
+        let taken = self
+            .categories
+            .values()
+            .any(|category| category.name == name && Some(category.id) != except);
+        if taken {
+            return Err(category_exists(name));
+        }
+        Ok(())
+    }
+
+    fn check_product(&self, new: &NewProduct, except: Option<ProductId>) -> RepoResult<()> {
+        if !self.categories.contains_key(&new.category_id) {
+            return Err(category_not_found(new.category_id));
+        }
+        let taken = self.products.values().any(|product| {
+            product.category_id == new.category_id
+                && product.name == new.name
+                && Some(product.id) != except
+        });
+        if taken {
+            return Err(product_exists(&new.name));
+        }
+        Ok(())
+    }
+}
+
+// Without `expected` only a missing row stops the write; with it, a missing
+// row means it was deleted since the caller read it.
+fn check_current<T: PartialEq>(
+    current: Option<&T>,
+    expected: Option<&T>,
+    not_found: impl FnOnce() -> RepositoryError,
+    changed: impl FnOnce() -> RepositoryError,
+) -> RepoResult<()> {
+    match (current, expected) {
+        (None, None) => Err(not_found()),
+        (current, Some(expected)) if current != Some(expected) => Err(changed()),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryCatalog {
+    catalog: Mutex<Catalog>,
+}
+
+impl CatalogRepository for MemoryCatalog {
+    async fn list_categories(&self) -> RepoResult<Vec<Category>> {
+        Ok(self
+            .catalog
+            .lock()
+            .unwrap()
+            .categories
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    async fn get_category(&self, id: CategoryId) -> RepoResult<Category> {
+        let catalog = self.catalog.lock().unwrap();
+        catalog
+            .categories
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| category_not_found(id))
+    }
+
+    async fn get_categories(&self, ids: &[CategoryId]) -> RepoResult<Vec<Category>> {
+        let catalog = self.catalog.lock().unwrap();
+        Ok(catalog
+            .categories
+            .values()
+            .filter(|category| ids.contains(&category.id))
+            .cloned()
+            .collect())
+    }
+
+    async fn create_category(&self, new: NewCategory) -> RepoResult<Category> {
+        let mut catalog = self.catalog.lock().unwrap();
+        catalog.check_category_name(&new.name, None)?;
+        let category = Category {
+            id: catalog.next_category_id(),
+            name: new.name,
+        };
+        catalog.categories.insert(category.id, category.clone());
+        Ok(category)
+    }
+
+    async fn update_category(
+        &self,
+        id: CategoryId,
+        new: NewCategory,
+        expected: Option<&Category>,
+    ) -> RepoResult<Category> {
+        let mut catalog = self.catalog.lock().unwrap();
+        check_current(
+            catalog.categories.get(&id),
+            expected,
+            || category_not_found(id),
+            || category_changed(id),
+        )?;
+        catalog.check_category_name(&new.name, Some(id))?;
+        let category = Category { id, name: new.name };
+        catalog.categories.insert(id, category.clone());
+        Ok(category)
+    }
+
+    async fn delete_category(
+        &self,
+        id: CategoryId,
+        expected: Option<&Category>,
+    ) -> RepoResult<()> {
+        let mut catalog = self.catalog.lock().unwrap();
+        check_current(
+            catalog.categories.get(&id),
+            expected,
+            || category_not_found(id),
+            || category_changed(id),
+        )?;
+        if catalog
+            .products
+            .values()
+            .any(|product| product.category_id == id)
+        {
+            return Err(category_in_use(id));
+        }
+        catalog.categories.remove(&id);
+        Ok(())
+    }
+
+    async fn list_products(&self, params: &ListParams<ProductList>) -> RepoResult<Page<Product>> {
+        let catalog = self.catalog.lock().unwrap();
+        Ok(params.apply(catalog.products.values().cloned()))
+    }
+
+    async fn get_product(&self, id: ProductId) -> RepoResult<Product> {
+        let catalog = self.catalog.lock().unwrap();
+        catalog
+            .products
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| product_not_found(id))
+    }
+
+    async fn products_in_categories(
+        &self,
+        ids: &[CategoryId],
+        limit: usize,
+    ) -> RepoResult<Vec<Product>> {
+        let catalog = self.catalog.lock().unwrap();
+        let mut counts: BTreeMap<CategoryId, usize> = BTreeMap::new();
+        Ok(catalog
+            .products
+            .values()
+            .filter(|product| ids.contains(&product.category_id))
+            .filter(|product| {
+                let count = counts.entry(product.category_id).or_default();
+                *count += 1;
+                *count <= limit
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn create_product(&self, new: NewProduct) -> RepoResult<Product> {
+        let mut catalog = self.catalog.lock().unwrap();
+        catalog.check_product(&new, None)?;
+        let product = Product {
+            id: catalog.next_product_id(),
+            category_id: new.category_id,
+            name: new.name,
+            price_cents: new.price_cents,
+        };
+        catalog.products.insert(product.id, product.clone());
+        Ok(product)
+    }
+
+    async fn update_product(
+        &self,
+        id: ProductId,
+        new: NewProduct,
+        expected: Option<&Product>,
+    ) -> RepoResult<Product> {
+        let mut catalog = self.catalog.lock().unwrap();
+        check_current(
+            catalog.products.get(&id),
+            expected,
+            || product_not_found(id),
+            || product_changed(id),
+        )?;
+        catalog.check_product(&new, Some(id))?;
+        let product = Product {
+            id,
+            category_id: new.category_id,
+            name: new.name,
+            price_cents: new.price_cents,
+        };
+        catalog.products.insert(id, product.clone());
+        Ok(product)
+    }
+
+    async fn delete_product(&self, id: ProductId, expected: Option<&Product>) -> RepoResult<()> {
+        let mut catalog = self.catalog.lock().unwrap();
+        check_current(
+            catalog.products.get(&id),
+            expected,
+            || product_not_found(id),
+            || product_changed(id),
+        )?;
+        catalog.products.remove(&id);
+        Ok(())
+    }
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS categories (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS products (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    category_id INTEGER NOT NULL REFERENCES categories (id),
+    name TEXT NOT NULL,
+    price_cents INTEGER NOT NULL,
+    UNIQUE (category_id, name)
+);
+";
+
+pub struct SqliteCatalog {
+    pool: SqlitePool,
+}
+
+fn database_error(err: sqlx::Error) -> RepositoryError {
+    RepositoryError::Database(err.into())
+}
+
+fn constraint_kind(err: &sqlx::Error) -> Option<ErrorKind> {
+    err.as_database_error().map(|err| err.kind())
+}
+
+impl SqliteCatalog {
+    // e.g. `sqlite://catalog.db` or `sqlite::memory:`. The schema is created
+    // if it does not exist yet.
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)?
+            .create_if_missing(true)
+            .foreign_keys(true);
+        // Every in-memory connection is its own database, so share just one.
+        let max_connections = if url.contains(":memory:") { 1 } else { 5 };
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .connect_with(options)
+            .await?;
+        sqlx::raw_sql(SCHEMA).execute(&pool).await?;
+        Ok(Self { pool })
+    }
+}
+
+impl CatalogRepository for SqliteCatalog {
+    async fn list_categories(&self) -> RepoResult<Vec<Category>> {
+        sqlx::query_as("SELECT id, name FROM categories ORDER BY id")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(database_error)
+    }
+
+    async fn get_category(&self, id: CategoryId) -> RepoResult<Category> {
+        sqlx::query_as("SELECT id, name FROM categories WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(database_error)?
+            .ok_or_else(|| category_not_found(id))
+    }
+
+    async fn get_categories(&self, ids: &[CategoryId]) -> RepoResult<Vec<Category>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut query = QueryBuilder::new("SELECT id, name FROM categories WHERE id IN (");
+        let mut separated = query.separated(", ");
+        for id in ids {
+            separated.push_bind(*id);
+        }
+        query.push(") ORDER BY id");
+        query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(database_error)
+    }
+
+    async fn create_category(&self, new: NewCategory) -> RepoResult<Category> {
+        sqlx::query_as("INSERT INTO categories (name) VALUES (?) RETURNING id, name")
+            .bind(&new.name)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|err| match constraint_kind(&err) {
+                Some(ErrorKind::UniqueViolation) => category_exists(&new.name),
+                _ => database_error(err),
+            })
+    }
+
+    // A conditional write compares every column in the same statement, so no
+    // other write can land between the check and the change.
+    async fn update_category(
+        &self,
+        id: CategoryId,
+        new: NewCategory,
+        expected: Option<&Category>,
+    ) -> RepoResult<Category> {
+        sqlx::query_as(
+            "UPDATE categories SET name = ?1 WHERE id = ?2 AND (?3 IS NULL OR name = ?3)
+             RETURNING id, name",
+        )
+        .bind(&new.name)
+        .bind(id)
+        .bind(expected.map(|category| &category.name))
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|err| match constraint_kind(&err) {
+            Some(ErrorKind::UniqueViolation) => category_exists(&new.name),
+            _ => database_error(err),
+        })?
+        .ok_or_else(|| match expected {
+            Some(_) => category_changed(id),
+            None => category_not_found(id),
+        })
+    }
+
+    async fn delete_category(
+        &self,
+        id: CategoryId,
+        expected: Option<&Category>,
+    ) -> RepoResult<()> {
+        let result =
+            sqlx::query("DELETE FROM categories WHERE id = ?1 AND (?2 IS NULL OR name = ?2)")
+                .bind(id)
+                .bind(expected.map(|category| &category.name))
+                .execute(&self.pool)
+                .await
+                .map_err(|err| match constraint_kind(&err) {
+                    Some(ErrorKind::ForeignKeyViolation) => category_in_use(id),
+                    _ => database_error(err),
+                })?;
+        match (result.rows_affected(), expected) {
+            (0, Some(_)) => Err(category_changed(id)),
+            (0, None) => Err(category_not_found(id)),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list_products(&self, params: &ListParams<ProductList>) -> RepoResult<Page<Product>> {
+        let mut query =
+            QueryBuilder::new("SELECT id, category_id, name, price_cents FROM products");
+        params.push_sql(&mut query);
+        let products = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(database_error)?;
+
+        // Totals are only reported for offset pagination.
+        let total = match params.pagination {
+            Pagination::Offset(_) => {
+                let mut query = QueryBuilder::new("SELECT COUNT(*) FROM products");
+                params.push_filters(&mut query);
+                let total: i64 = query
+                    .build_query_scalar()
+                    .fetch_one(&self.pool)
+                    .await
+                    .map_err(database_error)?;
+                Some(total as u64)
+            }
+            Pagination::Cursor(_) => None,
+        };
+        Ok(params.page(products, total))
+    }
+
+    async fn get_product(&self, id: ProductId) -> RepoResult<Product> {
+        sqlx::query_as("SELECT id, category_id, name, price_cents FROM products WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(database_error)?
+            .ok_or_else(|| product_not_found(id))
+    }
+
+    async fn products_in_categories(
+        &self,
+        ids: &[CategoryId],
+        limit: usize,
+    ) -> RepoResult<Vec<Product>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut query = QueryBuilder::new(
+            "SELECT id, category_id, name, price_cents FROM (
+                 SELECT id, category_id, name, price_cents,
+                        ROW_NUMBER() OVER (PARTITION BY category_id ORDER BY id) AS position
+                 FROM products WHERE category_id IN (",
+        );
+        let mut separated = query.separated(", ");
+        for id in ids {
+            separated.push_bind(*id);
+        }
+        query.push(")) WHERE position <= ");
+        query.push_bind(limit as i64);
+        query.push(" ORDER BY id");
+        query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(database_error)
+    }
+
+    async fn create_product(&self, new: NewProduct) -> RepoResult<Product> {
+        sqlx::query_as(
+            "INSERT INTO products (category_id, name, price_cents) VALUES (?, ?, ?)
+             RETURNING id, category_id, name, price_cents",
+        )
+        .bind(new.category_id)
+        .bind(&new.name)
+        .bind(new.price_cents)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|err| match constraint_kind(&err) {
+            Some(ErrorKind::UniqueViolation) => product_exists(&new.name),
+            Some(ErrorKind::ForeignKeyViolation) => category_not_found(new.category_id),
+            _ => database_error(err),
+        })
+    }
+
+    async fn update_product(
+        &self,
+        id: ProductId,
+        new: NewProduct,
+        expected: Option<&Product>,
+    ) -> RepoResult<Product> {
+        sqlx::query_as(
+            "UPDATE products SET category_id = ?1, name = ?2, price_cents = ?3
+             WHERE id = ?4
+             AND (?5 IS NULL OR (category_id = ?5 AND name = ?6 AND price_cents = ?7))
+             RETURNING id, category_id, name, price_cents",
+        )
+        .bind(new.category_id)
+        .bind(&new.name)
+        .bind(new.price_cents)
+        .bind(id)
+        .bind(expected.map(|product| product.category_id))
+        .bind(expected.map(|product| &product.name))
+        .bind(expected.map(|product| product.price_cents))
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|err| match constraint_kind(&err) {
+            Some(ErrorKind::UniqueViolation) => product_exists(&new.name),
+            Some(ErrorKind::ForeignKeyViolation) => category_not_found(new.category_id),
+            _ => database_error(err),
+        })?
+        .ok_or_else(|| match expected {
+            Some(_) => product_changed(id),
+            None => product_not_found(id),
+        })
+    }
+
+    async fn delete_product(&self, id: ProductId, expected: Option<&Product>) -> RepoResult<()> {
+        let result = sqlx::query(
+            "DELETE FROM products WHERE id = ?1
+             AND (?2 IS NULL OR (category_id = ?2 AND name = ?3 AND price_cents = ?4))",
+        )
+        .bind(id)
+        .bind(expected.map(|product| product.category_id))
+        .bind(expected.map(|product| &product.name))
+        .bind(expected.map(|product| product.price_cents))
+        .execute(&self.pool)
+        .await
+        .map_err(database_error)?;
+        match (result.rows_affected(), expected) {
+            (0, Some(_)) => Err(product_changed(id)),
+            (0, None) => Err(product_not_found(id)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn unprocessable(message: &str) -> AppError {
+    AppError::new(StatusCode::UNPROCESSABLE_ENTITY, message)
+}
+
+// Errors are messages for a 422 response.
+fn validate_name(name: &str) -> Result<(), &'static str> {
+    if name.trim().is_empty() {
+        return Err("Name must not be empty");
+    }
+    Ok(())
+}
+
+fn validate_product(new: &NewProduct) -> Result<(), &'static str> {
+    validate_name(&new.name)?;
+    if new.price_cents < 0 {
+        return Err("Price must not be negative");
+    }
+    Ok(())
+}
+
+fn created<T: Serialize>(location: String, body: T) -> impl IntoResponse {
+    (
+        StatusCode::CREATED,
+        [(header::LOCATION, HeaderValue::try_from(location).unwrap())],
+        Json(body),
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/categories",
+    tag = "categories",
+    responses(
+        (status = 200, description = "All categories", body = Vec<Category>),
+    )
+)]
+async fn list_categories<R: CatalogRepository>(
+    State(repo): State<Arc<R>>,
+) -> Result<Json<Vec<Category>>, AppError> {
+    Ok(Json(repo.list_categories().await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/categories/{id}",
+    tag = "categories",
+    params(
+        ("id" = CategoryId, Path, description = "Category id"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag held by the client"),
+    ),
+    responses(
+        (status = 200, description = "The category", body = Category),
+        (status = 304, description = "Not modified"),
+        (status = 404, description = "Category not found", body = ErrorBody),
+    )
+)]
+async fn get_category<R: CatalogRepository>(
+    State(repo): State<Arc<R>>,
+    Path(id): Path<CategoryId>,
+    preconditions: Preconditions,
+) -> Result<Conditional<Category>, AppError> {
+    Ok(preconditions.json(repo.get_category(id).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/categories",
+    tag = "categories",
+    request_body = NewCategory,
+    responses(
+        (status = 201, description = "Created", body = Category),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+        (status = 409, description = "Name already taken", body = ErrorBody),
+    )
+)]
+async fn create_category<R: CatalogRepository>(
+    State(repo): State<Arc<R>>,
+    State(hub): State<Arc<Hub>>,
+    _user: AuthUser,
+    Json(new): Json<NewCategory>,
+) -> Result<impl IntoResponse, AppError> {
+    validate_name(&new.name).map_err(unprocessable)?;
+    let category = repo.create_category(new).await?;
+    announce(&hub, "categories", "created", &category);
+    Ok(created(format!("/categories/{}", category.id.0), category))
+}
+
+#[utoipa::path(
+    put,
+    path = "/categories/{id}",
+    tag = "categories",
+    params(
+        ("id" = CategoryId, Path, description = "Category id"),
+        ("If-Match" = Option<String>, Header, description = "ETag the change is based on"),
+    ),
+    request_body = NewCategory,
+    responses(
+        (status = 200, description = "Updated", body = Category),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Category not found", body = ErrorBody),
+        (status = 412, description = "ETag does not match", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    )
+)]
+async fn update_category<R: CatalogRepository>(
+    State(repo): State<Arc<R>>,
+    State(hub): State<Arc<Hub>>,
+    _user: AuthUser,
+    Path(id): Path<CategoryId>,
+    preconditions: Preconditions,
+    Json(new): Json<NewCategory>,
+) -> Result<Conditional<Category>, AppError> {
+    validate_name(&new.name).map_err(unprocessable)?;
+    let current = repo.get_category(id).await?;
+    preconditions.check(Some(&ETag::for_json(&current)))?;
+    let expected = preconditions.is_conditional().then_some(&current);
+    let category = repo.update_category(id, new, expected).await?;
+    announce(&hub, "categories", "updated", &category);
+    Ok(preconditions.json(category))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/categories/{id}",
+    tag = "categories",
+    params(
+        ("id" = CategoryId, Path, description = "Category id"),
+        ("If-Match" = Option<String>, Header, description = "ETag the change is based on"),
+    ),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Category not found", body = ErrorBody),
+        (status = 409, description = "Category still has products", body = ErrorBody),
+        (status = 412, description = "ETag does not match", body = ErrorBody),
+    )
+)]
+async fn delete_category<R: CatalogRepository>(
+    State(repo): State<Arc<R>>,
+    State(hub): State<Arc<Hub>>,
+    _user: AuthUser,
+    Path(id): Path<CategoryId>,
+    preconditions: Preconditions,
+) -> Result<StatusCode, AppError> {
+    let current = repo.get_category(id).await?;
+    preconditions.check(Some(&ETag::for_json(&current)))?;
+    let expected = preconditions.is_conditional().then_some(&current);
+    repo.delete_category(id, expected).await?;
+    announce(&hub, "categories", "deleted", &current);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Tells realtime clients about a write, on the `categories` or `products`
+// channel of the hub.
+fn announce(hub: &Hub, channel: &str, action: &str, item: &impl Serialize) {
+    hub.publish(channel, json!({ "action": action, "item": item }));
+}
+
+#[utoipa::path(
+    get,
+    path = "/categories/{id}/products",
+    tag = "categories",
+    params(
+        ("id" = CategoryId, Path, description = "Category id"),
+        ListParams<ProductList>,
+    ),
+    responses(
+        (status = 200, description = "A page of products", body = Page<Product>),
+        (status = 400, description = "Invalid list parameters", body = ErrorBody),
+        (status = 404, description = "Category not found", body = ErrorBody),
+    )
+)]
+async fn category_products<R: CatalogRepository>(
+    State(repo): State<Arc<R>>,
+    Path(id): Path<CategoryId>,
+    mut params: ListParams<ProductList>,
+) -> Result<Response, AppError> {
+    repo.get_category(id).await?;
+    params.filters.push(Filter {
+        field: "category_id",
+        op: FilterOp::Eq,
+        value: Value::Int(id.0),
+    });
+    Ok(params.respond(repo.list_products(&params).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/products",
+    tag = "products",
+    params(
+        ListParams<ProductList>,
+    ),
+    responses(
+        (status = 200, description = "A page of products", body = Page<Product>),
+        (status = 400, description = "Invalid list parameters", body = ErrorBody),
+    )
+)]
+async fn list_products<R: CatalogRepository>(
+    State(repo): State<Arc<R>>,
+    params: ListParams<ProductList>,
+) -> Result<Response, AppError> {
+    Ok(params.respond(repo.list_products(&params).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/products/{id}",
+    tag = "products",
+    params(
+        ("id" = ProductId, Path, description = "Product id"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag held by the client"),
+    ),
+    responses(
+        (status = 200, description = "The product", body = Product),
+        (status = 304, description = "Not modified"),
+        (status = 404, description = "Product not found", body = ErrorBody),
+    )
+)]
+async fn get_product<R: CatalogRepository>(
+    State(repo): State<Arc<R>>,
+    Path(id): Path<ProductId>,
+    preconditions: Preconditions,
+) -> Result<Conditional<Product>, AppError> {
+    Ok(preconditions.json(repo.get_product(id).await?))
+}
+
+// A product addressed through a category it does not belong to is a 404.
+#[utoipa::path(
+    get,
+    path = "/products/{id}/category/{id_category}",
+    tag = "products",
+    params(
+        ("id" = ProductId, Path, description = "Product id"),
+        ("id_category" = CategoryId, Path, description = "Category id"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag held by the client"),
+    ),
+    responses(
+        (status = 200, description = "The product", body = Product),
+        (status = 304, description = "Not modified"),
+        (status = 404, description = "Product not found in the category", body = ErrorBody),
+    )
+)]
+async fn get_product_in_category<R: CatalogRepository>(
+    State(repo): State<Arc<R>>,
+    Path((id, category_id)): Path<(ProductId, CategoryId)>,
+    preconditions: Preconditions,
+) -> Result<Conditional<Product>, AppError> {
+    let product = repo.get_product(id).await?;
+    if product.category_id != category_id {
+        return Err(product_not_found(id).into());
+    }
+    Ok(preconditions.json(product))
+}
+
+#[utoipa::path(
+    post,
+    path = "/products",
+    tag = "products",
+    request_body = NewProduct,
+    responses(
+        (status = 201, description = "Created", body = Product),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+        (status = 404, description = "Category not found", body = ErrorBody),
+    )
+)]
+async fn create_product<R: CatalogRepository>(
+    State(repo): State<Arc<R>>,
+    State(hub): State<Arc<Hub>>,
+    _user: AuthUser,
+    Json(new): Json<NewProduct>,
+) -> Result<impl IntoResponse, AppError> {
+    validate_product(&new).map_err(unprocessable)?;
+    let product = repo.create_product(new).await?;
+    announce(&hub, "products", "created", &product);
+    Ok(created(format!("/products/{}", product.id.0), product))
+}
+
+#[utoipa::path(
+    put,
+    path = "/products/{id}",
+    tag = "products",
+    params(
+        ("id" = ProductId, Path, description = "Product id"),
+        ("If-Match" = Option<String>, Header, description = "ETag the change is based on"),
+    ),
+    request_body = NewProduct,
+    responses(
+        (status = 200, description = "Updated", body = Product),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Product not found", body = ErrorBody),
+        (status = 412, description = "ETag does not match", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    )
+)]
+async fn update_product<R: CatalogRepository>(
+    State(repo): State<Arc<R>>,
+    State(hub): State<Arc<Hub>>,
+    _user: AuthUser,
+    Path(id): Path<ProductId>,
+    preconditions: Preconditions,
+    Json(new): Json<NewProduct>,
+) -> Result<Conditional<Product>, AppError> {
+    validate_product(&new).map_err(unprocessable)?;
+    let current = repo.get_product(id).await?;
+    preconditions.check(Some(&ETag::for_json(&current)))?;
+    let expected = preconditions.is_conditional().then_some(&current);
+    let product = repo.update_product(id, new, expected).await?;
+    announce(&hub, "products", "updated", &product);
+    Ok(preconditions.json(product))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/products/{id}",
+    tag = "products",
+    params(
+        ("id" = ProductId, Path, description = "Product id"),
+        ("If-Match" = Option<String>, Header, description = "ETag the change is based on"),
+    ),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Product not found", body = ErrorBody),
+        (status = 412, description = "ETag does not match", body = ErrorBody),
+    )
+)]
+async fn delete_product<R: CatalogRepository>(
+    State(repo): State<Arc<R>>,
+    State(hub): State<Arc<Hub>>,
+    _user: AuthUser,
+    Path(id): Path<ProductId>,
+    preconditions: Preconditions,
+) -> Result<StatusCode, AppError> {
+    let current = repo.get_product(id).await?;
+    preconditions.check(Some(&ETag::for_json(&current)))?;
+    let expected = preconditions.is_conditional().then_some(&current);
+    repo.delete_product(id, expected).await?;
+    announce(&hub, "products", "deleted", &current);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    list_categories,
+    get_category,
+    create_category,
+    update_category,
+    delete_category,
+    category_products,
+    list_products,
+    get_product,
+    get_product_in_category,
+    create_product,
+    update_product,
+    delete_product,
+))]
+pub struct CatalogApi;
+
+// Reads are public; writes need a signed-in user and are published to the
+// hub.
+pub struct CatalogState<R> {
+    pub repo: Arc<R>,
+    pub tokens: Arc<TokenService>,
+    pub hub: Arc<Hub>,
+}
+
+impl<R> Clone for CatalogState<R> {
+    fn clone(&self) -> Self {
+        Self {
+            repo: self.repo.clone(),
+            tokens: self.tokens.clone(),
+            hub: self.hub.clone(),
+        }
+    }
+}
+
+impl<R: CatalogRepository> FromRef<CatalogState<R>> for Arc<R> {
+    fn from_ref(state: &CatalogState<R>) -> Self {
+        state.repo.clone()
+    }
+}
+
+impl<R> FromRef<CatalogState<R>> for Arc<TokenService> {
+    fn from_ref(state: &CatalogState<R>) -> Self {
+        state.tokens.clone()
+    }
+}
+
+impl<R> FromRef<CatalogState<R>> for Arc<Hub> {
+    fn from_ref(state: &CatalogState<R>) -> Self {
+        state.hub.clone()
+    }
+}
+
+pub fn router<R: CatalogRepository>(state: CatalogState<R>) -> Router {
+    Router::new()
+        .route(
+            "/categories",
+            get(list_categories::<R>).post(create_category::<R>),
+        )
+        .route(
+            "/categories/{id}",
+            get(get_category::<R>)
+                .put(update_category::<R>)
+                .delete(delete_category::<R>),
+        )
+        .route("/categories/{id}/products", get(category_products::<R>))
+        .route(
+            "/products",
+            get(list_products::<R>).post(create_product::<R>),
+        )
+        .route(
+            "/products/{id}",
+            get(get_product::<R>)
+                .put(update_product::<R>)
+                .delete(delete_product::<R>),
+        )
+        .route(
+            "/products/{id}/category/{id_category}",
+            get(get_product_in_category::<R>),
+        )
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::hub::HubConfig;
+    use axum_test::TestServer;
+    use serde_json::json;
+
+    fn new_category(name: &str) -> NewCategory {
+        NewCategory {
+            name: name.to_string(),
+        }
+    }
+
+    fn new_product(category_id: CategoryId, name: &str) -> NewProduct {
+        NewProduct {
+            category_id,
+            name: name.to_string(),
+            price_cents: 1500,
+        }
+    }
+
+    // Shared contract both repositories must satisfy.
+    async fn exercise(repo: impl CatalogRepository) {
+        let books = repo.create_category(new_category("Books")).await.unwrap();
+        let games = repo.create_category(new_category("Games")).await.unwrap();
+        assert!(matches!(
+            repo.create_category(new_category("Books")).await,
+            Err(RepositoryError::Conflict(_))
+        ));
+        assert!(matches!(
+            repo.update_category(games.id, new_category("Books"), None).await,
+            Err(RepositoryError::Conflict(_))
+        ));
+
+        let rust = repo
+            .create_product(new_product(books.id, "Rust"))
+            .await
+            .unwrap();
+        let rust_game = repo
+            .create_product(new_product(games.id, "Rust"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            repo.create_product(new_product(books.id, "Rust")).await,
+            Err(RepositoryError::Conflict(_))
+        ));
+        assert!(matches!(
+            repo.create_product(new_product(CategoryId(999), "Go"))
+                .await,
+            Err(RepositoryError::NotFound(_))
+        ));
+        assert_eq!(
+            repo.get_categories(&[games.id, CategoryId(999), books.id])
+                .await
+                .unwrap(),
+            vec![books.clone(), games.clone()]
+        );
+        assert_eq!(
+            repo.products_in_categories(&[books.id], 10).await.unwrap(),
+            vec![rust.clone()]
+        );
+        assert_eq!(
+            repo.products_in_categories(&[books.id, games.id], 1)
+                .await
+                .unwrap(),
+            vec![rust.clone(), rust_game]
+        );
+
+        let list =
+            |query: String| ListParams::<ProductList>::parse(query.parse().unwrap()).unwrap();
+        let page = repo
+            .list_products(&list("/products".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(page.data.len(), 2);
+        assert_eq!(page.page.total, Some(2));
+        let page = repo
+            .list_products(&list(format!("/products?category_id={}", books.id.0)))
+            .await
+            .unwrap();
+        assert_eq!(page.data, vec![rust.clone()]);
+
+        let renamed = repo
+            .update_product(rust.id, new_product(books.id, "Rust in Action"), Some(&rust))
+            .await
+            .unwrap();
+        assert_eq!(repo.get_product(rust.id).await.unwrap(), renamed);
+
+        // Conditional writes against a row that has since changed are refused.
+        assert!(matches!(
+            repo.update_product(rust.id, new_product(books.id, "Rust"), Some(&rust))
+                .await,
+            Err(RepositoryError::Changed(_))
+        ));
+        assert!(matches!(
+            repo.delete_product(rust.id, Some(&rust)).await,
+            Err(RepositoryError::Changed(_))
+        ));
+        let stale = Category {
+            id: books.id,
+            name: "Old Books".to_string(),
+        };
+        assert!(matches!(
+            repo.delete_category(books.id, Some(&stale)).await,
+            Err(RepositoryError::Changed(_))
+        ));
+
+        assert!(matches!(
+            repo.delete_category(books.id, None).await,
+            Err(RepositoryError::Conflict(_))
+        ));
+        repo.delete_product(rust.id, Some(&renamed)).await.unwrap();
+        repo.delete_category(books.id, Some(&books)).await.unwrap();
+        assert!(matches!(
+            repo.get_category(books.id).await,
+            Err(RepositoryError::NotFound(_))
+        ));
+        assert!(matches!(
+            repo.delete_product(rust.id, None).await,
+            Err(RepositoryError::NotFound(_))
+        ));
+        assert_eq!(repo.list_categories().await.unwrap(), vec![games]);
+    }
+
+    // Both repositories must page, sort and filter identically.
+    async fn exercise_listing(repo: impl CatalogRepository) {
+        let books = repo.create_category(new_category("Books")).await.unwrap();
+        for (name, price_cents) in [("Go", 3000), ("Rust", 4500), ("Zig", 3000), ("C", 1000)] {
+            repo.create_product(NewProduct {
+                category_id: books.id,
+                name: name.to_string(),
+                price_cents,
+            })
+            .await
+            .unwrap();
+        }
+
+        let names = |page: &Page<Product>| -> Vec<String> {
+            page.data
+                .iter()
+                .map(|product| product.name.clone())
+                .collect()
+        };
+        let list = |query: &str| {
+            ListParams::<ProductList>::parse(format!("/products?{}", query).parse().unwrap())
+                .unwrap()
+        };
+
+        let page = repo
+            .list_products(&list("sort=-price_cents,name&limit=3&cursor="))
+            .await
+            .unwrap();
+        assert_eq!(names(&page), vec!["Rust", "Go", "Zig"]);
+        let cursor = page.page.next_cursor.unwrap();
+        let page = repo
+            .list_products(&list(&format!(
+                "sort=-price_cents,name&limit=3&cursor={}",
+                cursor
+            )))
+            .await
+            .unwrap();
+        assert_eq!(names(&page), vec!["C"]);
+        assert!(!page.page.has_more);
+
+        let page = repo
+            .list_products(&list("price_cents[gte]=3000&name[like]=o&sort=name"))
+            .await
+            .unwrap();
+        assert_eq!(names(&page), vec!["Go"]);
+
+        // Both backends ignore case.
+        let page = repo.list_products(&list("name[like]=rU")).await.unwrap();
+        assert_eq!(names(&page), vec!["Rust"]);
+
+        let page = repo
+            .list_products(&list("sort=name&limit=2&offset=2"))
+            .await
+            .unwrap();
+        assert_eq!(names(&page), vec!["Rust", "Zig"]);
+        assert_eq!(page.page.total, Some(4));
+    }
+
+    #[tokio::test]
+    async fn test_memory_catalog() {
+        exercise(MemoryCatalog::default()).await;
+        exercise_listing(MemoryCatalog::default()).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_catalog() {
+        exercise(SqliteCatalog::connect("sqlite::memory:").await.unwrap()).await;
+        exercise_listing(SqliteCatalog::connect("sqlite::memory:").await.unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn test_routes() {
+        let tokens = Arc::new(TokenService::new("secret", Duration::from_secs(60)));
+        let mut server = TestServer::new(router(CatalogState {
+            repo: Arc::new(MemoryCatalog::default()),
+            tokens: tokens.clone(),
+            hub: Arc::new(Hub::new(HubConfig::default())),
+        }))
+        .unwrap();
+
+        let response = server
+            .post("/categories")
+            .json(&json!({"name": "Books"}))
+            .await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        server.add_header(
+            header::AUTHORIZATION,
+            format!("Bearer {}", tokens.issue("hadi")),
+        );
+        let response = server
+            .post("/categories")
+            .json(&json!({"name": "Books"}))
+            .await;
+        response.assert_status(StatusCode::CREATED);
+        response.assert_header("Location", "/categories/1");
+
+        let response = server
+            .post("/products")
+            .json(&json!({"category_id": 1, "name": "Rust", "price_cents": 4500}))
+            .await;
+        response.assert_status(StatusCode::CREATED);
+        response.assert_json(&json!({
+            "id": 1,
+            "category_id": 1,
+            "name": "Rust",
+            "price_cents": 4500,
+        }));
+
+        let response = server.get("/products/1/category/1").await;
+        response.assert_status_ok();
+        let response = server.get("/products/1/category/7").await;
+        response.assert_status(StatusCode::NOT_FOUND);
+        response.assert_json(&json!({"code": 404, "message": "Product 1 not found"}));
+
+        let response = server.get("/categories/1/products").await;
+        response.assert_json(&json!({
+            "data": [{
+                "id": 1,
+                "category_id": 1,
+                "name": "Rust",
+                "price_cents": 4500,
+            }],
+            "page": {"limit": 20, "offset": 0, "total": 1, "has_more": false},
+        }));
+        response.assert_header("Link", "</categories/1/products?offset=0>; rel=\"first\"");
+
+        let response = server.delete("/categories/1").await;
+        response.assert_status(StatusCode::CONFLICT);
+        response.assert_json(&json!({"code": 409, "message": "Category 1 still has products"}));
+
+        let response = server
+            .put("/products/1")
+            .json(&json!({"category_id": 1, "name": "Rust", "price_cents": -1}))
+            .await;
+        response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+
+        // Updates and deletes with a stale ETag are refused.
+        let etag = server.get("/products/1").await.header("ETag");
+        let response = server
+            .put("/products/1")
+            .add_header("If-Match", etag.clone())
+            .json(&json!({"category_id": 1, "name": "Rust 2e", "price_cents": 5000}))
+            .await;
+        response.assert_status_ok();
+        server
+            .get("/products/1")
+            .add_header("If-None-Match", response.header("ETag"))
+            .await
+            .assert_status(StatusCode::NOT_MODIFIED);
+        server
+            .delete("/products/1")
+            .add_header("If-Match", etag)
+            .await
+            .assert_status(StatusCode::PRECONDITION_FAILED);
+
+        // Typed IDs reject non-numeric segments before reaching the handler.
+        server
+            .get("/products/abc")
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+
+        server
+            .delete("/products/1")
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        server
+            .get("/products/1")
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_conditional_updates() {
+        let tokens = Arc::new(TokenService::new("secret", Duration::from_secs(60)));
+        let repo = Arc::new(SqliteCatalog::connect("sqlite::memory:").await.unwrap());
+        let mut server = TestServer::new(router(CatalogState {
+            repo: repo.clone(),
+            tokens: tokens.clone(),
+            hub: Arc::new(Hub::new(HubConfig::default())),
+        }))
+        .unwrap();
+        server.add_header(
+            header::AUTHORIZATION,
+            format!("Bearer {}", tokens.issue("hadi")),
+        );
+
+        server
+            .post("/categories")
+            .json(&json!({"name": "Books"}))
+            .await
+            .assert_status(StatusCode::CREATED);
+        let etag = server.get("/categories/1").await.header("ETag");
+
+        // Both carry the same ETag; only one of them may win.
+        let update = |name: &str| {
+            server
+                .put("/categories/1")
+                .add_header("If-Match", etag.clone())
+                .json(&json!({ "name": name }))
+        };
+        let (first, second) = tokio::join!(update("Novels"), update("Comics"));
+        let mut statuses = [first.status_code(), second.status_code()];
+        statuses.sort();
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::PRECONDITION_FAILED]);
+
+        let winner = if first.status_code() == StatusCode::OK {
+            "Novels"
+        } else {
+            "Comics"
+        };
+        assert_eq!(repo.get_category(CategoryId(1)).await.unwrap().name, winner);
+    }
+}
@@ -0,0 +1,574 @@
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    routing::get,
+};
+use bytes::Bytes;
+use http::StatusCode;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits, imageops::FilterType};
+use serde::Serialize;
+use tokio::{sync::Semaphore, time::Instant};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    blob_store::BlobStore,
+    error::{AppError, ErrorBody},
+};
+
+#[derive(Debug, Clone)]
+pub struct ImageConfig {
+    pub min_width: u32,
+    pub min_height: u32,
+    pub max_width: u32,
+    pub max_height: u32,
+    // Square thumbnails, in pixels. Sizes larger than the source are skipped.
+    pub thumbnail_sizes: Vec<u32>,
+    // Images processed at the same time; decoding is CPU and memory heavy.
+    pub concurrency: usize,
+    // Images accepted but not finished yet, each held in memory. Uploads
+    // beyond it get a 503.
+    pub max_queued: usize,
+    pub link_ttl: Duration,
+    // How long the status of a finished job is kept around.
+    pub job_ttl: Duration,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            min_width: 64,
+            min_height: 64,
+            max_width: 8192,
+            max_height: 8192,
+            thumbnail_sizes: vec![64, 128, 256],
+            concurrency: 2,
+            max_queued: 16,
+            link_ttl: Duration::from_secs(60 * 60),
+            job_ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Variant {
+    pub name: String,
+    pub key: String,
+    pub width: u32,
+    pub height: u32,
+    pub content_type: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ProcessingStatus {
+    Pending,
+    Ready { variants: Vec<Variant> },
+    Failed { error: String },
+}
+
+struct Encoded {
+    variant: Variant,
+    data: Bytes,
+}
+
+fn reader<'a>(
+    data: &'a [u8],
+    config: &ImageConfig,
+) -> anyhow::Result<ImageReader<Cursor<&'a [u8]>>> {
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(config.max_width);
+    limits.max_image_height = Some(config.max_height);
+    reader.limits(limits);
+    Ok(reader)
+}
+
+fn check_dimensions(width: u32, height: u32, config: &ImageConfig) -> Result<(), String> {
+    if width < config.min_width || height < config.min_height {
+        return Err(format!(
+            "Image is {}x{}, at least {}x{} is required",
+            width, height, config.min_width, config.min_height
+        ));
+    }
+    if width > config.max_width || height > config.max_height {
+        return Err(format!(
+            "Image is {}x{}, at most {}x{} is allowed",
+            width, height, config.max_width, config.max_height
+        ));
+    }
+    Ok(())
+}
+
+// Reads only the header, so oversized images are rejected before decoding.
+// The error is a message suitable for a 422 response.
+pub fn validate(data: &[u8], config: &ImageConfig) -> Result<(), String> {
+    // No size limits here, so too-large images get a descriptive error.
+    let decoder = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(anyhow::Error::from)
+        .and_then(|reader| Ok(reader.into_decoder()?))
+        .map_err(|_| "Image could not be decoded".to_string())?;
+    let (width, height) = decoder.dimensions();
+    check_dimensions(width, height, config)
+}
+
+fn decode(data: &[u8], config: &ImageConfig) -> anyhow::Result<DynamicImage> {
+    let mut decoder = reader(data, config)?.into_decoder()?;
+    let (width, height) = decoder.dimensions();
+    check_dimensions(width, height, config).map_err(anyhow::Error::msg)?;
+
+    // Bake the EXIF orientation into the pixels, since the metadata is dropped.
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+fn encode(
+    image: &DynamicImage,
+    format: ImageFormat,
+    name: &str,
+    prefix: &str,
+) -> anyhow::Result<Encoded> {
+    // Re-encoding writes pixels only, which is what strips EXIF and other metadata.
+    let mut data = Cursor::new(Vec::new());
+    match format {
+        ImageFormat::Jpeg => {
+            DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut data, format)?
+        }
+        _ => image.write_to(&mut data, format)?,
+    }
+    let extension = format.extensions_str()[0];
+    Ok(Encoded {
+        variant: Variant {
+            name: name.to_string(),
+            key: format!("{}/{}.{}", prefix, name, extension),
+            width: image.width(),
+            height: image.height(),
+            content_type: format.to_mime_type().to_string(),
+        },
+        data: data.into_inner().into(),
+    })
+}
+
+fn process(data: &[u8], id: &str, config: &ImageConfig) -> anyhow::Result<Vec<Encoded>> {
+    let format = match ImageReader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .format()
+    {
+        Some(ImageFormat::Jpeg) => ImageFormat::Jpeg,
+        _ => ImageFormat::Png,
+    };
+    let image = decode(data, config)?;
+    let prefix = format!("profiles/{}", id);
+
+    let mut variants = vec![encode(&image, format, "original", &prefix)?];
+    let shortest = image.width().min(image.height());
+    for &size in config
+        .thumbnail_sizes
+        .iter()
+        .filter(|&&size| size <= shortest)
+    {
+        let thumbnail = image.resize_to_fill(size, size, FilterType::Lanczos3);
+        variants.push(encode(&thumbnail, format, &size.to_string(), &prefix)?);
+    }
+    Ok(variants)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SubmitError {
+    // Not an image, or outside the allowed dimensions.
+    Invalid(String),
+    QueueFull,
+}
+
+impl From<SubmitError> for AppError {
+    fn from(error: SubmitError) -> Self {
+        match error {
+            SubmitError::Invalid(message) => {
+                AppError::new(StatusCode::UNPROCESSABLE_ENTITY, message)
+            }
+            SubmitError::QueueFull => AppError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Too many images are waiting to be processed",
+            ),
+        }
+    }
+}
+
+struct Job {
+    status: ProcessingStatus,
+    // Set once the job is done; pending jobs never expire.
+    expires: Option<Instant>,
+}
+
+pub struct ImagePipeline {
+    config: ImageConfig,
+    store: Arc<dyn BlobStore>,
+    jobs: Mutex<HashMap<String, Job>>,
+    permits: Semaphore,
+    queued: AtomicUsize,
+}
+
+impl ImagePipeline {
+    pub fn new(config: ImageConfig, store: Arc<dyn BlobStore>) -> Self {
+        let permits = Semaphore::new(config.concurrency.max(1));
+        Self {
+            config,
+            store,
+            jobs: Mutex::new(HashMap::new()),
+            permits,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    // Locks the jobs with the expired ones already dropped.
+    fn jobs(&self) -> std::sync::MutexGuard<'_, HashMap<String, Job>> {
+        let now = Instant::now();
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|_, job| job.expires.is_none_or(|expires| expires > now));
+        jobs
+    }
+
+    pub fn status(&self, id: &str) -> Option<ProcessingStatus> {
+        self.jobs().get(id).map(|job| job.status.clone())
+    }
+
+    fn finish(&self, id: &str, status: ProcessingStatus) {
+        let expires = Some(Instant::now() + self.config.job_ttl);
+        self.jobs().insert(id.to_string(), Job { status, expires });
+    }
+
+    // Validates synchronously and processes in the background. `id` should be
+    // the content hash, so re-uploading the same image reuses the earlier job.
+    pub fn submit(self: &Arc<Self>, id: &str, data: Bytes) -> Result<(), SubmitError> {
+        validate(&data, &self.config).map_err(SubmitError::Invalid)?;
+
+        {
+            let mut jobs = self.jobs();
+            if matches!(
+                jobs.get(id).map(|job| &job.status),
+                Some(ProcessingStatus::Pending | ProcessingStatus::Ready { .. })
+            ) {
+                return Ok(());
+            }
+            if self.queued.fetch_add(1, Ordering::Relaxed) >= self.config.max_queued {
+                self.queued.fetch_sub(1, Ordering::Relaxed);
+                return Err(SubmitError::QueueFull);
+            }
+            jobs.insert(
+                id.to_string(),
+                Job {
+                    status: ProcessingStatus::Pending,
+                    expires: None,
+                },
+            );
+        }
+
+        let pipeline = self.clone();
+        let id = id.to_string();
+        tokio::spawn(async move {
+            let status = match pipeline.run(&id, data).await {
+                Ok(variants) => ProcessingStatus::Ready { variants },
+                Err(err) => {
+                    tracing::warn!("processing image {} failed: {:#}", id, err);
+                    ProcessingStatus::Failed {
+                        error: "Image could not be processed".to_string(),
+                    }
+                }
+            };
+            pipeline.finish(&id, status);
+            pipeline.queued.fetch_sub(1, Ordering::Relaxed);
+        });
+        Ok(())
+    }
+
+    async fn run(&self, id: &str, data: Bytes) -> anyhow::Result<Vec<Variant>> {
+        let _permit = self.permits.acquire().await?;
+
+        let config = self.config.clone();
+        let job_id = id.to_string();
+        let encoded =
+            tokio::task::spawn_blocking(move || process(&data, &job_id, &config)).await??;
+
+        let mut variants = Vec::with_capacity(encoded.len());
+        for Encoded { variant, data } in encoded {
+            if let Err(err) = self
+                .store
+                .put(&variant.key, data, &variant.content_type)
+                .await
+            {
+                self.discard(id).await;
+                return Err(err);
+            }
+            variants.push(variant);
+        }
+        Ok(variants)
+    }
+
+    // Removes what a failed job managed to store, so no partial set of
+    // variants is left behind.
+    async fn discard(&self, id: &str) {
+        let Ok(blobs) = self.store.list(&format!("profiles/{}/", id)).await else {
+            return;
+        };
+        for blob in blobs {
+            let _ = self.store.delete(&blob.key).await;
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct VariantResponse {
+    #[serde(flatten)]
+    pub variant: Variant,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum StatusResponse {
+    Pending,
+    Ready { variants: Vec<VariantResponse> },
+    Failed { error: String },
+}
+
+#[utoipa::path(
+    get,
+    path = "/profile/images/{id}",
+    tag = "files",
+    params(("id" = String, Path, description = "SHA-256 of the uploaded image")),
+    responses(
+        (status = 200, description = "Processing status and, once ready, signed variant links", body = StatusResponse),
+        (status = 404, description = "Image not found", body = ErrorBody),
+    )
+)]
+async fn image_status(
+    State(pipeline): State<Arc<ImagePipeline>>,
+    Path(id): Path<String>,
+) -> Result<Json<StatusResponse>, AppError> {
+    let status = pipeline
+        .status(&id)
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "Image not found"))?;
+
+    Ok(Json(match status {
+        ProcessingStatus::Pending => StatusResponse::Pending,
+        ProcessingStatus::Failed { error } => StatusResponse::Failed { error },
+        ProcessingStatus::Ready { variants } => {
+            let mut responses = Vec::with_capacity(variants.len());
+            for variant in variants {
+                // Links are signed per request since they expire.
+                let url = pipeline
+                    .store
+                    .presign_get(&variant.key, pipeline.config.link_ttl)
+                    .await
+                    .map_err(AppError::internal)?;
+                responses.push(VariantResponse { variant, url });
+            }
+            StatusResponse::Ready {
+                variants: responses,
+            }
+        }
+    }))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(image_status))]
+pub struct ProfileImageApi;
+
+pub fn router(pipeline: Arc<ImagePipeline>) -> Router {
+    Router::new()
+        .route("/profile/images/{id}", get(image_status))
+        .with_state(pipeline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blob_store::{LocalBlobStore, UrlSigner};
+    use axum_test::TestServer;
+    use image::{Rgb, RgbImage};
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbImage::from_fn(width, height, |x, _| Rgb([(x % 256) as u8, 80, 160]));
+        let mut data = Cursor::new(Vec::new());
+        image.write_to(&mut data, ImageFormat::Jpeg).unwrap();
+        data.into_inner()
+    }
+
+    // Inserts an APP1 segment with a single Orientation tag (6 = rotate 90° CW)
+    // and a camera model, right after the SOI marker.
+    fn with_exif(jpeg: &[u8]) -> Vec<u8> {
+        let mut tiff = b"MM\x00\x2a\x00\x00\x00\x08\x00\x02".to_vec();
+        tiff.extend_from_slice(b"\x01\x12\x00\x03\x00\x00\x00\x01\x00\x06\x00\x00");
+        tiff.extend_from_slice(b"\x01\x10\x00\x02\x00\x00\x00\x04HADI");
+        tiff.extend_from_slice(b"\x00\x00\x00\x00");
+        let mut segment = b"Exif\x00\x00".to_vec();
+        segment.extend_from_slice(&tiff);
+
+        let mut data = jpeg[..2].to_vec();
+        data.extend_from_slice(&[0xFF, 0xE1]);
+        data.extend_from_slice(&((segment.len() + 2) as u16).to_be_bytes());
+        data.extend_from_slice(&segment);
+        data.extend_from_slice(&jpeg[2..]);
+        data
+    }
+
+    fn pipeline(dir: &std::path::Path) -> Arc<ImagePipeline> {
+        let store = LocalBlobStore::new(dir, "", Arc::new(UrlSigner::new("secret")));
+        Arc::new(ImagePipeline::new(ImageConfig::default(), Arc::new(store)))
+    }
+
+    async fn wait_until_done(pipeline: &ImagePipeline, id: &str) -> ProcessingStatus {
+        for _ in 0..200 {
+            match pipeline.status(id) {
+                Some(ProcessingStatus::Pending) => {
+                    tokio::time::sleep(Duration::from_millis(10)).await
+                }
+                Some(status) => return status,
+                None => panic!("unknown job {}", id),
+            }
+        }
+        panic!("job {} did not finish", id)
+    }
+
+    #[test]
+    fn test_validate_dimensions() {
+        let config = ImageConfig::default();
+        assert!(validate(&jpeg(200, 100), &config).is_ok());
+
+        assert_eq!(
+            validate(&jpeg(32, 100), &config).unwrap_err(),
+            "Image is 32x100, at least 64x64 is required"
+        );
+
+        let config = ImageConfig {
+            max_width: 150,
+            ..ImageConfig::default()
+        };
+        assert_eq!(
+            validate(&jpeg(200, 100), &config).unwrap_err(),
+            "Image is 200x100, at most 150x8192 is allowed"
+        );
+        assert!(validate(b"not an image", &config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_process_strips_exif_and_resizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let pipeline = pipeline(dir.path());
+        let data = with_exif(&jpeg(200, 100));
+        assert!(data.windows(4).any(|window| window == b"HADI"));
+
+        pipeline.submit("abc", data.into()).unwrap();
+        let ProcessingStatus::Ready { variants } = wait_until_done(&pipeline, "abc").await else {
+            panic!("processing failed");
+        };
+
+        let sizes: Vec<_> = variants
+            .iter()
+            .map(|variant| (variant.name.as_str(), variant.width, variant.height))
+            .collect();
+        // Rotated by the orientation tag; 128 and 256 don't fit in 100px.
+        assert_eq!(sizes, vec![("original", 100, 200), ("64", 64, 64)]);
+
+        let original = std::fs::read(dir.path().join("profiles/abc/original.jpg")).unwrap();
+        assert!(!original.windows(4).any(|window| window == b"Exif"));
+        assert!(!original.windows(4).any(|window| window == b"HADI"));
+        assert!(dir.path().join("profiles/abc/64.jpg").exists());
+    }
+
+    #[tokio::test]
+    async fn test_status_endpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let pipeline = pipeline(dir.path());
+        let server = TestServer::new(router(pipeline.clone())).unwrap();
+
+        server
+            .get("/profile/images/missing")
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+
+        pipeline.submit("abc", jpeg(128, 128).into()).unwrap();
+        wait_until_done(&pipeline, "abc").await;
+
+        let response = server.get("/profile/images/abc").await;
+        response.assert_status_ok();
+        let body = response.json::<serde_json::Value>();
+        assert_eq!(body["status"], "ready");
+        assert_eq!(body["variants"][1]["name"], "64");
+        assert_eq!(body["variants"][1]["key"], "profiles/abc/64.jpg");
+        assert!(
+            body["variants"][1]["url"]
+                .as_str()
+                .unwrap()
+                .starts_with("/files/profiles/abc/64.jpg?expires=")
+        );
+    }
+    #[tokio::test]
+    async fn test_failed_job_discards_variants() {
+        let dir = tempfile::tempdir().unwrap();
+        let pipeline = pipeline(dir.path());
+        // A directory in the way of the thumbnail makes storing it fail.
+        std::fs::create_dir_all(dir.path().join("profiles/abc/64.jpg")).unwrap();
+
+        pipeline.submit("abc", jpeg(128, 128).into()).unwrap();
+        let status = wait_until_done(&pipeline, "abc").await;
+        // The storage error stays in the logs.
+        assert_eq!(
+            status,
+            ProcessingStatus::Failed {
+                error: "Image could not be processed".to_string()
+            }
+        );
+        assert!(!dir.path().join("profiles/abc/original.jpg").exists());
+    }
+    #[tokio::test]
+    async fn test_queue_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalBlobStore::new(dir.path(), "", Arc::new(UrlSigner::new("secret")));
+        let config = ImageConfig {
+            max_queued: 1,
+            ..ImageConfig::default()
+        };
+        let pipeline = Arc::new(ImagePipeline::new(config, Arc::new(store)));
+
+        pipeline.submit("a", jpeg(128, 128).into()).unwrap();
+        // The same image joins the queued job.
+        pipeline.submit("a", jpeg(128, 128).into()).unwrap();
+        assert_eq!(
+            pipeline.submit("b", jpeg(128, 128).into()),
+            Err(SubmitError::QueueFull)
+        );
+        assert!(pipeline.status("b").is_none());
+
+        wait_until_done(&pipeline, "a").await;
+        pipeline.submit("b", jpeg(128, 128).into()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_finished_jobs_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let pipeline = pipeline(dir.path());
+
+        pipeline.submit("abc", jpeg(128, 128).into()).unwrap();
+        wait_until_done(&pipeline, "abc").await;
+
+        tokio::time::pause();
+        tokio::time::advance(Duration::from_secs(24 * 60 * 60 - 1)).await;
+        assert!(pipeline.status("abc").is_some());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(pipeline.status("abc").is_none());
+        assert!(pipeline.jobs.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,828 @@
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{HeaderMap, HeaderValue, Method, request::Parts};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+    sync::Semaphore,
+};
+
+use crate::{
+    auth::{AuthUser, TokenService},
+    client_ip::ClientIp,
+    error::AppError,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub limit: u32,
+    pub period: Duration,
+}
+
+impl Quota {
+    pub fn per_second(limit: u32) -> Self {
+        Self {
+            limit,
+            period: Duration::from_secs(1),
+        }
+    }
+
+    pub fn per_minute(limit: u32) -> Self {
+        Self {
+            limit,
+            period: Duration::from_secs(60),
+        }
+    }
+
+    fn emission_interval_ms(&self) -> u64 {
+        (self.period.as_millis() as u64 / self.limit.max(1) as u64).max(1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyBy {
+    Ip,
+    // `X-API-Key` header when it is one of `RateLimitConfig::api_keys`,
+    // falling back to the client IP.
+    ApiKey,
+    // Authenticated username, falling back to the client IP.
+    User,
+}
+
+#[derive(Debug, Clone)]
+pub struct RouteQuota {
+    pub path_prefix: String,
+    pub method: Option<Method>,
+    pub quota: Quota,
+    pub key: KeyBy,
+}
+
+impl RouteQuota {
+    pub fn new(path_prefix: &str, quota: Quota) -> Self {
+        Self {
+            path_prefix: path_prefix.to_string(),
+            method: None,
+            quota,
+            key: KeyBy::Ip,
+        }
+    }
+
+    pub fn method(mut self, method: Method) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        self.method.as_ref().is_none_or(|m| m == method) && path.starts_with(&self.path_prefix)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub default: Quota,
+    pub default_key: KeyBy,
+    // Checked in order, the first match wins.
+    pub routes: Vec<RouteQuota>,
+    // Known API keys; made-up ones would otherwise each get a fresh bucket.
+    pub api_keys: HashSet<String>,
+    // Redis-compatible server shared by all instances; counters are kept in
+    // memory when unset.
+    pub redis_address: Option<String>,
+    // Longest a Redis command may take, waiting for a connection included,
+    // before the request goes through unlimited.
+    pub redis_timeout: Duration,
+    pub redis_connections: usize,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            default: Quota::per_second(50),
+            default_key: KeyBy::Ip,
+            routes: vec![
+                RouteQuota::new("/login", Quota::per_minute(20)).method(Method::POST),
+                RouteQuota::new("/axum_rs.auth.v1.Auth/Login", Quota::per_minute(20)),
+            ],
+            api_keys: HashSet::new(),
+            redis_address: None,
+            redis_timeout: Duration::from_millis(250),
+            redis_connections: 8,
+        }
+    }
+}
+
+// Counter store holding the GCRA "theoretical arrival time" per key, in
+// milliseconds since the unix epoch.
+pub trait RateLimitStore: Send + Sync + 'static {
+    fn get(&self, key: &str) -> impl Future<Output = anyhow::Result<Option<u64>>> + Send;
+
+    fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<u64>,
+        value: u64,
+        ttl: Duration,
+    ) -> impl Future<Output = anyhow::Result<bool>> + Send;
+}
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+struct MemoryEntries {
+    values: HashMap<String, (u64, SystemTime)>,
+    next_sweep: SystemTime,
+}
+
+// Expired keys are dropped when read and, for keys that are never read
+// again, by a sweep on the first write after `sweep_interval`.
+pub struct MemoryStore {
+    entries: Mutex<MemoryEntries>,
+    sweep_interval: Duration,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self {
+            entries: Mutex::new(MemoryEntries {
+                values: HashMap::new(),
+                next_sweep: SystemTime::now() + SWEEP_INTERVAL,
+            }),
+            sweep_interval: SWEEP_INTERVAL,
+        }
+    }
+}
+
+impl RateLimitStore for MemoryStore {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<u64>> {
+        let entries = &mut self.entries.lock().unwrap().values;
+        match entries.get(key) {
+            Some((_, expires)) if *expires <= SystemTime::now() => {
+                entries.remove(key);
+                Ok(None)
+            }
+            entry => Ok(entry.map(|(value, _)| *value)),
+        }
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<u64>,
+        value: u64,
+        ttl: Duration,
+    ) -> anyhow::Result<bool> {
+        let mut state = self.entries.lock().unwrap();
+        let now = SystemTime::now();
+        if now >= state.next_sweep {
+            state.values.retain(|_, (_, expires)| *expires > now);
+            state.next_sweep = now + self.sweep_interval;
+        }
+        let entries = &mut state.values;
+        let current = entries
+            .get(key)
+            .filter(|(_, expires)| *expires > now)
+            .map(|(value, _)| *value);
+        if current != expected {
+            return Ok(false);
+        }
+        entries.insert(key.to_string(), (value, now + ttl));
+        Ok(true)
+    }
+}
+
+const CAS_SCRIPT: &str = "local current = redis.call('GET', KEYS[1]) \
+if (current == false and ARGV[1] == '') or current == ARGV[1] then \
+redis.call('SET', KEYS[1], ARGV[2], 'PX', ARGV[3]) return 1 end return 0";
+
+#[derive(Debug, PartialEq)]
+enum Reply {
+    Simple(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+}
+
+// Minimal RESP client, enough for GET and an EVAL based compare-and-set,
+// over a small pool of connections.
+pub struct RedisStore {
+    address: String,
+    prefix: String,
+    timeout: Duration,
+    // One permit per connection that may be open at a time.
+    slots: Semaphore,
+    idle: Mutex<Vec<BufStream<TcpStream>>>,
+}
+
+impl RedisStore {
+    pub fn new(address: &str, prefix: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            prefix: prefix.to_string(),
+            timeout: Duration::from_millis(250),
+            slots: Semaphore::new(8),
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Longest a command may take, waiting for and opening a connection
+    // included, before the request goes through unlimited.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_connections(mut self, connections: usize) -> Self {
+        self.slots = Semaphore::new(connections.max(1));
+        self
+    }
+
+    // A connection is only put back once a reply has been read in full. A
+    // command that fails, times out or is dropped takes it along, so a late
+    // reply can never be read as the answer to the next command.
+    async fn command(&self, args: &[&[u8]]) -> anyhow::Result<Reply> {
+        let exchange = async {
+            let _slot = self.slots.acquire().await?;
+            let idle = self.idle.lock().unwrap().pop();
+            let mut stream = match idle {
+                Some(stream) => stream,
+                None => BufStream::new(TcpStream::connect(&self.address).await?),
+            };
+            let mut buffer = format!("*{}\r\n", args.len()).into_bytes();
+            for arg in args {
+                buffer.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+                buffer.extend_from_slice(arg);
+                buffer.extend_from_slice(b"\r\n");
+            }
+            stream.write_all(&buffer).await?;
+            stream.flush().await?;
+            let reply = read_reply(&mut stream).await?;
+            self.idle.lock().unwrap().push(stream);
+            anyhow::Ok(reply)
+        };
+
+        tokio::time::timeout(self.timeout, exchange)
+            .await
+            .map_err(|_| anyhow::anyhow!("redis command timed out"))?
+    }
+}
+
+async fn read_reply(stream: &mut BufStream<TcpStream>) -> anyhow::Result<Reply> {
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+    let line = line.trim_end();
+    let (kind, rest) = line.split_at(1.min(line.len()));
+
+    match kind {
+        "+" => Ok(Reply::Simple(rest.to_string())),
+        ":" => Ok(Reply::Integer(rest.parse()?)),
+        "-" => Err(anyhow::anyhow!("redis error: {}", rest)),
+        "$" => {
+            let length: i64 = rest.parse()?;
+            if length < 0 {
+                return Ok(Reply::Bulk(None));
+            }
+            let mut data = vec![0; length as usize + 2];
+            stream.read_exact(&mut data).await?;
+            data.truncate(length as usize);
+            Ok(Reply::Bulk(Some(data)))
+        }
+        _ => Err(anyhow::anyhow!("unexpected redis reply: {}", line)),
+    }
+}
+
+impl RateLimitStore for RedisStore {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<u64>> {
+        let key = format!("{}{}", self.prefix, key);
+        match self.command(&[b"GET", key.as_bytes()]).await? {
+            Reply::Bulk(Some(value)) => Ok(Some(String::from_utf8(value)?.parse()?)),
+            Reply::Bulk(None) => Ok(None),
+            reply => Err(anyhow::anyhow!("unexpected GET reply: {:?}", reply)),
+        }
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<u64>,
+        value: u64,
+        ttl: Duration,
+    ) -> anyhow::Result<bool> {
+        let key = format!("{}{}", self.prefix, key);
+        let expected = expected.map(|value| value.to_string()).unwrap_or_default();
+        let value = value.to_string();
+        let ttl = ttl.as_millis().max(1).to_string();
+
+        let reply = self
+            .command(&[
+                b"EVAL",
+                CAS_SCRIPT.as_bytes(),
+                b"1",
+                key.as_bytes(),
+                expected.as_bytes(),
+                value.as_bytes(),
+                ttl.as_bytes(),
+            ])
+            .await?;
+        Ok(reply == Reply::Integer(1))
+    }
+}
+
+// The store `RateLimitConfig::redis_address` asks for.
+pub enum Store {
+    Memory(MemoryStore),
+    Redis(RedisStore),
+}
+
+impl Store {
+    pub fn from_config(config: &RateLimitConfig) -> Self {
+        match &config.redis_address {
+            Some(address) => Self::Redis(
+                RedisStore::new(address, "ratelimit:")
+                    .with_timeout(config.redis_timeout)
+                    .with_connections(config.redis_connections),
+            ),
+            None => Self::Memory(MemoryStore::default()),
+        }
+    }
+}
+
+impl RateLimitStore for Store {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<u64>> {
+        match self {
+            Self::Memory(store) => store.get(key).await,
+            Self::Redis(store) => store.get(key).await,
+        }
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<u64>,
+        value: u64,
+        ttl: Duration,
+    ) -> anyhow::Result<bool> {
+        match self {
+            Self::Memory(store) => store.compare_and_set(key, expected, value, ttl).await,
+            Self::Redis(store) => store.compare_and_set(key, expected, value, ttl).await,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    // Seconds until the bucket is full again.
+    pub reset: u64,
+    pub retry_after: u64,
+}
+
+pub struct RateLimiter<S> {
+    config: RateLimitConfig,
+    store: S,
+    tokens: Option<Arc<TokenService>>,
+}
+
+impl<S: RateLimitStore> RateLimiter<S> {
+    pub fn new(config: RateLimitConfig, store: S) -> Self {
+        Self {
+            config,
+            store,
+            tokens: None,
+        }
+    }
+
+    // Needed to key by user; without it `KeyBy::User` falls back to the IP.
+    pub fn with_tokens(mut self, tokens: Arc<TokenService>) -> Self {
+        self.tokens = Some(tokens);
+        self
+    }
+
+    fn resolve(&self, parts: &Parts) -> (String, Quota) {
+        let (id, quota, key_by) = self
+            .config
+            .routes
+            .iter()
+            .find(|route| route.matches(&parts.method, parts.uri.path()))
+            .map(|route| (route.path_prefix.as_str(), route.quota, route.key))
+            .unwrap_or(("*", self.config.default, self.config.default_key));
+
+        let ip = || format!("ip:{}", ClientIp::from_parts(parts).0);
+        let key = match key_by {
+            KeyBy::Ip => ip(),
+            KeyBy::ApiKey => parts
+                .headers
+                .get("X-API-Key")
+                .and_then(|value| value.to_str().ok())
+                .filter(|value| self.config.api_keys.contains(*value))
+                .map(|value| format!("key:{}", value))
+                .unwrap_or_else(ip),
+            KeyBy::User => AuthUser::bearer_token(parts)
+                .zip(self.tokens.as_ref())
+                .and_then(|(token, tokens)| tokens.verify(token))
+                .map(|claims| format!("user:{}", claims.sub))
+                .unwrap_or_else(ip),
+        };
+
+        (format!("{}|{}", id, key), quota)
+    }
+
+    pub async fn check(&self, key: &str, quota: Quota) -> anyhow::Result<Decision> {
+        let interval = quota.emission_interval_ms();
+        let burst = interval * quota.limit as u64;
+
+        loop {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+            let stored = self.store.get(key).await?;
+            let tat = stored.unwrap_or(now).max(now);
+            let new_tat = tat + interval;
+            let allow_at = new_tat.saturating_sub(burst);
+
+            if now < allow_at {
+                let remaining_ms = tat - now;
+                return Ok(Decision {
+                    allowed: false,
+                    limit: quota.limit,
+                    remaining: 0,
+                    reset: remaining_ms.div_ceil(1000),
+                    retry_after: (allow_at - now).div_ceil(1000),
+                });
+            }
+
+            let ttl = Duration::from_millis(new_tat - now);
+            if self
+                .store
+                .compare_and_set(key, stored, new_tat, ttl)
+                .await?
+            {
+                return Ok(Decision {
+                    allowed: true,
+                    limit: quota.limit,
+                    remaining: ((now + burst - new_tat) / interval) as u32,
+                    reset: (new_tat - now).div_ceil(1000),
+                    retry_after: 0,
+                });
+            }
+        }
+    }
+}
+
+fn insert_headers(headers: &mut HeaderMap, decision: &Decision) {
+    headers.insert("RateLimit-Limit", HeaderValue::from(decision.limit));
+    headers.insert("RateLimit-Remaining", HeaderValue::from(decision.remaining));
+    headers.insert("RateLimit-Reset", HeaderValue::from(decision.reset));
+}
+
+pub async fn rate_limit<S: RateLimitStore>(
+    State(limiter): State<Arc<RateLimiter<S>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    let (key, quota) = limiter.resolve(&parts);
+
+    let decision = match limiter.check(&key, quota).await {
+        Ok(decision) => decision,
+        Err(err) => {
+            // Fail open: an unavailable store must not take the API down.
+            tracing::warn!("rate limit store error: {}", err);
+            return next.run(Request::from_parts(parts, body)).await;
+        }
+    };
+
+    let mut response = if decision.allowed {
+        next.run(Request::from_parts(parts, body)).await
+    } else {
+        AppError::too_many_requests(decision.retry_after).into_response()
+    };
+    insert_headers(response.headers_mut(), &decision);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Router,
+        middleware::from_fn_with_state,
+        routing::{get, post},
+    };
+    use axum_test::TestServer;
+    use http::StatusCode;
+    use tokio::{io::BufReader, net::TcpListener};
+
+    fn config() -> RateLimitConfig {
+        RateLimitConfig {
+            default: Quota::per_minute(3),
+            default_key: KeyBy::Ip,
+            routes: vec![
+                RouteQuota::new("/login", Quota::per_minute(1)).method(Method::POST),
+                RouteQuota {
+                    key: KeyBy::ApiKey,
+                    ..RouteQuota::new("/api", Quota::per_minute(2))
+                },
+            ],
+            api_keys: HashSet::from(["a".to_string(), "b".to_string()]),
+            redis_address: None,
+            redis_timeout: Duration::from_millis(250),
+            redis_connections: 8,
+        }
+    }
+
+    fn app<S: RateLimitStore>(limiter: RateLimiter<S>) -> Router {
+        async fn route(method: Method) -> String {
+            format!("Hello, {}", method)
+        }
+
+        Router::new()
+            .route("/", get(route))
+            .route("/login", post(route))
+            .route("/api/users", get(route))
+            .layer(from_fn_with_state(Arc::new(limiter), rate_limit::<S>))
+    }
+
+    #[tokio::test]
+    async fn test_default_quota_headers() {
+        let server =
+            TestServer::new(app(RateLimiter::new(config(), MemoryStore::default()))).unwrap();
+
+        for remaining in ["2", "1", "0"] {
+            let response = server.get("/").await;
+            response.assert_status_ok();
+            response.assert_header("RateLimit-Limit", "3");
+            response.assert_header("RateLimit-Remaining", remaining);
+        }
+
+        let response = server.get("/").await;
+        response.assert_status(StatusCode::TOO_MANY_REQUESTS);
+        response.assert_header("Retry-After", "20");
+        response.assert_header("RateLimit-Remaining", "0");
+    }
+
+    #[tokio::test]
+    async fn test_route_quota_and_keys() {
+        let server =
+            TestServer::new(app(RateLimiter::new(config(), MemoryStore::default()))).unwrap();
+
+        server.post("/login").await.assert_status_ok();
+        server
+            .post("/login")
+            .await
+            .assert_status(StatusCode::TOO_MANY_REQUESTS);
+        server
+            .post("/login")
+            .add_header("X-Forwarded-For", "10.0.0.2")
+            .await
+            .assert_status_ok();
+
+        for _ in 0..2 {
+            server
+                .get("/api/users")
+                .add_header("X-API-Key", "a")
+                .await
+                .assert_status_ok();
+        }
+        server
+            .get("/api/users")
+            .add_header("X-API-Key", "a")
+            .await
+            .assert_status(StatusCode::TOO_MANY_REQUESTS);
+        server
+            .get("/api/users")
+            .add_header("X-API-Key", "b")
+            .await
+            .assert_status_ok();
+
+        // Unknown keys share the caller's IP bucket.
+        for key in ["x", "y"] {
+            server
+                .get("/api/users")
+                .add_header("X-API-Key", key)
+                .await
+                .assert_status_ok();
+        }
+        server
+            .get("/api/users")
+            .add_header("X-API-Key", "z")
+            .await
+            .assert_status(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_key_by_user() {
+        let tokens = Arc::new(TokenService::new("secret", Duration::from_secs(60)));
+        let config = RateLimitConfig {
+            default: Quota::per_minute(1),
+            default_key: KeyBy::User,
+            routes: Vec::new(),
+            ..RateLimitConfig::default()
+        };
+        let limiter = RateLimiter::new(config, MemoryStore::default()).with_tokens(tokens.clone());
+        let server = TestServer::new(app(limiter)).unwrap();
+
+        let hadi = tokens.issue("hadi");
+        server
+            .get("/")
+            .authorization_bearer(&hadi)
+            .await
+            .assert_status_ok();
+        server
+            .get("/")
+            .authorization_bearer(&hadi)
+            .await
+            .assert_status(StatusCode::TOO_MANY_REQUESTS);
+        server
+            .get("/")
+            .authorization_bearer(tokens.issue("budi"))
+            .await
+            .assert_status_ok();
+    }
+
+    // Stand-in for a Redis server that understands the commands RedisStore
+    // sends. The first `hung` connections never get a reply.
+    async fn redis_stand_in(hung: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let data = Arc::new(Mutex::new(HashMap::<Vec<u8>, Vec<u8>>::new()));
+
+        tokio::spawn(async move {
+            let mut remaining = hung;
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let data = data.clone();
+                let hung = remaining > 0;
+                remaining = remaining.saturating_sub(1);
+                tokio::spawn(async move {
+                    let mut socket = BufReader::new(socket);
+                    if hung {
+                        let _ = socket.read_to_end(&mut Vec::new()).await;
+                        return;
+                    }
+                    loop {
+                        let mut line = String::new();
+                        if socket.read_line(&mut line).await.unwrap_or(0) == 0 {
+                            return;
+                        }
+                        let count: usize = line.trim()[1..].parse().unwrap();
+                        let mut args = Vec::new();
+                        for _ in 0..count {
+                            let mut header = String::new();
+                            socket.read_line(&mut header).await.unwrap();
+                            let length: usize = header.trim()[1..].parse().unwrap();
+                            let mut arg = vec![0; length + 2];
+                            socket.read_exact(&mut arg).await.unwrap();
+                            arg.truncate(length);
+                            args.push(arg);
+                        }
+
+                        let reply = {
+                            let mut data = data.lock().unwrap();
+                            match args[0].as_slice() {
+                                b"GET" => match data.get(&args[1]) {
+                                    Some(value) => {
+                                        let mut reply =
+                                            format!("${}\r\n", value.len()).into_bytes();
+                                        reply.extend_from_slice(value);
+                                        reply.extend_from_slice(b"\r\n");
+                                        reply
+                                    }
+                                    None => b"$-1\r\n".to_vec(),
+                                },
+                                b"EVAL" => {
+                                    let (key, expected, value) = (&args[3], &args[4], &args[5]);
+                                    let matches = match data.get(key) {
+                                        Some(current) => current == expected,
+                                        None => expected.is_empty(),
+                                    };
+                                    if matches {
+                                        data.insert(key.clone(), value.clone());
+                                        b":1\r\n".to_vec()
+                                    } else {
+                                        b":0\r\n".to_vec()
+                                    }
+                                }
+                                _ => b"-ERR unknown command\r\n".to_vec(),
+                            }
+                        };
+                        socket.get_mut().write_all(&reply).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        address
+    }
+
+    #[tokio::test]
+    async fn test_redis_store() {
+        let address = redis_stand_in(0).await;
+        let store = RedisStore::new(&address, "ratelimit:");
+
+        assert_eq!(store.get("a").await.unwrap(), None);
+        assert!(
+            store
+                .compare_and_set("a", None, 10, Duration::from_secs(1))
+                .await
+                .unwrap()
+        );
+        assert!(
+            !store
+                .compare_and_set("a", None, 20, Duration::from_secs(1))
+                .await
+                .unwrap()
+        );
+        assert!(
+            store
+                .compare_and_set("a", Some(10), 20, Duration::from_secs(1))
+                .await
+                .unwrap()
+        );
+        assert_eq!(store.get("a").await.unwrap(), Some(20));
+
+        let server = TestServer::new(app(RateLimiter::new(config(), store))).unwrap();
+        server.post("/login").await.assert_status_ok();
+        server
+            .post("/login")
+            .await
+            .assert_status(StatusCode::TOO_MANY_REQUESTS);
+    }
+    #[tokio::test]
+    async fn test_redis_timeout_fails_open() {
+        let address = redis_stand_in(1).await;
+        let store = RedisStore::new(&address, "ratelimit:").with_timeout(Duration::from_millis(50));
+
+        assert!(store.get("a").await.is_err());
+        // The unanswered connection is gone; the next command uses a new one.
+        assert_eq!(store.get("a").await.unwrap(), None);
+
+        let config = RateLimitConfig {
+            redis_address: Some(redis_stand_in(1).await),
+            redis_timeout: Duration::from_millis(50),
+            ..config()
+        };
+        let store = Store::from_config(&config);
+        let server = TestServer::new(app(RateLimiter::new(config, store))).unwrap();
+        let response = server.get("/").await;
+        response.assert_status_ok();
+        assert!(response.maybe_header("RateLimit-Limit").is_none());
+    }
+
+    // Callers waiting for a connection give up after the timeout too, so an
+    // unresponsive Redis delays each request once rather than queueing them.
+    #[tokio::test]
+    async fn test_redis_pool_wait_times_out() {
+        let address = redis_stand_in(usize::MAX).await;
+        let store = Arc::new(
+            RedisStore::new(&address, "ratelimit:")
+                .with_timeout(Duration::from_millis(50))
+                .with_connections(2),
+        );
+
+        let started = std::time::Instant::now();
+        let calls: Vec<_> = (0..16)
+            .map(|_| {
+                let store = store.clone();
+                tokio::spawn(async move { store.get("a").await })
+            })
+            .collect();
+        for call in calls {
+            assert!(call.await.unwrap().is_err());
+        }
+        assert!(started.elapsed() < Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_sweep() {
+        let store = MemoryStore {
+            sweep_interval: Duration::ZERO,
+            ..MemoryStore::default()
+        };
+        store.entries.lock().unwrap().next_sweep = SystemTime::now();
+        for key in ["a", "b", "c"] {
+            assert!(
+                store
+                    .compare_and_set(key, None, 1, Duration::from_millis(10))
+                    .await
+                    .unwrap()
+            );
+        }
+        assert_eq!(store.entries.lock().unwrap().values.len(), 3);
+
+        // Expired keys go on the next write, without being read again.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        store
+            .compare_and_set("d", None, 1, Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(store.entries.lock().unwrap().values.len(), 1);
+    }
+}
@@ -0,0 +1,247 @@
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use http::{HeaderMap, HeaderName, HeaderValue, header, request::Parts};
+
+#[derive(Debug, Clone)]
+pub struct SecurityHeadersConfig {
+    pub hsts: Option<String>,
+    // `{nonce}` is replaced with the per-request nonce.
+    pub content_security_policy: Option<String>,
+    // Appended to the CSP as a `frame-ancestors` directive.
+    pub frame_ancestors: Option<String>,
+    pub content_type_options: bool,
+    pub referrer_policy: Option<String>,
+    pub permissions_policy: Option<String>,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        Self {
+            hsts: Some("max-age=63072000; includeSubDomains".to_string()),
+            content_security_policy: Some(
+                "default-src 'self'; script-src 'self' 'nonce-{nonce}'; object-src 'none'; base-uri 'self'"
+                    .to_string(),
+            ),
+            frame_ancestors: Some("'none'".to_string()),
+            content_type_options: true,
+            referrer_policy: Some("strict-origin-when-cross-origin".to_string()),
+            permissions_policy: Some("camera=(), microphone=(), geolocation=()".to_string()),
+        }
+    }
+}
+
+impl SecurityHeadersConfig {
+    fn content_security_policy(&self, nonce: &str) -> Option<String> {
+        let policy = self
+            .content_security_policy
+            .as_ref()
+            .map(|policy| policy.replace("{nonce}", nonce));
+        let frame_ancestors = self
+            .frame_ancestors
+            .as_ref()
+            .map(|sources| format!("frame-ancestors {}", sources));
+
+        match (policy, frame_ancestors) {
+            (Some(policy), Some(frame_ancestors)) => {
+                Some(format!("{}; {}", policy, frame_ancestors))
+            }
+            (policy, frame_ancestors) => policy.or(frame_ancestors),
+        }
+    }
+
+    fn apply(&self, headers: &mut HeaderMap, nonce: &str) {
+        let mut set = |name: HeaderName, value: Option<String>| {
+            // Headers set explicitly by the handler win.
+            if headers.contains_key(&name) {
+                return;
+            }
+            if let Some(value) = value.and_then(|value| HeaderValue::try_from(value).ok()) {
+                headers.insert(name, value);
+            }
+        };
+
+        set(header::STRICT_TRANSPORT_SECURITY, self.hsts.clone());
+        set(
+            header::CONTENT_SECURITY_POLICY,
+            self.content_security_policy(nonce),
+        );
+        set(
+            header::X_CONTENT_TYPE_OPTIONS,
+            self.content_type_options.then(|| "nosniff".to_string()),
+        );
+        set(header::REFERRER_POLICY, self.referrer_policy.clone());
+        set(
+            HeaderName::from_static("permissions-policy"),
+            self.permissions_policy.clone(),
+        );
+        set(
+            header::X_FRAME_OPTIONS,
+            match self.frame_ancestors.as_deref() {
+                Some("'none'") => Some("DENY".to_string()),
+                Some("'self'") => Some("SAMEORIGIN".to_string()),
+                _ => None,
+            },
+        );
+    }
+}
+
+// Per-request CSP nonce for inline `<script nonce="...">` tags.
+#[derive(Debug, Clone)]
+pub struct CspNonce(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for CspNonce {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<CspNonce>()
+            .cloned()
+            .unwrap_or(CspNonce(String::new())))
+    }
+}
+
+pub async fn security_headers(
+    State(config): State<Arc<SecurityHeadersConfig>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let nonce = STANDARD.encode(rand::random::<[u8; 16]>());
+    request.extensions_mut().insert(CspNonce(nonce.clone()));
+
+    let mut response = next.run(request).await;
+    let config = response
+        .extensions()
+        .get::<Arc<SecurityHeadersConfig>>()
+        .cloned()
+        .unwrap_or(config);
+    config.apply(response.headers_mut(), &nonce);
+    response
+}
+
+// Route layer that swaps the configuration used by `security_headers` for
+// the routes it wraps.
+pub async fn override_security_headers(
+    State(config): State<Arc<SecurityHeadersConfig>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    response.extensions_mut().insert(config);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, middleware::from_fn_with_state, response::Html, routing::get};
+    use axum_test::TestServer;
+    use http::StatusCode;
+
+    fn app() -> Router {
+        async fn route(request: Request) -> Response {
+            Response::builder()
+                .status(StatusCode::OK)
+                .header("X-Owner", "hadi")
+                .body(Body::from(format!("Hello {}", request.method())))
+                .unwrap()
+        }
+
+        async fn page(CspNonce(nonce): CspNonce) -> Html<String> {
+            Html(format!("<script nonce=\"{}\">hello()</script>", nonce))
+        }
+
+        async fn embed() -> &'static str {
+            "Embeddable"
+        }
+
+        async fn custom() -> ([(HeaderName, &'static str); 1], &'static str) {
+            ([(header::REFERRER_POLICY, "no-referrer")], "Custom")
+        }
+
+        let embeddable = Arc::new(SecurityHeadersConfig {
+            frame_ancestors: Some("https://partner.example.com".to_string()),
+            content_security_policy: None,
+            ..SecurityHeadersConfig::default()
+        });
+
+        Router::new()
+            .route("/", get(route))
+            .route("/page", get(page))
+            .route("/custom", get(custom))
+            .route(
+                "/embed",
+                get(embed).route_layer(from_fn_with_state(embeddable, override_security_headers)),
+            )
+            .layer(from_fn_with_state(
+                Arc::new(SecurityHeadersConfig::default()),
+                security_headers,
+            ))
+    }
+
+    #[tokio::test]
+    async fn test_default_security_headers() {
+        let server = TestServer::new(app()).unwrap();
+        let response = server.get("/").await;
+
+        response.assert_status_ok();
+        response.assert_header("X-Owner", "hadi");
+        response.assert_header(
+            "Strict-Transport-Security",
+            "max-age=63072000; includeSubDomains",
+        );
+        response.assert_header("X-Content-Type-Options", "nosniff");
+        response.assert_header("Referrer-Policy", "strict-origin-when-cross-origin");
+        response.assert_header(
+            "Permissions-Policy",
+            "camera=(), microphone=(), geolocation=()",
+        );
+        response.assert_header("X-Frame-Options", "DENY");
+
+        let csp = response.header("Content-Security-Policy");
+        assert!(csp.to_str().unwrap().ends_with("; frame-ancestors 'none'"));
+    }
+
+    #[tokio::test]
+    async fn test_csp_nonce_matches_page() {
+        let server = TestServer::new(app()).unwrap();
+        let response = server.get("/page").await;
+
+        let csp = response.header("Content-Security-Policy");
+        let nonce = csp
+            .to_str()
+            .unwrap()
+            .split("'nonce-")
+            .nth(1)
+            .and_then(|rest| rest.split('\'').next())
+            .unwrap()
+            .to_string();
+        response.assert_text(format!("<script nonce=\"{}\">hello()</script>", nonce));
+
+        let other = server.get("/page").await;
+        assert_ne!(other.header("Content-Security-Policy"), csp);
+    }
+
+    #[tokio::test]
+    async fn test_route_override() {
+        let server = TestServer::new(app()).unwrap();
+
+        let response = server.get("/embed").await;
+        response.assert_status_ok();
+        response.assert_header(
+            "Content-Security-Policy",
+            "frame-ancestors https://partner.example.com",
+        );
+        assert!(response.maybe_header("X-Frame-Options").is_none());
+
+        let response = server.get("/custom").await;
+        response.assert_header("Referrer-Policy", "no-referrer");
+        response.assert_header("X-Content-Type-Options", "nosniff");
+    }
+}
@@ -0,0 +1,321 @@
+use std::{convert::Infallible, sync::Arc, time::Duration};
+
+use axum::{
+    Router,
+    extract::{FromRef, Query, State},
+    response::{
+        IntoResponse, Response,
+        sse::{Event as SseEvent, KeepAlive, Sse},
+    },
+    routing::get,
+};
+use futures_util::{Stream, StreamExt, stream};
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use utoipa::OpenApi;
+
+use crate::{
+    auth::{AuthUser, TokenService},
+    error::{AppError, ErrorBody},
+    hub::{Event, Hub, SubscribeError, Subscriber},
+};
+
+const LAST_EVENT_ID: HeaderName = HeaderName::from_static("last-event-id");
+
+#[derive(Debug, Clone)]
+pub struct SseConfig {
+    // Interval between keep-alive comments, so proxies don't time out an
+    // idle stream.
+    pub keep_alive: Duration,
+    // Sent as `retry:` to tell clients how long to wait before reconnecting.
+    pub retry: Duration,
+}
+
+impl Default for SseConfig {
+    fn default() -> Self {
+        Self {
+            keep_alive: Duration::from_secs(15),
+            retry: Duration::from_secs(3),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SseState {
+    pub config: Arc<SseConfig>,
+    pub hub: Arc<Hub>,
+    pub tokens: Arc<TokenService>,
+}
+
+impl FromRef<SseState> for Arc<TokenService> {
+    fn from_ref(state: &SseState) -> Self {
+        state.tokens.clone()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    // Comma-separated; the caller's own `user:<name>` channel is always
+    // included.
+    #[serde(default)]
+    channels: String,
+}
+
+fn to_sse(event: &Event) -> SseEvent {
+    SseEvent::default()
+        .id(event.id.to_string())
+        .event(&event.channel)
+        .data(event.data.to_string())
+}
+
+// Buffered events the client missed, then live ones until the hub drops the
+// connection for falling behind. The subscriber lives as long as the stream.
+fn events(
+    missed: Vec<Arc<Event>>,
+    subscriber: Subscriber,
+    receiver: mpsc::Receiver<Arc<Event>>,
+) -> impl Stream<Item = Result<SseEvent, Infallible>> {
+    let live = stream::unfold(
+        (subscriber, receiver),
+        |(subscriber, mut receiver)| async move {
+            let event = receiver.recv().await?;
+            Some((event, (subscriber, receiver)))
+        },
+    );
+    stream::iter(missed)
+        .chain(live)
+        .map(|event| Ok(to_sse(&event)))
+}
+
+// Streams hub events as server-sent events, for clients that cannot use the
+// WebSocket endpoint. A reconnecting client sends `Last-Event-ID` and gets
+// what it missed from the hub's replay buffer.
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "realtime",
+    params(
+        ("channels" = Option<String>, Query, description = "Comma-separated channels"),
+        ("Last-Event-ID" = Option<String>, Header, description = "Resume after this event"),
+    ),
+    responses(
+        (status = 200, description = "Hub events", content_type = "text/event-stream"),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+    )
+)]
+async fn subscribe(
+    State(state): State<SseState>,
+    Query(query): Query<EventsQuery>,
+    headers: HeaderMap,
+    user: AuthUser,
+) -> Result<Response, AppError> {
+    let last_event_id = match headers.get(LAST_EVENT_ID) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .ok_or_else(|| AppError::new(StatusCode::BAD_REQUEST, "Invalid Last-Event-ID"))?,
+        ),
+        None => None,
+    };
+    let own = format!("user:{}", user.username);
+    let mut channels: Vec<&str> = query
+        .channels
+        .split(',')
+        .map(str::trim)
+        .filter(|channel| !channel.is_empty())
+        .collect();
+    if !channels.contains(&own.as_str()) {
+        channels.push(&own);
+    }
+
+    let (subscriber, receiver) = state.hub.connect(&user.username);
+    let missed = subscriber
+        .resume(&channels, last_event_id)
+        .map_err(|err| match err {
+            SubscribeError::Forbidden => AppError::new(
+                StatusCode::FORBIDDEN,
+                "Not allowed to subscribe to this channel",
+            ),
+            SubscribeError::TooManySubscriptions => {
+                AppError::new(StatusCode::BAD_REQUEST, "Too many subscriptions")
+            }
+        })?;
+
+    let retry = SseEvent::default().retry(state.config.retry);
+    let retry = stream::once(async move { Ok(retry) });
+    let sse = Sse::new(retry.chain(events(missed, subscriber, receiver))).keep_alive(
+        KeepAlive::new()
+            .interval(state.config.keep_alive)
+            .text("keep-alive"),
+    );
+    // Stops nginx from buffering the stream.
+    Ok((
+        [(
+            HeaderName::from_static("x-accel-buffering"),
+            HeaderValue::from_static("no"),
+        )],
+        sse,
+    )
+        .into_response())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(subscribe))]
+pub struct SseApi;
+
+pub fn router(state: SseState) -> Router {
+    Router::new()
+        .route("/events", get(subscribe))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hub::HubConfig;
+    use axum::body::Body;
+    use http::Request;
+    use http_body_util::BodyExt;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    fn setup(config: SseConfig) -> (Router, Arc<Hub>, String) {
+        let hub = Arc::new(Hub::new(HubConfig::default()));
+        let tokens = Arc::new(TokenService::new("secret", Duration::from_secs(60)));
+        let token = tokens.issue("hadi");
+        let app = router(SseState {
+            config: Arc::new(config),
+            hub: hub.clone(),
+            tokens,
+        });
+        (app, hub, token)
+    }
+
+    fn request(uri: &str, token: &str) -> http::request::Builder {
+        Request::get(uri).header("Authorization", format!("Bearer {}", token))
+    }
+
+    // The next chunk of the stream, as text.
+    async fn next_chunk(body: &mut Body) -> String {
+        let frame = tokio::time::timeout(Duration::from_secs(1), body.frame())
+            .await
+            .expect("no event in time")
+            .unwrap()
+            .unwrap();
+        String::from_utf8(frame.into_data().unwrap().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_stream_events() {
+        let (app, hub, token) = setup(SseConfig::default());
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/events").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app
+            .clone()
+            .oneshot(
+                request("/events?channels=user:other", &token)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = app
+            .oneshot(
+                request("/events?channels=products", &token)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let mut body = response.into_body();
+        assert_eq!(next_chunk(&mut body).await, "retry: 3000\n\n");
+
+        hub.publish("user:other", json!("private"));
+        hub.publish("categories", json!({"id": 1}));
+        hub.publish("products", json!({"id": 2}));
+        hub.publish("user:hadi", json!("mine"));
+        assert_eq!(
+            next_chunk(&mut body).await,
+            "id: 3\nevent: products\ndata: {\"id\":2}\n\n"
+        );
+        assert_eq!(
+            next_chunk(&mut body).await,
+            "id: 4\nevent: user:hadi\ndata: \"mine\"\n\n"
+        );
+
+        drop(body);
+        assert_eq!(hub.connection_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_last_event_id() {
+        let (app, hub, token) = setup(SseConfig::default());
+        for id in 1..=3 {
+            hub.publish("products", json!(id));
+        }
+
+        let response = app
+            .clone()
+            .oneshot(
+                request("/events?channels=products", &token)
+                    .header("Last-Event-ID", "1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let mut body = response.into_body();
+        next_chunk(&mut body).await;
+        assert_eq!(
+            next_chunk(&mut body).await,
+            "id: 2\nevent: products\ndata: 2\n\n"
+        );
+        assert_eq!(
+            next_chunk(&mut body).await,
+            "id: 3\nevent: products\ndata: 3\n\n"
+        );
+        hub.publish("products", json!(4));
+        assert_eq!(
+            next_chunk(&mut body).await,
+            "id: 4\nevent: products\ndata: 4\n\n"
+        );
+
+        let response = app
+            .oneshot(
+                request("/events", &token)
+                    .header("Last-Event-ID", "latest")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive() {
+        let (app, _, token) = setup(SseConfig {
+            keep_alive: Duration::from_millis(20),
+            ..SseConfig::default()
+        });
+        let response = app
+            .oneshot(request("/events", &token).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let mut body = response.into_body();
+        next_chunk(&mut body).await;
+        assert_eq!(next_chunk(&mut body).await, ": keep-alive\n\n");
+    }
+}
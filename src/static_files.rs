@@ -0,0 +1,587 @@
+use std::{
+    borrow::Cow,
+    io::SeekFrom,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    Router,
+    body::{Body, Bytes},
+    extract::{Request, State},
+    response::{IntoResponse, Response},
+};
+use http::{HeaderMap, HeaderValue, Method, StatusCode, header};
+use percent_encoding::percent_decode_str;
+use rust_embed::RustEmbed;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    error::{AppError, fallback},
+    etag::{ETag, EntityTags, Preconditions},
+    negotiate::accepts_html,
+    openapi,
+};
+
+// Release builds embed the built frontend; debug builds read the same
+// directory from disk on each request.
+#[derive(RustEmbed)]
+#[folder = "$CARGO_MANIFEST_DIR/frontend/dist"]
+#[allow_missing = true]
+struct Frontend;
+
+// Precompressed variants, in order of preference, by `Content-Encoding` and
+// file suffix.
+const VARIANTS: [(&str, &str); 2] = [("br", ".br"), ("gzip", ".gz")];
+
+#[derive(Debug, Clone)]
+pub enum AssetSource {
+    Dir(PathBuf),
+    Embedded,
+}
+
+#[derive(Debug, Clone)]
+pub struct StaticConfig {
+    pub source: AssetSource,
+    // Served for client-side routes, and for directories.
+    pub index: String,
+    // Unmatched paths under these prefixes get the JSON 404 instead of the
+    // app. Defaults to everything the API mounts.
+    pub api_prefixes: Vec<String>,
+    // For file names with a content hash, which never change.
+    pub immutable_max_age: Duration,
+}
+
+impl Default for StaticConfig {
+    fn default() -> Self {
+        Self {
+            source: AssetSource::Embedded,
+            index: "index.html".to_string(),
+            api_prefixes: openapi::mount_points(),
+            immutable_max_age: Duration::from_secs(365 * 24 * 60 * 60),
+        }
+    }
+}
+
+// Files on disk are streamed; embedded ones are already in memory.
+enum Content {
+    Bytes(Bytes),
+    File(tokio::fs::File),
+}
+
+struct Asset {
+    content: Content,
+    len: u64,
+    modified: Option<SystemTime>,
+    etag: ETag,
+}
+
+impl Asset {
+    // The bytes in `start..=end`, or the whole file.
+    async fn body(self, range: Option<(u64, u64)>) -> std::io::Result<Body> {
+        let (start, end) = range.unwrap_or((0, self.len.saturating_sub(1)));
+        match self.content {
+            Content::Bytes(bytes) if self.len == 0 => Ok(Body::from(bytes)),
+            Content::Bytes(bytes) => Ok(Body::from(bytes.slice(start as usize..=end as usize))),
+            Content::File(mut file) => {
+                file.seek(SeekFrom::Start(start)).await?;
+                let file = file.take(self.len.min(end + 1) - start);
+                Ok(Body::from_stream(ReaderStream::new(file)))
+            }
+        }
+    }
+}
+
+impl StaticConfig {
+    async fn load(&self, path: &str) -> Option<Asset> {
+        match &self.source {
+            AssetSource::Dir(dir) => {
+                let file = tokio::fs::File::open(dir.join(path)).await.ok()?;
+                let metadata = file.metadata().await.ok()?;
+                if !metadata.is_file() {
+                    return None;
+                }
+                let modified = metadata.modified().ok();
+                // Hashing the contents would mean reading the whole file, so
+                // the tag changes with the size and modification time instead.
+                let nanos = modified
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .unwrap_or_default()
+                    .as_nanos();
+                let tag = format!("{}:{}:{}", path, metadata.len(), nanos);
+                Some(Asset {
+                    etag: ETag::for_bytes(tag.as_bytes()),
+                    len: metadata.len(),
+                    modified,
+                    content: Content::File(file),
+                })
+            }
+            AssetSource::Embedded => {
+                let file = Frontend::get(path)?;
+                Some(Asset {
+                    etag: ETag::for_sha256(&file.metadata.sha256_hash()),
+                    len: file.data.len() as u64,
+                    modified: file
+                        .metadata
+                        .last_modified()
+                        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+                    content: Content::Bytes(match file.data {
+                        Cow::Borrowed(bytes) => Bytes::from_static(bytes),
+                        Cow::Owned(bytes) => Bytes::from(bytes),
+                    }),
+                })
+            }
+        }
+    }
+
+    fn is_api(&self, path: &str) -> bool {
+        self.api_prefixes.iter().any(|prefix| {
+            path.strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+}
+
+// The file path for a request path, or `None` when it tries to leave the
+// asset root.
+fn file_path(path: &str, index: &str) -> Option<String> {
+    let decoded = percent_decode_str(path).decode_utf8().ok()?;
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            _ if segment.contains(['\\', '\0']) => return None,
+            _ => segments.push(segment),
+        }
+    }
+    if decoded.ends_with('/') || segments.is_empty() {
+        segments.push(index);
+    }
+    Some(segments.join("/"))
+}
+
+// Names like `app-4f3c2b1a.js` or `main.8d1e0f2c.css` as emitted by
+// bundlers: a segment of at least 8 alphanumerics with a digit.
+fn is_hashed(file: &str) -> bool {
+    let name = file.rsplit('/').next().unwrap_or(file);
+    let Some((stem, _extension)) = name.rsplit_once('.') else {
+        return false;
+    };
+    stem.split(['.', '-', '_']).skip(1).any(|part| {
+        part.len() >= 8
+            && part.chars().all(|c| c.is_ascii_alphanumeric())
+            && part.chars().any(|c| c.is_ascii_digit())
+    })
+}
+
+fn accepts_encoding(headers: &HeaderMap, encoding: &str) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|item| {
+            let mut params = item.split(';');
+            let name = params.next().unwrap_or_default().trim();
+            let q = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (name.eq_ignore_ascii_case(encoding) || name == "*") && q > 0.0
+        })
+}
+
+// A single `bytes=` range as a start and end (inclusive). Multiple ranges
+// and malformed headers are ignored, which serves the whole file.
+enum Range {
+    Full,
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+fn parse_range(value: &str, len: u64) -> Range {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Range::Full;
+    };
+    if spec.contains(',') {
+        return Range::Full;
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return Range::Full;
+    };
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return Range::Unsatisfiable,
+            Ok(suffix) => (len.saturating_sub(suffix), len.saturating_sub(1)),
+            Err(_) => return Range::Full,
+        },
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return Range::Full;
+            };
+            let end = match end {
+                "" => len.saturating_sub(1),
+                end => match end.parse::<u64>() {
+                    Ok(end) if end >= start => end.min(len.saturating_sub(1)),
+                    _ => return Range::Full,
+                },
+            };
+            (start, end)
+        }
+    };
+    if len == 0 || start >= len {
+        return Range::Unsatisfiable;
+    }
+    Range::Partial(start, end)
+}
+
+fn http_date(time: SystemTime) -> HeaderValue {
+    HeaderValue::try_from(httpdate::fmt_http_date(time)).unwrap()
+}
+
+fn header_date(headers: &HeaderMap, name: header::HeaderName) -> Option<SystemTime> {
+    httpdate::parse_http_date(headers.get(name)?.to_str().ok()?).ok()
+}
+
+// HTTP dates have no sub-second part, so comparisons use whole seconds.
+fn whole_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+async fn respond(
+    asset: Asset,
+    mut headers: HeaderMap,
+    request: &HeaderMap,
+    preconditions: &Preconditions,
+) -> Response {
+    headers.insert(header::ETAG, asset.etag.header_value());
+    if let Some(modified) = asset.modified {
+        headers.insert(header::LAST_MODIFIED, http_date(modified));
+    }
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    if let Err(unsatisfied) = preconditions.check(Some(&asset.etag)) {
+        if unsatisfied.status == StatusCode::NOT_MODIFIED {
+            return (StatusCode::NOT_MODIFIED, headers).into_response();
+        }
+        return unsatisfied.into_response();
+    }
+    // `If-None-Match` takes precedence when both are sent.
+    if preconditions.if_none_match.is_none()
+        && let (Some(since), Some(modified)) = (
+            header_date(request, header::IF_MODIFIED_SINCE),
+            asset.modified,
+        )
+        && whole_seconds(modified) <= whole_seconds(since)
+    {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    let len = asset.len;
+    // A stale `If-Range` gets the whole current file instead of a slice.
+    let range_is_current = match request.get(header::IF_RANGE) {
+        None => true,
+        Some(value) => {
+            let value = value.to_str().unwrap_or_default();
+            match EntityTags::parse(value) {
+                Some(EntityTags::List(tags)) if !tags.is_empty() => {
+                    tags.iter().any(|tag| tag.strong_eq(&asset.etag))
+                }
+                _ => httpdate::parse_http_date(value).is_ok_and(|date| {
+                    asset
+                        .modified
+                        .is_some_and(|modified| whole_seconds(modified) == whole_seconds(date))
+                }),
+            }
+        }
+    };
+    let range = match request.get(header::RANGE) {
+        Some(value) if range_is_current => parse_range(value.to_str().unwrap_or_default(), len),
+        _ => Range::Full,
+    };
+
+    let (status, range) = match range {
+        Range::Full => (StatusCode::OK, None),
+        Range::Partial(start, end) => {
+            headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::try_from(format!("bytes {}-{}/{}", start, end, len)).unwrap(),
+            );
+            (StatusCode::PARTIAL_CONTENT, Some((start, end)))
+        }
+        Range::Unsatisfiable => {
+            headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::try_from(format!("bytes */{}", len)).unwrap(),
+            );
+            return (StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response();
+        }
+    };
+    let content_length = range.map_or(len, |(start, end)| end - start + 1);
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(content_length));
+    match asset.body(range).await {
+        Ok(body) => (status, headers, body).into_response(),
+        Err(err) => AppError::internal(err).into_response(),
+    }
+}
+
+// Looks up `path`, preferring a precompressed variant the client accepts.
+async fn serve_file(
+    config: &StaticConfig,
+    path: &str,
+    request: &HeaderMap,
+    preconditions: &Preconditions,
+) -> Option<Response> {
+    let content_type = mime_guess::from_path(path).first_or_octet_stream();
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::try_from(content_type.as_ref()).unwrap(),
+    );
+    let cache_control = if is_hashed(path) {
+        format!(
+            "public, max-age={}, immutable",
+            config.immutable_max_age.as_secs()
+        )
+    } else {
+        "no-cache".to_string()
+    };
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::try_from(cache_control).unwrap(),
+    );
+    headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+
+    for (encoding, suffix) in VARIANTS {
+        if accepts_encoding(request, encoding)
+            && let Some(asset) = config.load(&format!("{}{}", path, suffix)).await
+        {
+            headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+            return Some(respond(asset, headers, request, preconditions).await);
+        }
+    }
+    let asset = config.load(path).await?;
+    Some(respond(asset, headers, request, preconditions).await)
+}
+
+// Serves assets for every path no route matched. Unknown page paths get the
+// index so the frontend can route them; API paths, other methods and
+// missing assets get the usual JSON 404.
+async fn serve(
+    State(config): State<Arc<StaticConfig>>,
+    preconditions: Preconditions,
+    request: Request,
+) -> Response {
+    let method = request.method();
+    let uri_path = request.uri().path();
+    if (method != Method::GET && method != Method::HEAD) || config.is_api(uri_path) {
+        return fallback(request.uri().clone()).await.into_response();
+    }
+
+    if let Some(path) = file_path(uri_path, &config.index)
+        && let Some(response) = serve_file(&config, &path, request.headers(), &preconditions).await
+    {
+        return response;
+    }
+
+    let is_page = !uri_path
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .contains('.');
+    if is_page
+        && accepts_html(request.headers())
+        && let Some(response) =
+            serve_file(&config, &config.index, request.headers(), &preconditions).await
+    {
+        return response;
+    }
+    fallback(request.uri().clone()).await.into_response()
+}
+
+pub fn router(config: StaticConfig) -> Router {
+    Router::new().fallback(serve).with_state(Arc::new(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use axum_test::TestServer;
+    use serde_json::json;
+
+    fn server(dir: &std::path::Path) -> TestServer {
+        let app = Router::new()
+            .route("/api/products", get(|| async { "products" }))
+            .merge(router(StaticConfig {
+                source: AssetSource::Dir(dir.to_path_buf()),
+                api_prefixes: vec!["/api".to_string()],
+                ..StaticConfig::default()
+            }));
+        TestServer::new(app).unwrap()
+    }
+
+    fn write(dir: &std::path::Path, path: &str, contents: &[u8]) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(file_path("/", "index.html").unwrap(), "index.html");
+        assert_eq!(
+            file_path("/docs/", "index.html").unwrap(),
+            "docs/index.html"
+        );
+        assert_eq!(file_path("/a%20b.txt", "index.html").unwrap(), "a b.txt");
+        assert!(file_path("/../secret", "index.html").is_none());
+        assert!(file_path("/%2e%2e/secret", "index.html").is_none());
+
+        assert!(is_hashed("assets/index-B4x9kQ2z.js"));
+        assert!(is_hashed("main.8d1e0f2c.css"));
+        assert!(!is_hashed("index.html"));
+        assert!(!is_hashed("app-settings.js"));
+
+        // By default every API mount point is kept from the frontend.
+        let config = StaticConfig::default();
+        for path in ["/graphql", "/products/9/missing", "/uploads/tus/x", "/docs/assets/x"] {
+            assert!(config.is_api(path), "{}", path);
+        }
+        assert!(!config.is_api("/settings"));
+        assert!(!config.is_api("/productsearch"));
+    }
+
+    #[tokio::test]
+    async fn test_static_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "index.html",
+            b"<!doctype html><title>app</title>",
+        );
+        write(dir.path(), "assets/app-4f3c2b1a.js", b"console.log('app')");
+        write(dir.path(), "assets/app-4f3c2b1a.js.br", b"brotli bytes");
+        let server = server(dir.path());
+
+        let response = server.get("/").await;
+        response.assert_status_ok();
+        response.assert_header("Content-Type", "text/html");
+        response.assert_header("Cache-Control", "no-cache");
+
+        let response = server.get("/assets/app-4f3c2b1a.js").await;
+        response.assert_text("console.log('app')");
+        response.assert_header("Cache-Control", "public, max-age=31536000, immutable");
+        assert!(response.maybe_header("Last-Modified").is_some());
+        let etag = response.header("ETag");
+
+        let response = server
+            .get("/assets/app-4f3c2b1a.js")
+            .add_header("Accept-Encoding", "gzip, br")
+            .await;
+        response.assert_header("Content-Encoding", "br");
+        response.assert_header("Content-Type", "text/javascript");
+        response.assert_text("brotli bytes");
+        assert_ne!(response.header("ETag"), etag);
+
+        let response = server
+            .get("/assets/app-4f3c2b1a.js")
+            .add_header("If-None-Match", etag.clone())
+            .await;
+        response.assert_status(StatusCode::NOT_MODIFIED);
+        response.assert_header("ETag", etag.clone());
+
+        let modified = server.get("/index.html").await.header("Last-Modified");
+        server
+            .get("/index.html")
+            .add_header("If-Modified-Since", modified)
+            .await
+            .assert_status(StatusCode::NOT_MODIFIED);
+    }
+
+    #[tokio::test]
+    async fn test_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "video.txt", b"0123456789");
+        let server = server(dir.path());
+
+        let response = server
+            .get("/video.txt")
+            .add_header("Range", "bytes=2-5")
+            .await;
+        response.assert_status(StatusCode::PARTIAL_CONTENT);
+        response.assert_header("Content-Range", "bytes 2-5/10");
+        response.assert_text("2345");
+
+        let response = server
+            .get("/video.txt")
+            .add_header("Range", "bytes=-3")
+            .await;
+        response.assert_text("789");
+
+        let response = server
+            .get("/video.txt")
+            .add_header("Range", "bytes=10-")
+            .await;
+        response.assert_status(StatusCode::RANGE_NOT_SATISFIABLE);
+        response.assert_header("Content-Range", "bytes */10");
+
+        // A range of an older version is not applied to the current one.
+        let response = server
+            .get("/video.txt")
+            .add_header("Range", "bytes=2-5")
+            .add_header("If-Range", "\"stale\"")
+            .await;
+        response.assert_status_ok();
+        response.assert_text("0123456789");
+    }
+
+    #[tokio::test]
+    async fn test_spa_fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "index.html",
+            b"<!doctype html><title>app</title>",
+        );
+        let server = server(dir.path());
+
+        let response = server
+            .get("/settings/profile")
+            .add_header("Accept", "text/html,application/xhtml+xml")
+            .await;
+        response.assert_status_ok();
+        response.assert_text_contains("<title>app</title>");
+
+        server
+            .get("/api/products")
+            .add_header("Accept", "text/html")
+            .await
+            .assert_text("products");
+
+        // API paths, missing assets and non-browser requests are not pages.
+        for (path, accept) in [
+            ("/api/missing", "text/html"),
+            ("/assets/missing.js", "text/html"),
+            ("/settings", "application/json"),
+        ] {
+            let response = server.get(path).add_header("Accept", accept).await;
+            response.assert_status(StatusCode::NOT_FOUND);
+            response.assert_json(&json!({
+                "code": 404,
+                "message": format!("No route for {}", path),
+            }));
+        }
+
+        server
+            .post("/settings")
+            .add_header("Accept", "text/html")
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,323 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{FromRequestParts, Request},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+};
+use http::{header, request::Parts};
+use minijinja::{Environment, Value, context};
+use rust_embed::RustEmbed;
+use serde::Serialize;
+
+use crate::{
+    csrf::CsrfToken,
+    error::{AppError, ErrorMessage},
+    flash::FlashMessages,
+    negotiate::accepts_html,
+    security_headers::CspNonce,
+};
+
+#[derive(RustEmbed)]
+#[folder = "templates/"]
+struct EmbeddedTemplates;
+
+#[derive(Debug, Clone)]
+pub struct TemplateConfig {
+    // Templates are read from here instead of the copies in the binary.
+    pub dir: Option<PathBuf>,
+    // Reload templates on every render, to pick up edits without a restart.
+    pub reload: bool,
+}
+
+impl Default for TemplateConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            reload: cfg!(debug_assertions),
+        }
+    }
+}
+
+fn load(dir: Option<&Path>, name: &str) -> Result<Option<String>, minijinja::Error> {
+    if name
+        .split('/')
+        .any(|segment| segment == ".." || segment.is_empty())
+    {
+        return Ok(None);
+    }
+    let Some(dir) = dir else {
+        return Ok(EmbeddedTemplates::get(name)
+            .map(|file| String::from_utf8_lossy(&file.data).into_owned()));
+    };
+    match std::fs::read_to_string(dir.join(name)) {
+        Ok(source) => Ok(Some(source)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(minijinja::Error::new(
+            minijinja::ErrorKind::InvalidOperation,
+            format!("failed to read template {}", name),
+        )
+        .with_source(err)),
+    }
+}
+
+pub struct Templates {
+    config: TemplateConfig,
+    globals: BTreeMap<String, Value>,
+    // Kept between renders unless templates are reloaded.
+    cached: Option<Environment<'static>>,
+}
+
+impl Templates {
+    pub fn new(config: TemplateConfig) -> Self {
+        let mut templates = Self {
+            config,
+            globals: BTreeMap::new(),
+            cached: None,
+        };
+        templates.rebuild();
+        templates
+    }
+
+    // A value available to every template.
+    pub fn with_global(mut self, name: &str, value: impl Serialize) -> Self {
+        self.globals
+            .insert(name.to_string(), Value::from_serialize(value));
+        self.rebuild();
+        self
+    }
+
+    fn rebuild(&mut self) {
+        self.cached = (!self.config.reload).then(|| self.environment());
+    }
+
+    // `.html` templates are auto-escaped.
+    fn environment(&self) -> Environment<'static> {
+        let mut env = Environment::new();
+        let dir = self.config.dir.clone();
+        env.set_loader(move |name| load(dir.as_deref(), name));
+        for (name, value) in &self.globals {
+            env.add_global(name.clone(), value.clone());
+        }
+        env
+    }
+
+    pub fn render(&self, name: &str, context: impl Serialize) -> Result<String, RenderError> {
+        let fresh;
+        let env = match &self.cached {
+            Some(env) => env,
+            None => {
+                fresh = self.environment();
+                &fresh
+            }
+        };
+        env.get_template(name)
+            .and_then(|template| template.render(context))
+            .map_err(RenderError)
+    }
+}
+
+#[derive(Debug)]
+pub struct RenderError(pub minijinja::Error);
+
+impl From<RenderError> for AppError {
+    fn from(RenderError(err): RenderError) -> Self {
+        AppError::internal(err)
+    }
+}
+
+impl IntoResponse for RenderError {
+    fn into_response(self) -> Response {
+        AppError::from(self).into_response()
+    }
+}
+
+// Renders pages with the request's CSRF token, CSP nonce and flash messages
+// merged into the context.
+pub struct Template {
+    templates: Arc<Templates>,
+    request: Value,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Template {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let templates = parts
+            .extensions
+            .get::<Arc<Templates>>()
+            .cloned()
+            .ok_or_else(|| AppError::internal("Templates are not configured"))?;
+        let Ok(CsrfToken(csrf_token)) = CsrfToken::from_request_parts(parts, state).await;
+        let Ok(CspNonce(csp_nonce)) = CspNonce::from_request_parts(parts, state).await;
+        let FlashMessages(flash_messages) = parts
+            .extensions
+            .get::<FlashMessages>()
+            .cloned()
+            .unwrap_or_default();
+        Ok(Self {
+            templates,
+            request: context! { csrf_token, csp_nonce, flash_messages },
+        })
+    }
+}
+
+impl Template {
+    pub fn render(&self, name: &str, context: impl Serialize) -> Result<Html<String>, RenderError> {
+        let context = context! { ..Value::from_serialize(context), ..self.request.clone() };
+        self.templates.render(name, context).map(Html)
+    }
+
+    // An HTML error page, or the JSON error when it cannot be rendered.
+    pub fn error(&self, error: AppError) -> Response {
+        let status = match error.status.canonical_reason() {
+            Some(reason) => format!("{} {}", error.status.as_u16(), reason),
+            None => error.status.as_u16().to_string(),
+        };
+        match self.render("error.html", context! { status, message => &error.message }) {
+            Ok(page) => (error.status, error.headers, page).into_response(),
+            Err(_) => error.into_response(),
+        }
+    }
+}
+
+// Errors for browsers get `error.html` instead of the JSON body. Goes inside
+// the layers that provide the templates, CSRF token, nonce and flashes.
+pub async fn html_errors(request: Request, next: Next) -> Response {
+    if !accepts_html(request.headers()) {
+        return next.run(request).await;
+    }
+    let (mut parts, body) = request.into_parts();
+    let template = Template::from_request_parts(&mut parts, &()).await;
+    let response = next.run(Request::from_parts(parts, body)).await;
+
+    let (Ok(template), Some(ErrorMessage(message))) =
+        (template, response.extensions().get::<ErrorMessage>().cloned())
+    else {
+        return response;
+    };
+    let (parts, _) = response.into_parts();
+    let mut headers = parts.headers;
+    headers.remove(header::CONTENT_TYPE);
+    headers.remove(header::CONTENT_LENGTH);
+    template.error(AppError {
+        status: parts.status,
+        message,
+        headers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::{FlashMessage, Level};
+    use axum::{Extension, Router, routing::get};
+    use axum_test::TestServer;
+    use http::StatusCode;
+
+    fn server(templates: Templates) -> TestServer {
+        async fn page(template: Template) -> Result<Html<String>, RenderError> {
+            template.render("login.html", context! { username => "<hadi>" })
+        }
+
+        async fn missing(template: Template) -> Response {
+            template.error(AppError::new(StatusCode::NOT_FOUND, "No such <page>"))
+        }
+
+        async fn failing() -> Result<(), AppError> {
+            Err(AppError::new(StatusCode::CONFLICT, "Already <taken>"))
+        }
+
+        let app = Router::new()
+            .route("/login", get(page))
+            .route("/missing", get(missing))
+            .route("/failing", get(failing))
+            .fallback(crate::error::fallback)
+            .layer(axum::middleware::from_fn(html_errors))
+            .layer(Extension(Arc::new(
+                templates.with_global("csrf_field", "_csrf"),
+            )))
+            .layer(axum::middleware::from_fn(
+                |mut request: axum::extract::Request, next: axum::middleware::Next| async move {
+                    request
+                        .extensions_mut()
+                        .insert(CsrfToken("TOKEN".to_string()));
+                    request
+                        .extensions_mut()
+                        .insert(FlashMessages(vec![FlashMessage {
+                            level: Level::Error,
+                            message: "Try <again>".to_string(),
+                        }]));
+                    next.run(request).await
+                },
+            ));
+        TestServer::new(app).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_render_pages() {
+        let server = server(Templates::new(TemplateConfig::default()));
+
+        let response = server.get("/login").await;
+        response.assert_status_ok();
+        response.assert_header("Content-Type", "text/html; charset=utf-8");
+        response.assert_text_contains("<title>Sign in</title>");
+        response.assert_text_contains(r#"<input type="hidden" name="_csrf" value="TOKEN">"#);
+        response.assert_text_contains(r#"value="&lt;hadi&gt;""#);
+        response.assert_text_contains(
+            r#"<p class="flash flash-error" role="status">Try &lt;again&gt;</p>"#,
+        );
+
+        let response = server.get("/missing").await;
+        response.assert_status(StatusCode::NOT_FOUND);
+        response.assert_text_contains("<h1>404 Not Found</h1>");
+        response.assert_text_contains("No such &lt;page&gt;");
+    }
+
+    #[tokio::test]
+    async fn test_html_errors() {
+        let server = server(Templates::new(TemplateConfig::default()));
+
+        let response = server.get("/failing").add_header("Accept", "text/html").await;
+        response.assert_status(StatusCode::CONFLICT);
+        response.assert_header("Content-Type", "text/html; charset=utf-8");
+        response.assert_text_contains("Already &lt;taken&gt;");
+
+        let response = server.get("/nowhere").add_header("Accept", "text/html").await;
+        response.assert_status(StatusCode::NOT_FOUND);
+        response.assert_text_contains("<h1>404 Not Found</h1>");
+        response.assert_text_contains("No route for &#x2f;nowhere");
+
+        // API clients keep the JSON body.
+        let response = server
+            .get("/failing")
+            .add_header("Accept", "application/json")
+            .await;
+        response.assert_status(StatusCode::CONFLICT);
+        response.assert_header("Content-Type", "application/json");
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("page.html"), "first").unwrap();
+        let config = |reload| TemplateConfig {
+            dir: Some(dir.path().to_path_buf()),
+            reload,
+        };
+        let reloading = Templates::new(config(true));
+        let cached = Templates::new(config(false));
+        assert_eq!(reloading.render("page.html", ()).unwrap(), "first");
+        assert_eq!(cached.render("page.html", ()).unwrap(), "first");
+
+        std::fs::write(dir.path().join("page.html"), "second").unwrap();
+        assert_eq!(reloading.render("page.html", ()).unwrap(), "second");
+        assert_eq!(cached.render("page.html", ()).unwrap(), "first");
+
+        assert!(cached.render("../page.html", ()).is_err());
+    }
+}
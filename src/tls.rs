@@ -0,0 +1,501 @@
+use std::{
+    fs,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::{ConnectInfo, FromRequestParts, OptionalFromRequestParts, Request},
+    response::Redirect,
+};
+use http::{header, request::Parts, uri::Authority};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use rustls::{
+    RootCertStore, ServerConfig,
+    crypto::{CryptoProvider, ring},
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier},
+    sign::CertifiedKey,
+};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+
+use crate::error::AppError;
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    // CA bundle used to verify client certificates (mutual TLS).
+    pub client_ca_path: Option<PathBuf>,
+    pub require_client_cert: bool,
+    // Plain HTTP listener that redirects everything to HTTPS.
+    pub redirect_address: Option<String>,
+    pub reload_interval: Duration,
+    // Connections that haven't finished the handshake by then are dropped.
+    pub handshake_timeout: Duration,
+}
+
+impl TlsConfig {
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            client_ca_path: None,
+            require_client_cert: false,
+            redirect_address: None,
+            reload_interval: Duration::from_secs(10),
+            handshake_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+// Serves the current certificate and swaps it when the files on disk change.
+#[derive(Debug)]
+pub struct ReloadingCertResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<(Arc<CertifiedKey>, [u8; 32])>,
+}
+
+impl ReloadingCertResolver {
+    pub fn new(cert_path: PathBuf, key_path: PathBuf) -> anyhow::Result<Self> {
+        let (key, digest) = Self::load(&cert_path, &key_path)?;
+        Ok(Self {
+            cert_path,
+            key_path,
+            current: RwLock::new((key, digest)),
+        })
+    }
+
+    fn load(
+        cert_path: &PathBuf,
+        key_path: &PathBuf,
+    ) -> anyhow::Result<(Arc<CertifiedKey>, [u8; 32])> {
+        let cert_pem = fs::read(cert_path)?;
+        let key_pem = fs::read(key_path)?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(&cert_pem);
+        hasher.update(&key_pem);
+        let digest: [u8; 32] = hasher.finalize().into();
+
+        let certs = CertificateDer::pem_slice_iter(&cert_pem).collect::<Result<Vec<_>, _>>()?;
+        anyhow::ensure!(
+            !certs.is_empty(),
+            "no certificate in {}",
+            cert_path.display()
+        );
+        let key = PrivateKeyDer::from_pem_slice(&key_pem)?;
+        let signing_key = provider().key_provider.load_private_key(key)?;
+
+        Ok((Arc::new(CertifiedKey::new(certs, signing_key)), digest))
+    }
+
+    pub fn current(&self) -> Arc<CertifiedKey> {
+        self.current.read().unwrap().0.clone()
+    }
+
+    // Returns true when a new certificate was loaded.
+    pub fn reload(&self) -> anyhow::Result<bool> {
+        let (key, digest) = Self::load(&self.cert_path, &self.key_path)?;
+        let mut current = self.current.write().unwrap();
+        if current.1 == digest {
+            return Ok(false);
+        }
+        *current = (key, digest);
+        Ok(true)
+    }
+
+    pub fn watch(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match self.reload() {
+                    Ok(true) => {
+                        tracing::info!("TLS certificate reloaded from {}", self.cert_path.display())
+                    }
+                    Ok(false) => {}
+                    // Keep serving the old certificate while files are mid-update.
+                    Err(err) => tracing::warn!("TLS certificate reload failed: {}", err),
+                }
+            }
+        });
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+}
+
+pub fn server_config(
+    config: &TlsConfig,
+    resolver: Arc<ReloadingCertResolver>,
+) -> anyhow::Result<ServerConfig> {
+    let builder =
+        ServerConfig::builder_with_provider(provider()).with_safe_default_protocol_versions()?;
+
+    let builder = match &config.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(path)? {
+                roots.add(cert?)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider());
+            let verifier = if config.require_client_cert {
+                verifier.build()?
+            } else {
+                verifier.allow_unauthenticated().build()?
+            };
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder.with_cert_resolver(resolver);
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(server_config)
+}
+
+// Identity of a caller that presented a verified client certificate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientIdentity {
+    pub common_name: Option<String>,
+    pub fingerprint: String,
+}
+
+impl ClientIdentity {
+    fn from_der(der: &CertificateDer<'_>) -> Self {
+        let common_name = x509_parser::parse_x509_certificate(der)
+            .ok()
+            .and_then(|(_, cert)| {
+                cert.subject()
+                    .iter_common_name()
+                    .next()
+                    .and_then(|cn| cn.as_str().ok())
+                    .map(str::to_string)
+            });
+        let fingerprint = Sha256::digest(der)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Self {
+            common_name,
+            fingerprint,
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIdentity {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<ClientIdentity>()
+            .cloned()
+            .ok_or_else(|| AppError::unauthorized("Client certificate required"))
+    }
+}
+
+impl<S: Send + Sync> OptionalFromRequestParts<S> for ClientIdentity {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        Ok(parts.extensions.get::<ClientIdentity>().cloned())
+    }
+}
+
+pub async fn serve_tls(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    app: Router,
+    handshake_timeout: Duration,
+) {
+    loop {
+        let (stream, remote) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                tracing::warn!("TCP accept failed: {}", err);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+
+        tokio::spawn(async move {
+            let stream =
+                match tokio::time::timeout(handshake_timeout, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(err)) => {
+                        tracing::debug!("TLS handshake with {} failed: {}", remote, err);
+                        return;
+                    }
+                    Err(_) => {
+                        tracing::debug!("TLS handshake with {} timed out", remote);
+                        return;
+                    }
+                };
+            let identity = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(ClientIdentity::from_der);
+
+            let service = app.map_request(move |request: Request<hyper::body::Incoming>| {
+                let mut request = request.map(Body::new);
+                request.extensions_mut().insert(ConnectInfo(remote));
+                if let Some(identity) = identity.clone() {
+                    request.extensions_mut().insert(identity);
+                }
+                request
+            });
+
+            if let Err(err) = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(
+                    TokioIo::new(stream),
+                    TowerToHyperService::new(service),
+                )
+                .await
+            {
+                tracing::debug!("Connection from {} closed with error: {}", remote, err);
+            }
+        });
+    }
+}
+
+pub fn redirect_router(https_port: u16) -> Router {
+    Router::new().fallback(move |request: Request| async move {
+        // Keeps the brackets of an IPv6 host.
+        let authority = request
+            .headers()
+            .get(header::HOST)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<Authority>().ok());
+        let host = authority.as_ref().map_or("localhost", Authority::host);
+        let path = request
+            .uri()
+            .path_and_query()
+            .map_or("/", |path| path.as_str());
+
+        let location = if https_port == 443 {
+            format!("https://{}{}", host, path)
+        } else {
+            format!("https://{}:{}{}", host, https_port, path)
+        };
+        Redirect::permanent(&location)
+    })
+}
+
+pub async fn run(config: TlsConfig, address: SocketAddr, app: Router) -> anyhow::Result<()> {
+    let resolver = Arc::new(ReloadingCertResolver::new(
+        config.cert_path.clone(),
+        config.key_path.clone(),
+    )?);
+    resolver.clone().watch(config.reload_interval);
+    let acceptor = TlsAcceptor::from(Arc::new(server_config(&config, resolver)?));
+
+    if let Some(redirect_address) = &config.redirect_address {
+        let listener = TcpListener::bind(redirect_address).await?;
+        let redirect = redirect_router(address.port());
+        tokio::spawn(async move { axum::serve(listener, redirect).await });
+    }
+
+    let listener = TcpListener::bind(address).await?;
+    serve_tls(listener, acceptor, app, config.handshake_timeout).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use axum_test::TestServer;
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, DnType, IsCa, KeyPair};
+    use rustls::{ClientConfig, pki_types::ServerName};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::TlsConnector;
+
+    struct Pki {
+        ca: CertifiedIssuer<'static, KeyPair>,
+    }
+
+    impl Pki {
+        fn new() -> Self {
+            let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params
+                .distinguished_name
+                .push(DnType::CommonName, "Test CA");
+            let ca = CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap();
+            Self { ca }
+        }
+
+        // Returns (certificate PEM, key PEM).
+        fn issue(&self, name: &str) -> (String, String) {
+            let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+            params.distinguished_name.push(DnType::CommonName, name);
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &self.ca).unwrap();
+            (cert.pem(), key.serialize_pem())
+        }
+    }
+
+    #[test]
+    fn test_certificate_hot_reload() {
+        let pki = Pki::new();
+        let dir = tempfile::tempdir().unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+
+        let (cert, key) = pki.issue("localhost");
+        fs::write(&cert_path, &cert).unwrap();
+        fs::write(&key_path, &key).unwrap();
+
+        let resolver = ReloadingCertResolver::new(cert_path.clone(), key_path.clone()).unwrap();
+        let first = resolver.current();
+        assert!(!resolver.reload().unwrap());
+
+        let (cert, key) = pki.issue("localhost");
+        fs::write(&cert_path, &cert).unwrap();
+        fs::write(&key_path, &key).unwrap();
+        assert!(resolver.reload().unwrap());
+        assert_ne!(resolver.current().cert, first.cert);
+
+        // A half-written pair keeps the previous certificate in place.
+        fs::write(&key_path, "").unwrap();
+        assert!(resolver.reload().is_err());
+        assert_eq!(
+            resolver.current().cert[0].as_ref(),
+            CertificateDer::from_pem_slice(cert.as_bytes())
+                .unwrap()
+                .as_ref()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls_identity() {
+        async fn route(identity: Option<ClientIdentity>) -> String {
+            match identity.and_then(|identity| identity.common_name) {
+                Some(name) => format!("Hello, {}", name),
+                None => "Hello, anonymous".to_string(),
+            }
+        }
+
+        let pki = Pki::new();
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, key) = pki.issue("localhost");
+        fs::write(dir.path().join("cert.pem"), cert).unwrap();
+        fs::write(dir.path().join("key.pem"), key).unwrap();
+        fs::write(dir.path().join("ca.pem"), pki.ca.pem()).unwrap();
+
+        let config = TlsConfig {
+            client_ca_path: Some(dir.path().join("ca.pem")),
+            ..TlsConfig::new(dir.path().join("cert.pem"), dir.path().join("key.pem"))
+        };
+        let resolver = Arc::new(
+            ReloadingCertResolver::new(config.cert_path.clone(), config.key_path.clone()).unwrap(),
+        );
+        let acceptor = TlsAcceptor::from(Arc::new(server_config(&config, resolver).unwrap()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = Router::new().route("/", get(route));
+        tokio::spawn(serve_tls(listener, acceptor, app, config.handshake_timeout));
+
+        let mut roots = RootCertStore::empty();
+        roots.add(pki.ca.der().clone()).unwrap();
+        let (client_cert, client_key) = pki.issue("client-1");
+        let client_config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(
+                vec![CertificateDer::from_pem_slice(client_cert.as_bytes()).unwrap()],
+                PrivateKeyDer::from_pem_slice(client_key.as_bytes()).unwrap(),
+            )
+            .unwrap();
+
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let mut stream = TlsConnector::from(Arc::new(client_config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("Hello, client-1"));
+    }
+
+    #[tokio::test]
+    async fn test_http_redirect() {
+        let server = TestServer::new(redirect_router(8443)).unwrap();
+
+        let response = server
+            .get("/login")
+            .add_query_param("next", "home")
+            .add_header("Host", "example.com:8080")
+            .await;
+        response.assert_status(http::StatusCode::PERMANENT_REDIRECT);
+        response.assert_header("Location", "https://example.com:8443/login?next=home");
+
+        let response = server.get("/").add_header("Host", "[::1]:8080").await;
+        response.assert_header("Location", "https://[::1]:8443/");
+    }
+
+    #[tokio::test]
+    async fn test_handshake_timeout() {
+        let pki = Pki::new();
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, key) = pki.issue("localhost");
+        fs::write(dir.path().join("cert.pem"), cert).unwrap();
+        fs::write(dir.path().join("key.pem"), key).unwrap();
+        let config = TlsConfig::new(dir.path().join("cert.pem"), dir.path().join("key.pem"));
+        let resolver = Arc::new(
+            ReloadingCertResolver::new(config.cert_path.clone(), config.key_path.clone()).unwrap(),
+        );
+        let acceptor = TlsAcceptor::from(Arc::new(server_config(&config, resolver).unwrap()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_tls(
+            listener,
+            acceptor,
+            Router::new(),
+            Duration::from_millis(50),
+        ));
+
+        // A client that never sends a ClientHello is disconnected.
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let read = tokio::time::timeout(Duration::from_secs(1), stream.read(&mut [0; 1]))
+            .await
+            .expect("connection was not closed");
+        assert_eq!(read.unwrap(), 0);
+    }
+}
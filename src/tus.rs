@@ -0,0 +1,857 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::{FromRef, Path, Request, State},
+    middleware::{Next, from_fn},
+    response::{IntoResponse, Response},
+    routing::{head, post},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use futures_util::StreamExt;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, header};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use utoipa::OpenApi;
+
+use crate::{
+    auth::{AuthUser, TokenService},
+    blob_store::{BlobStore, content_key, hex},
+    error::{AppError, ErrorBody},
+    upload::{SNIFF_LEN, sniff},
+};
+
+pub const TUS_VERSION: &str = "1.0.0";
+pub const TUS_EXTENSIONS: &str = "creation,creation-with-upload,expiration,termination";
+
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+const TUS_VERSION_HEADER: HeaderName = HeaderName::from_static("tus-version");
+const TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+const TUS_MAX_SIZE: HeaderName = HeaderName::from_static("tus-max-size");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+const UPLOAD_EXPIRES: HeaderName = HeaderName::from_static("upload-expires");
+
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+#[derive(Debug, Clone)]
+pub struct TusConfig {
+    // Staging directory for partial uploads.
+    pub dir: PathBuf,
+    pub max_size: u64,
+    // Uploads not completed within this window are discarded.
+    pub expiry: Duration,
+    pub sweep_interval: Duration,
+    pub allowed_types: Vec<String>,
+    pub link_ttl: Duration,
+}
+
+impl Default for TusConfig {
+    fn default() -> Self {
+        Self {
+            dir: std::env::temp_dir().join("axum-tus"),
+            max_size: 1024 * 1024 * 1024,
+            expiry: Duration::from_secs(24 * 60 * 60),
+            sweep_interval: Duration::from_secs(60 * 60),
+            allowed_types: [
+                "image/png",
+                "image/jpeg",
+                "image/gif",
+                "image/webp",
+                "application/pdf",
+            ]
+            .map(str::to_string)
+            .to_vec(),
+            link_ttl: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TusUpload {
+    length: u64,
+    offset: u64,
+    metadata: String,
+    expires_at: SystemTime,
+    path: PathBuf,
+    // Blob key once the upload is complete and stored.
+    key: Option<String>,
+    url: Option<String>,
+}
+
+struct Entry {
+    // Only the user who created an upload can see or change it.
+    owner: String,
+    upload: Arc<tokio::sync::Mutex<TusUpload>>,
+}
+
+// Each upload has its own async lock so PATCH requests for one upload are
+// serialized without blocking the others.
+pub struct Tus {
+    config: TusConfig,
+    store: Arc<dyn BlobStore>,
+    uploads: Mutex<HashMap<String, Entry>>,
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &HeaderName) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn bad_request(message: &str) -> AppError {
+    AppError::new(StatusCode::BAD_REQUEST, message)
+}
+
+fn not_found() -> AppError {
+    AppError::new(StatusCode::NOT_FOUND, "Upload not found")
+}
+
+// `key base64value,key2` pairs; values are optional per the spec.
+pub fn parse_metadata(value: &str) -> Option<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+    for pair in value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, value)) => {
+                let value = String::from_utf8(STANDARD.decode(value.trim()).ok()?).ok()?;
+                (key, value)
+            }
+            None => (pair, String::new()),
+        };
+        if key.is_empty() || metadata.insert(key.to_string(), value).is_some() {
+            return None;
+        }
+    }
+    Some(metadata)
+}
+
+impl Tus {
+    pub fn new(config: TusConfig, store: Arc<dyn BlobStore>) -> Self {
+        Self {
+            config,
+            store,
+            uploads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Someone else's upload is treated as missing.
+    fn get(&self, id: &str, owner: &str) -> Option<Arc<tokio::sync::Mutex<TusUpload>>> {
+        self.uploads
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|entry| entry.owner == owner)
+            .map(|entry| entry.upload.clone())
+    }
+
+    async fn remove(&self, id: &str) {
+        let entry = self.uploads.lock().unwrap().remove(id);
+        if let Some(entry) = entry {
+            let _ = tokio::fs::remove_file(&entry.upload.lock().await.path).await;
+        }
+    }
+
+    // Drops expired uploads, finished or not; a finished one only loses its
+    // status record, the blob stays. Returns how many were removed.
+    pub async fn sweep(&self) -> usize {
+        let now = SystemTime::now();
+        let candidates: Vec<_> = self
+            .uploads
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, entry)| (id.clone(), entry.upload.clone()))
+            .collect();
+
+        let mut removed = 0;
+        for (id, upload) in candidates {
+            // Uploads mid-PATCH are skipped and picked up by a later sweep.
+            let Ok(upload) = upload.try_lock() else {
+                continue;
+            };
+            if upload.expires_at <= now {
+                drop(upload);
+                self.remove(&id).await;
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    pub fn watch(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.config.sweep_interval);
+            loop {
+                ticker.tick().await;
+                let removed = self.sweep().await;
+                if removed > 0 {
+                    tracing::info!("Removed {} expired tus uploads", removed);
+                }
+            }
+        });
+    }
+
+    // Hashes and sniffs the assembled file, then hands it to the blob store.
+    async fn finish(&self, upload: &mut TusUpload) -> Result<(), AppError> {
+        let mut file = tokio::fs::File::open(&upload.path)
+            .await
+            .map_err(AppError::internal)?;
+        let mut hasher = Sha256::new();
+        let mut head = Vec::new();
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = file.read(&mut buffer).await.map_err(AppError::internal)?;
+            if read == 0 {
+                break;
+            }
+            let take = (SNIFF_LEN - head.len()).min(read);
+            head.extend_from_slice(&buffer[..take]);
+            hasher.update(&buffer[..read]);
+        }
+
+        let content_type = sniff(&head)
+            .filter(|detected| {
+                self.config
+                    .allowed_types
+                    .iter()
+                    .any(|allowed| allowed == detected)
+            })
+            .ok_or_else(|| {
+                AppError::new(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    "Upload has an unsupported file type",
+                )
+            })?;
+
+        let key = content_key(&hex(&hasher.finalize()), content_type);
+        self.store
+            .put_file(&key, &upload.path, content_type)
+            .await
+            .map_err(AppError::internal)?;
+        let url = self
+            .store
+            .presign_get(&key, self.config.link_ttl)
+            .await
+            .map_err(AppError::internal)?;
+        let _ = tokio::fs::remove_file(&upload.path).await;
+
+        upload.key = Some(key);
+        upload.url = Some(url);
+        // The status is only worth keeping while its link works.
+        upload.expires_at = SystemTime::now() + self.config.link_ttl;
+        Ok(())
+    }
+
+    // Appends the body at `upload.offset`, stopping with 413 past `length`.
+    async fn append(&self, upload: &mut TusUpload, body: Body) -> Result<(), AppError> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&upload.path)
+            .await
+            .map_err(AppError::internal)?;
+
+        let mut stream = body.into_data_stream();
+        let mut result = Ok(());
+        while let Some(chunk) = stream.next().await {
+            // A dropped connection keeps whatever arrived, so the client can resume.
+            let Ok(chunk) = chunk else {
+                break;
+            };
+            if upload.offset + chunk.len() as u64 > upload.length {
+                result = Err(AppError::new(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "Chunk exceeds Upload-Length",
+                ));
+                break;
+            }
+            file.write_all(&chunk).await.map_err(AppError::internal)?;
+            upload.offset += chunk.len() as u64;
+        }
+        file.flush().await.map_err(AppError::internal)?;
+        result
+    }
+}
+
+fn upload_headers(id: Option<&str>, upload: &TusUpload) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(UPLOAD_OFFSET, HeaderValue::from(upload.offset));
+    if upload.key.is_none() {
+        headers.insert(
+            UPLOAD_EXPIRES,
+            HeaderValue::try_from(httpdate::fmt_http_date(upload.expires_at)).unwrap(),
+        );
+    }
+    if let Some(url) = upload
+        .url
+        .as_deref()
+        .and_then(|url| HeaderValue::try_from(url).ok())
+    {
+        headers.insert(header::CONTENT_LOCATION, url);
+    }
+    if let Some(id) = id {
+        headers.insert(
+            header::LOCATION,
+            HeaderValue::try_from(format!("/uploads/tus/{}", id)).unwrap(),
+        );
+    }
+    headers
+}
+
+#[utoipa::path(
+    options,
+    path = "/uploads/tus",
+    tag = "files",
+    responses(
+        (status = 204, description = "Supported tus version, extensions and size limit", headers(
+            ("Tus-Version" = String),
+            ("Tus-Extension" = String),
+            ("Tus-Max-Size" = u64),
+        )),
+    )
+)]
+async fn options(State(tus): State<Arc<Tus>>) -> impl IntoResponse {
+    (
+        StatusCode::NO_CONTENT,
+        [
+            (TUS_VERSION_HEADER, TUS_VERSION.to_string()),
+            (TUS_EXTENSION, TUS_EXTENSIONS.to_string()),
+            (TUS_MAX_SIZE, tus.config.max_size.to_string()),
+        ],
+    )
+}
+
+#[utoipa::path(
+    post,
+    path = "/uploads/tus",
+    tag = "files",
+    params(
+        ("Tus-Resumable" = String, Header, description = "Must be 1.0.0"),
+        ("Upload-Length" = u64, Header, description = "Total size in bytes"),
+        ("Upload-Metadata" = Option<String>, Header, description = "`key base64value` pairs"),
+    ),
+    request_body(
+        content_type = "application/offset+octet-stream",
+        description = "Optional first chunk",
+    ),
+    responses(
+        (status = 201, description = "Upload created", headers(
+            ("Location" = String, description = "Upload URL"),
+            ("Upload-Offset" = u64),
+            ("Upload-Expires" = String),
+        )),
+        (status = 400, description = "Missing or malformed headers", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 412, description = "Unsupported tus version", body = ErrorBody),
+        (status = 413, description = "Upload too large", body = ErrorBody),
+    )
+)]
+async fn create(
+    State(tus): State<Arc<Tus>>,
+    user: AuthUser,
+    request: Request,
+) -> Result<Response, AppError> {
+    let headers = request.headers();
+    let length: u64 = header_str(headers, &UPLOAD_LENGTH)
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| bad_request("Upload-Length is required"))?;
+    if length > tus.config.max_size {
+        return Err(AppError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Upload-Length exceeds {} bytes", tus.config.max_size),
+        ));
+    }
+    let metadata = header_str(headers, &UPLOAD_METADATA).unwrap_or_default();
+    if parse_metadata(metadata).is_none() {
+        return Err(bad_request("Upload-Metadata is malformed"));
+    }
+    let with_body = header_str(headers, &header::CONTENT_TYPE) == Some(OFFSET_OCTET_STREAM);
+
+    tokio::fs::create_dir_all(&tus.config.dir)
+        .await
+        .map_err(AppError::internal)?;
+    let id = format!("{:032x}", rand::random::<u128>());
+    let path = tus.config.dir.join(format!("{}.part", id));
+    tokio::fs::File::create(&path)
+        .await
+        .map_err(AppError::internal)?;
+
+    let upload = Arc::new(tokio::sync::Mutex::new(TusUpload {
+        length,
+        offset: 0,
+        metadata: metadata.to_string(),
+        expires_at: SystemTime::now() + tus.config.expiry,
+        path,
+        key: None,
+        url: None,
+    }));
+    tus.uploads.lock().unwrap().insert(
+        id.clone(),
+        Entry {
+            owner: user.username,
+            upload: upload.clone(),
+        },
+    );
+
+    let mut upload = upload.lock().await;
+    if with_body {
+        tus.append(&mut upload, request.into_body()).await?;
+    }
+    if upload.offset == upload.length
+        && let Err(err) = tus.finish(&mut upload).await
+    {
+        drop(upload);
+        tus.remove(&id).await;
+        return Err(err);
+    }
+    Ok((StatusCode::CREATED, upload_headers(Some(&id), &upload)).into_response())
+}
+
+#[utoipa::path(
+    head,
+    path = "/uploads/tus/{id}",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "Upload id"),
+        ("Tus-Resumable" = String, Header, description = "Must be 1.0.0"),
+    ),
+    responses(
+        (status = 200, description = "Bytes received so far", headers(
+            ("Upload-Offset" = u64),
+            ("Upload-Length" = u64),
+        )),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Upload not found"),
+        (status = 410, description = "Upload expired"),
+    )
+)]
+async fn offset(
+    State(tus): State<Arc<Tus>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    let upload = tus.get(&id, &user.username).ok_or_else(not_found)?;
+    let upload = upload.lock().await;
+    if upload.key.is_none() && upload.expires_at <= SystemTime::now() {
+        return Err(AppError::new(StatusCode::GONE, "Upload expired"));
+    }
+
+    let mut headers = upload_headers(None, &upload);
+    headers.insert(UPLOAD_LENGTH, HeaderValue::from(upload.length));
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    if !upload.metadata.is_empty() {
+        headers.insert(
+            UPLOAD_METADATA,
+            HeaderValue::try_from(upload.metadata.as_str()).unwrap(),
+        );
+    }
+    Ok((StatusCode::OK, headers).into_response())
+}
+
+#[utoipa::path(
+    patch,
+    path = "/uploads/tus/{id}",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "Upload id"),
+        ("Tus-Resumable" = String, Header, description = "Must be 1.0.0"),
+        ("Upload-Offset" = u64, Header, description = "Offset the chunk starts at"),
+    ),
+    request_body(content_type = "application/offset+octet-stream", description = "The next chunk"),
+    responses(
+        (status = 204, description = "Chunk stored; the last one also stores the file", headers(
+            ("Upload-Offset" = u64),
+            ("Content-Location" = String, description = "Signed link once complete"),
+        )),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Upload not found", body = ErrorBody),
+        (status = 409, description = "Upload-Offset does not match", body = ErrorBody),
+        (status = 410, description = "Upload expired", body = ErrorBody),
+        (status = 413, description = "Chunk exceeds Upload-Length", body = ErrorBody),
+        (status = 415, description = "Wrong Content-Type or file type", body = ErrorBody),
+        (status = 423, description = "Another request is writing", body = ErrorBody),
+    )
+)]
+async fn patch(
+    State(tus): State<Arc<Tus>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+    request: Request,
+) -> Result<Response, AppError> {
+    if header_str(request.headers(), &header::CONTENT_TYPE) != Some(OFFSET_OCTET_STREAM) {
+        return Err(AppError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Content-Type must be {}", OFFSET_OCTET_STREAM),
+        ));
+    }
+    let offset: u64 = header_str(request.headers(), &UPLOAD_OFFSET)
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| bad_request("Upload-Offset is required"))?;
+
+    let upload = tus.get(&id, &user.username).ok_or_else(not_found)?;
+    let Ok(mut upload) = upload.try_lock() else {
+        return Err(AppError::new(
+            StatusCode::LOCKED,
+            "Upload is being written by another request",
+        ));
+    };
+    if upload.key.is_none() && upload.expires_at <= SystemTime::now() {
+        return Err(AppError::new(StatusCode::GONE, "Upload expired"));
+    }
+    if offset != upload.offset || upload.key.is_some() {
+        return Err(AppError::new(
+            StatusCode::CONFLICT,
+            format!("Upload-Offset must be {}", upload.offset),
+        ));
+    }
+
+    tus.append(&mut upload, request.into_body()).await?;
+    if upload.offset == upload.length
+        && let Err(err) = tus.finish(&mut upload).await
+    {
+        drop(upload);
+        tus.remove(&id).await;
+        return Err(err);
+    }
+    Ok((StatusCode::NO_CONTENT, upload_headers(None, &upload)).into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/uploads/tus/{id}",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "Upload id"),
+        ("Tus-Resumable" = String, Header, description = "Must be 1.0.0"),
+    ),
+    responses(
+        (status = 204, description = "Upload discarded"),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Upload not found", body = ErrorBody),
+    )
+)]
+async fn terminate(
+    State(tus): State<Arc<Tus>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    tus.get(&id, &user.username).ok_or_else(not_found)?;
+    tus.remove(&id).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Every request except OPTIONS must name the protocol version, and every
+// response carries it.
+async fn tus_resumable(request: Request, next: Next) -> Response {
+    let version = header_str(request.headers(), &TUS_RESUMABLE);
+    if request.method() != Method::OPTIONS && version != Some(TUS_VERSION) {
+        return AppError::new(StatusCode::PRECONDITION_FAILED, "Unsupported tus version")
+            .with_header(TUS_VERSION_HEADER, HeaderValue::from_static(TUS_VERSION))
+            .into_response();
+    }
+
+    let mut response = next.run(request).await;
+    response
+        .headers_mut()
+        .insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+    response
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(options, create, offset, patch, terminate))]
+pub struct TusApi;
+
+// Uploads are written to disk, so creating one needs a signed-in user.
+#[derive(Clone)]
+pub struct TusState {
+    pub tus: Arc<Tus>,
+    pub tokens: Arc<TokenService>,
+}
+
+impl FromRef<TusState> for Arc<Tus> {
+    fn from_ref(state: &TusState) -> Self {
+        state.tus.clone()
+    }
+}
+
+impl FromRef<TusState> for Arc<TokenService> {
+    fn from_ref(state: &TusState) -> Self {
+        state.tokens.clone()
+    }
+}
+
+pub fn router(state: TusState) -> Router {
+    Router::new()
+        .route("/uploads/tus", post(create).options(options))
+        .route(
+            "/uploads/tus/{id}",
+            head(offset).patch(patch).delete(terminate),
+        )
+        .layer(from_fn(tus_resumable))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blob_store::{LocalBlobStore, UrlSigner};
+    use axum_test::TestServer;
+
+    const PNG: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 13];
+
+    // The server signs every request in as `hadi`.
+    fn setup(dir: &std::path::Path, expiry: Duration) -> (Arc<Tus>, TestServer) {
+        let store = LocalBlobStore::new(dir.join("blobs"), "", Arc::new(UrlSigner::new("secret")));
+        let config = TusConfig {
+            dir: dir.join("tus"),
+            max_size: 1024,
+            expiry,
+            ..TusConfig::default()
+        };
+        let tus = Arc::new(Tus::new(config, Arc::new(store)));
+        let tokens = Arc::new(TokenService::new("secret", Duration::from_secs(60)));
+        let mut server = TestServer::new(router(TusState {
+            tus: tus.clone(),
+            tokens: tokens.clone(),
+        }))
+        .unwrap();
+        server.add_header(
+            header::AUTHORIZATION,
+            format!("Bearer {}", tokens.issue("hadi")),
+        );
+        (tus, server)
+    }
+
+    async fn create_upload(server: &TestServer, length: usize) -> String {
+        let response = server
+            .post("/uploads/tus")
+            .add_header("Tus-Resumable", TUS_VERSION)
+            .add_header("Upload-Length", length.to_string())
+            .add_header("Upload-Metadata", "filename cHJvZmlsZS5wbmc=,private")
+            .await;
+        response.assert_status(StatusCode::CREATED);
+        response.assert_header("Tus-Resumable", TUS_VERSION);
+        assert!(response.maybe_header("Upload-Expires").is_some());
+        response.header("Location").to_str().unwrap().to_string()
+    }
+
+    async fn patch_chunk(
+        server: &TestServer,
+        location: &str,
+        offset: usize,
+        chunk: &[u8],
+    ) -> axum_test::TestResponse {
+        server
+            .patch(location)
+            .add_header("Tus-Resumable", TUS_VERSION)
+            .add_header("Upload-Offset", offset.to_string())
+            .content_type(OFFSET_OCTET_STREAM)
+            .bytes(chunk.to_vec().into())
+            .await
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        let metadata = parse_metadata("filename cHJvZmlsZS5wbmc=, private").unwrap();
+        assert_eq!(metadata["filename"], "profile.png");
+        assert_eq!(metadata["private"], "");
+        assert!(parse_metadata("filename !!!").is_none());
+        assert!(parse_metadata("a,a").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_options_and_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, server) = setup(dir.path(), Duration::from_secs(60));
+
+        let response = server.method(Method::OPTIONS, "/uploads/tus").await;
+        response.assert_status(StatusCode::NO_CONTENT);
+        response.assert_header("Tus-Version", TUS_VERSION);
+        response.assert_header("Tus-Max-Size", "1024");
+        assert!(
+            response
+                .header("Tus-Extension")
+                .to_str()
+                .unwrap()
+                .contains("termination")
+        );
+
+        let response = server
+            .post("/uploads/tus")
+            .add_header("Upload-Length", "12")
+            .await;
+        response.assert_status(StatusCode::PRECONDITION_FAILED);
+        response.assert_header("Tus-Version", TUS_VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_resumable_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, server) = setup(dir.path(), Duration::from_secs(60));
+        let location = create_upload(&server, PNG.len()).await;
+
+        let response = patch_chunk(&server, &location, 0, &PNG[..5]).await;
+        response.assert_status(StatusCode::NO_CONTENT);
+        response.assert_header("Upload-Offset", "5");
+
+        // A retried chunk with a stale offset is rejected.
+        let response = patch_chunk(&server, &location, 0, &PNG[..5]).await;
+        response.assert_status(StatusCode::CONFLICT);
+
+        let response = server
+            .method(Method::HEAD, &location)
+            .add_header("Tus-Resumable", TUS_VERSION)
+            .await;
+        response.assert_status_ok();
+        response.assert_header("Upload-Offset", "5");
+        response.assert_header("Upload-Length", "12");
+        response.assert_header("Cache-Control", "no-store");
+        response.assert_header("Upload-Metadata", "filename cHJvZmlsZS5wbmc=,private");
+
+        let response = patch_chunk(&server, &location, 5, &PNG[5..]).await;
+        response.assert_status(StatusCode::NO_CONTENT);
+        response.assert_header("Upload-Offset", "12");
+        let url = response
+            .header("Content-Location")
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let key = content_key(&hex(&Sha256::digest(PNG)), "image/png");
+        assert!(url.starts_with(&format!("/files/{}?expires=", key)));
+        assert_eq!(
+            std::fs::read(dir.path().join("blobs").join(&key)).unwrap(),
+            PNG
+        );
+        assert_eq!(
+            std::fs::read_dir(dir.path().join("tus")).unwrap().count(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunk_limits_and_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, server) = setup(dir.path(), Duration::from_secs(60));
+
+        let response = server
+            .post("/uploads/tus")
+            .add_header("Tus-Resumable", TUS_VERSION)
+            .add_header("Upload-Length", "2048")
+            .await;
+        response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+
+        let location = create_upload(&server, 4).await;
+        let response = patch_chunk(&server, &location, 0, b"too long").await;
+        response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+
+        let location = create_upload(&server, 4).await;
+        let response = patch_chunk(&server, &location, 0, b"text").await;
+        response.assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let response = server
+            .method(Method::HEAD, &location)
+            .add_header("Tus-Resumable", TUS_VERSION)
+            .await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_expiry_and_termination() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tus, server) = setup(dir.path(), Duration::ZERO);
+        let location = create_upload(&server, PNG.len()).await;
+
+        let response = patch_chunk(&server, &location, 0, &PNG[..5]).await;
+        response.assert_status(StatusCode::GONE);
+
+        assert_eq!(tus.sweep().await, 1);
+        let response = server
+            .method(Method::HEAD, &location)
+            .add_header("Tus-Resumable", TUS_VERSION)
+            .await;
+        response.assert_status(StatusCode::NOT_FOUND);
+        assert_eq!(
+            std::fs::read_dir(dir.path().join("tus")).unwrap().count(),
+            0
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let (_, server) = setup(dir.path(), Duration::from_secs(60));
+        let location = create_upload(&server, PNG.len()).await;
+        let response = server
+            .delete(&location)
+            .add_header("Tus-Resumable", TUS_VERSION)
+            .await;
+        response.assert_status(StatusCode::NO_CONTENT);
+        assert_eq!(
+            std::fs::read_dir(dir.path().join("tus")).unwrap().count(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_uploads_need_their_owner() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tus, mut server) = setup(dir.path(), Duration::from_secs(60));
+        let location = create_upload(&server, PNG.len()).await;
+
+        server.clear_headers();
+        let response = server
+            .post("/uploads/tus")
+            .add_header("Tus-Resumable", TUS_VERSION)
+            .add_header("Upload-Length", "12")
+            .await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        assert_eq!(std::fs::read_dir(dir.path().join("tus")).unwrap().count(), 1);
+
+        // Another user cannot see, write or discard it.
+        let other = TokenService::new("secret", Duration::from_secs(60)).issue("other");
+        server.add_header(header::AUTHORIZATION, format!("Bearer {}", other));
+        let response = server
+            .method(Method::HEAD, &location)
+            .add_header("Tus-Resumable", TUS_VERSION)
+            .await;
+        response.assert_status(StatusCode::NOT_FOUND);
+        let response = patch_chunk(&server, &location, 0, PNG).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+        let response = server
+            .delete(&location)
+            .add_header("Tus-Resumable", TUS_VERSION)
+            .await;
+        response.assert_status(StatusCode::NOT_FOUND);
+
+        let id = location.rsplit('/').next().unwrap();
+        assert_eq!(tus.get(id, "hadi").unwrap().lock().await.offset, 0);
+    }
+
+    #[tokio::test]
+    async fn test_finished_uploads_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tus, server) = setup(dir.path(), Duration::from_secs(60));
+        let location = create_upload(&server, PNG.len()).await;
+        let response = patch_chunk(&server, &location, 0, PNG).await;
+        response.assert_status(StatusCode::NO_CONTENT);
+        assert_eq!(tus.sweep().await, 0);
+
+        let id = location.rsplit('/').next().unwrap();
+        tus.get(id, "hadi").unwrap().lock().await.expires_at = SystemTime::now();
+        assert_eq!(tus.sweep().await, 1);
+        let response = server
+            .method(Method::HEAD, &location)
+            .add_header("Tus-Resumable", TUS_VERSION)
+            .await;
+        response.assert_status(StatusCode::NOT_FOUND);
+
+        // The stored file is not part of the upload's state.
+        let key = content_key(&hex(&Sha256::digest(PNG)), "image/png");
+        assert!(dir.path().join("blobs").join(&key).exists());
+    }
+}
@@ -0,0 +1,595 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
+
+use axum::{
+    Json, Router,
+    extract::{DefaultBodyLimit, FromRef, Multipart, State, multipart::Field},
+    routing::post,
+};
+use http::StatusCode;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::{fs::File, io::AsyncWriteExt};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthUser, TokenService},
+    blob_store::{BlobStore, content_key},
+    error::{AppError, ErrorBody},
+    profile_image::ImagePipeline,
+};
+
+// Multipart field holding the user's profile image.
+pub const PROFILE_FIELD: &str = "profile";
+
+// Bytes needed to recognise every signature in `sniff`.
+pub const SNIFF_LEN: usize = 12;
+
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    pub dir: PathBuf,
+    pub max_field_size: usize,
+    pub max_total_size: usize,
+    // Content types accepted for file fields, checked against magic bytes.
+    pub allowed_types: Vec<String>,
+    // Lifetime of the download links returned after an upload.
+    pub link_ttl: Duration,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            dir: std::env::temp_dir().join("axum-uploads"),
+            max_field_size: 5 * 1024 * 1024,
+            max_total_size: 10 * 1024 * 1024,
+            allowed_types: ["image/png", "image/jpeg", "image/gif", "image/webp"]
+                .map(str::to_string)
+                .to_vec(),
+            link_ttl: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+pub fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    // WEBP is a RIFF container with `WEBP` as its form type.
+    if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        return Some("image/webp");
+    }
+    let signatures: [(&[u8], usize, &str); 5] = [
+        (b"\x89PNG\r\n\x1a\n", 0, "image/png"),
+        (b"\xff\xd8\xff", 0, "image/jpeg"),
+        (b"GIF87a", 0, "image/gif"),
+        (b"GIF89a", 0, "image/gif"),
+        (b"%PDF-", 0, "application/pdf"),
+    ];
+    signatures
+        .iter()
+        .find(|(magic, offset, _)| {
+            bytes
+                .get(*offset..)
+                .is_some_and(|rest| rest.starts_with(magic))
+        })
+        .map(|(_, _, content_type)| *content_type)
+}
+
+// A file field streamed to disk. The file is removed on drop.
+#[derive(Debug)]
+pub struct UploadedFile {
+    pub field: String,
+    pub file_name: Option<String>,
+    pub content_type: String,
+    pub size: usize,
+    pub sha256: String,
+    path: PathBuf,
+}
+
+impl UploadedFile {
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Upload {
+    pub fields: HashMap<String, String>,
+    pub files: Vec<UploadedFile>,
+}
+
+fn too_large(message: &str) -> AppError {
+    AppError::new(StatusCode::PAYLOAD_TOO_LARGE, message)
+}
+
+fn unsupported(message: String) -> AppError {
+    AppError::new(StatusCode::UNSUPPORTED_MEDIA_TYPE, message)
+}
+
+fn multipart_error(err: axum::extract::multipart::MultipartError) -> AppError {
+    AppError::new(err.status(), err.body_text())
+}
+
+async fn store_field(
+    mut field: Field<'_>,
+    config: &UploadConfig,
+    total: &mut usize,
+) -> Result<UploadedFile, AppError> {
+    let name = field.name().unwrap_or_default().to_string();
+    let file_name = field.file_name().map(str::to_string);
+    let declared = field.content_type().map(str::to_string);
+
+    tokio::fs::create_dir_all(&config.dir)
+        .await
+        .map_err(AppError::internal)?;
+    let path = config
+        .dir
+        .join(format!("{:032x}.part", rand::random::<u128>()));
+    let mut file = File::create(&path).await.map_err(AppError::internal)?;
+
+    // Owns the path from here on so every early return cleans up.
+    let mut uploaded = UploadedFile {
+        field: name,
+        file_name,
+        content_type: String::new(),
+        size: 0,
+        sha256: String::new(),
+        path,
+    };
+    let mut hasher = Sha256::new();
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+
+    while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+        uploaded.size += chunk.len();
+        *total += chunk.len();
+        if uploaded.size > config.max_field_size {
+            return Err(too_large(&format!(
+                "Field {} exceeds {} bytes",
+                uploaded.field, config.max_field_size
+            )));
+        }
+        if *total > config.max_total_size {
+            return Err(too_large(&format!(
+                "Upload exceeds {} bytes",
+                config.max_total_size
+            )));
+        }
+
+        if head.len() < SNIFF_LEN {
+            let take = (SNIFF_LEN - head.len()).min(chunk.len());
+            head.extend_from_slice(&chunk[..take]);
+        }
+        hasher.update(&chunk);
+        file.write_all(&chunk).await.map_err(AppError::internal)?;
+    }
+    file.flush().await.map_err(AppError::internal)?;
+
+    let detected = sniff(&head)
+        .filter(|detected| {
+            config
+                .allowed_types
+                .iter()
+                .any(|allowed| allowed == detected)
+        })
+        .ok_or_else(|| {
+            unsupported(format!(
+                "Field {} has an unsupported file type",
+                uploaded.field
+            ))
+        })?;
+    if declared
+        .as_deref()
+        .is_some_and(|declared| declared != detected && declared != "application/octet-stream")
+    {
+        return Err(unsupported(format!(
+            "Field {} declared {} but contains {}",
+            uploaded.field,
+            declared.unwrap_or_default(),
+            detected
+        )));
+    }
+
+    uploaded.content_type = detected.to_string();
+    uploaded.sha256 = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok(uploaded)
+}
+
+pub async fn receive(mut multipart: Multipart, config: &UploadConfig) -> Result<Upload, AppError> {
+    let mut upload = Upload::default();
+    let mut total = 0;
+
+    while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+        if field.file_name().is_some() {
+            upload
+                .files
+                .push(store_field(field, config, &mut total).await?);
+            continue;
+        }
+
+        let name = field.name().unwrap_or_default().to_string();
+        let text = field.text().await.map_err(multipart_error)?;
+        total += text.len();
+        if text.len() > config.max_field_size || total > config.max_total_size {
+            return Err(too_large(&format!("Field {} is too large", name)));
+        }
+        upload.fields.insert(name, text);
+    }
+
+    Ok(upload)
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FileResponse {
+    pub field: String,
+    pub file_name: Option<String>,
+    pub content_type: String,
+    pub size: usize,
+    pub sha256: String,
+    // Set for files stored as uploaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    // Set for profile images, which are only stored once processed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_url: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UploadResponse {
+    pub username: Option<String>,
+    pub files: Vec<FileResponse>,
+}
+
+#[derive(Clone)]
+pub struct UploadState {
+    pub config: Arc<UploadConfig>,
+    pub store: Arc<dyn BlobStore>,
+    // When set, the profile field goes through the image pipeline instead of
+    // being stored as-is.
+    pub images: Option<Arc<ImagePipeline>>,
+    // Uploads are written to disk, so they need a signed-in user.
+    pub tokens: Arc<TokenService>,
+}
+
+impl FromRef<UploadState> for Arc<TokenService> {
+    fn from_ref(state: &UploadState) -> Self {
+        state.tokens.clone()
+    }
+}
+
+async fn persist(file: UploadedFile, state: &UploadState) -> Result<FileResponse, AppError> {
+    let mut response = FileResponse {
+        field: file.field.clone(),
+        file_name: file.file_name.clone(),
+        content_type: file.content_type.clone(),
+        size: file.size,
+        sha256: file.sha256.clone(),
+        key: None,
+        url: None,
+        status_url: None,
+    };
+
+    if let Some(images) = state
+        .images
+        .as_ref()
+        .filter(|_| file.field == PROFILE_FIELD)
+    {
+        let data = tokio::fs::read(file.path())
+            .await
+            .map_err(AppError::internal)?;
+        images
+            .submit(&file.sha256, data.into())?;
+        response.status_url = Some(format!("/profile/images/{}", file.sha256));
+        return Ok(response);
+    }
+
+    // Identical content lands on the same key, so re-uploads are free.
+    let key = content_key(&file.sha256, &file.content_type);
+    state
+        .store
+        .put_file(&key, file.path(), &file.content_type)
+        .await
+        .map_err(AppError::internal)?;
+    let url = state
+        .store
+        .presign_get(&key, state.config.link_ttl)
+        .await
+        .map_err(AppError::internal)?;
+
+    response.key = Some(key);
+    response.url = Some(url);
+    Ok(response)
+}
+
+#[utoipa::path(
+    post,
+    path = "/upload",
+    tag = "files",
+    request_body(
+        content_type = "multipart/form-data",
+        description = "Files plus an optional `username` field; `profile` is processed as an image",
+    ),
+    responses(
+        (status = 200, description = "Stored files", body = UploadResponse),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 413, description = "Upload too large", body = ErrorBody),
+        (status = 415, description = "File type not allowed", body = ErrorBody),
+        (status = 422, description = "Invalid profile image", body = ErrorBody),
+        (status = 503, description = "Image processing queue is full", body = ErrorBody),
+    )
+)]
+// `AuthUser` comes before `Multipart`, so anonymous requests are turned away
+// before any of the body is read.
+async fn upload(
+    State(state): State<UploadState>,
+    _user: AuthUser,
+    multipart: Multipart,
+) -> Result<Json<UploadResponse>, AppError> {
+    let mut upload = receive(multipart, &state.config).await?;
+    let mut files = Vec::with_capacity(upload.files.len());
+    for file in upload.files.drain(..) {
+        files.push(persist(file, &state).await?);
+    }
+    Ok(Json(UploadResponse {
+        username: upload.fields.get("username").cloned(),
+        files,
+    }))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(upload))]
+pub struct UploadApi;
+
+pub fn router(state: UploadState) -> Router {
+    // Leave room for the multipart framing around the payload itself.
+    let body_limit = state.config.max_total_size + 64 * 1024;
+    Router::new()
+        .route("/upload", post(upload))
+        .layer(DefaultBodyLimit::max(body_limit))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        blob_store::{LocalBlobStore, UrlSigner},
+        profile_image::ImageConfig,
+    };
+    use axum_test::{
+        TestServer,
+        multipart::{MultipartForm, Part},
+    };
+
+    const PNG: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 13];
+
+    fn tokens() -> Arc<TokenService> {
+        Arc::new(TokenService::new("secret", Duration::from_secs(60)))
+    }
+
+    // Signs every request in as `hadi`, with a token from `tokens()`.
+    fn signed_in(router: Router) -> TestServer {
+        let mut server = TestServer::new(router).unwrap();
+        server.add_header(
+            http::header::AUTHORIZATION,
+            format!("Bearer {}", tokens().issue("hadi")),
+        );
+        server
+    }
+
+    fn server(dir: &std::path::Path) -> TestServer {
+        let config = UploadConfig {
+            dir: dir.join("tmp"),
+            max_field_size: 64,
+            max_total_size: 100,
+            ..UploadConfig::default()
+        };
+        let store = LocalBlobStore::new(
+            dir.join("blobs"),
+            "http://localhost:3000",
+            Arc::new(UrlSigner::new("secret")),
+        );
+        signed_in(router(UploadState {
+            config: Arc::new(config),
+            store: Arc::new(store),
+            images: None,
+            tokens: tokens(),
+        }))
+    }
+
+    fn temp_files(dir: &std::path::Path) -> usize {
+        std::fs::read_dir(dir.join("tmp"))
+            .map(|entries| entries.count())
+            .unwrap_or(0)
+    }
+
+    fn png_part(size: usize) -> Part {
+        let mut bytes = PNG.to_vec();
+        bytes.resize(size, 0);
+        Part::bytes(bytes)
+            .file_name("profile.png")
+            .mime_type("image/png")
+    }
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(PNG), Some("image/png"));
+        assert_eq!(sniff(&[0xFF, 0xD8, 0xFF, 0xE0]), Some("image/jpeg"));
+        assert_eq!(sniff(b"GIF89a"), Some("image/gif"));
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff(b"AVI \0\0\0\0WEBPVP8 "), None);
+        assert_eq!(sniff(b"profile"), None);
+    }
+
+    #[tokio::test]
+    async fn test_upload_needs_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut server = server(dir.path());
+        server.clear_headers();
+
+        let request = MultipartForm::new().add_part("profile", png_part(PNG.len()));
+        let response = server.post("/upload").multipart(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        assert_eq!(temp_files(dir.path()), 0);
+        assert!(!dir.path().join("blobs").exists());
+    }
+
+    #[tokio::test]
+    async fn test_upload_streams_to_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let request = MultipartForm::new()
+            .add_text("username", "hadi")
+            .add_part("profile", png_part(PNG.len()));
+
+        let response = server(dir.path()).post("/upload").multipart(request).await;
+        response.assert_status_ok();
+
+        let sha256: String = Sha256::digest(PNG)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let key = content_key(&sha256, "image/png");
+        let body = response.json::<serde_json::Value>();
+        assert_eq!(body["username"], "hadi");
+        assert_eq!(body["files"][0]["field"], "profile");
+        assert_eq!(body["files"][0]["file_name"], "profile.png");
+        assert_eq!(body["files"][0]["content_type"], "image/png");
+        assert_eq!(body["files"][0]["size"], 12);
+        assert_eq!(body["files"][0]["sha256"], sha256);
+        assert_eq!(body["files"][0]["key"], key.as_str());
+        assert!(body["files"][0].get("status_url").is_none());
+        let url = body["files"][0]["url"].as_str().unwrap();
+        assert!(url.starts_with(&format!("http://localhost:3000/files/{}?expires=", key)));
+
+        // The blob lives in the store and the temp file is gone.
+        assert_eq!(
+            std::fs::read(dir.path().join("blobs").join(&key)).unwrap(),
+            PNG
+        );
+        assert_eq!(temp_files(dir.path()), 0);
+    }
+
+    #[tokio::test]
+    async fn test_field_and_total_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = server(dir.path());
+
+        let request = MultipartForm::new().add_part("profile", png_part(65));
+        let response = server.post("/upload").multipart(request).await;
+        response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+        response.assert_json(&serde_json::json!({
+            "code": 413,
+            "message": "Field profile exceeds 64 bytes",
+        }));
+
+        let request = MultipartForm::new()
+            .add_part("profile", png_part(60))
+            .add_part("cover", png_part(60));
+        let response = server.post("/upload").multipart(request).await;
+        response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+        response.assert_json(&serde_json::json!({
+            "code": 413,
+            "message": "Upload exceeds 100 bytes",
+        }));
+        assert_eq!(temp_files(dir.path()), 0);
+    }
+
+    #[tokio::test]
+    async fn test_magic_bytes_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = server(dir.path());
+
+        let request = MultipartForm::new().add_part(
+            "profile",
+            Part::bytes(b"profile".to_vec())
+                .file_name("profile.png")
+                .mime_type("image/png"),
+        );
+        let response = server.post("/upload").multipart(request).await;
+        response.assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let request = MultipartForm::new().add_part(
+            "profile",
+            Part::bytes(PNG.to_vec())
+                .file_name("profile.jpg")
+                .mime_type("image/jpeg"),
+        );
+        let response = server.post("/upload").multipart(request).await;
+        response.assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        response.assert_json(&serde_json::json!({
+            "code": 415,
+            "message": "Field profile declared image/jpeg but contains image/png",
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_profile_goes_through_pipeline() {
+        let dir = tempfile::tempdir().unwrap();
+        let store: Arc<dyn BlobStore> = Arc::new(LocalBlobStore::new(
+            dir.path().join("blobs"),
+            "",
+            Arc::new(UrlSigner::new("secret")),
+        ));
+        let images = ImagePipeline::new(
+            ImageConfig {
+                min_width: 16,
+                min_height: 16,
+                thumbnail_sizes: vec![16],
+                ..ImageConfig::default()
+            },
+            store.clone(),
+        );
+        let server = signed_in(router(UploadState {
+            config: Arc::new(UploadConfig {
+                dir: dir.path().join("tmp"),
+                ..UploadConfig::default()
+            }),
+            store,
+            images: Some(Arc::new(images)),
+            tokens: tokens(),
+        }));
+
+        let png = |size: u32| {
+            let mut data = std::io::Cursor::new(Vec::new());
+            image::RgbImage::new(size, size)
+                .write_to(&mut data, image::ImageFormat::Png)
+                .unwrap();
+            Part::bytes(data.into_inner())
+                .file_name("profile.png")
+                .mime_type("image/png")
+        };
+
+        let request = MultipartForm::new().add_part("profile", png(8));
+        let response = server.post("/upload").multipart(request).await;
+        response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        response.assert_json(&serde_json::json!({
+            "code": 422,
+            "message": "Image is 8x8, at least 16x16 is required",
+        }));
+
+        let request = MultipartForm::new().add_part("profile", png(32));
+        let response = server.post("/upload").multipart(request).await;
+        response.assert_status_ok();
+        let body = response.json::<serde_json::Value>();
+        let sha256 = body["files"][0]["sha256"].as_str().unwrap();
+        assert_eq!(
+            body["files"][0]["status_url"],
+            format!("/profile/images/{}", sha256)
+        );
+        // The unprocessed upload is never stored.
+        assert!(body["files"][0].get("key").is_none());
+        assert!(
+            !dir.path()
+                .join("blobs")
+                .join(content_key(sha256, "image/png"))
+                .exists()
+        );
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Router,
+    extract::{
+        FromRef, Query, State, WebSocketUpgrade,
+        ws::{CloseFrame, Message, Utf8Bytes, WebSocket, close_code},
+    },
+    response::Response,
+    routing::get,
+};
+use futures_util::{SinkExt, StreamExt};
+use http::{HeaderMap, StatusCode, Uri, header};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::OpenApi;
+
+use crate::{
+    auth::{AuthUser, TokenService},
+    error::{AppError, ErrorBody},
+    hub::{Hub, SubscribeError, Subscriber},
+};
+
+#[derive(Debug, Clone)]
+pub struct WebSocketConfig {
+    // Interval between pings.
+    pub heartbeat: Duration,
+    // A connection with no frames from the client for this long is closed, as
+    // is one that takes this long to accept a frame from us.
+    pub timeout: Duration,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat: Duration::from_secs(30),
+            timeout: Duration::from_secs(75),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct WebSocketState {
+    pub config: Arc<WebSocketConfig>,
+    pub hub: Arc<Hub>,
+    pub tokens: Arc<TokenService>,
+}
+
+impl FromRef<WebSocketState> for Arc<TokenService> {
+    fn from_ref(state: &WebSocketState) -> Self {
+        state.tokens.clone()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientMessage {
+    Subscribe { channel: String },
+    Unsubscribe { channel: String },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ServerMessage<'a> {
+    Subscribed {
+        channel: &'a str,
+    },
+    Unsubscribed {
+        channel: &'a str,
+    },
+    Event {
+        id: u64,
+        channel: &'a str,
+        data: &'a Value,
+    },
+    Error {
+        message: &'a str,
+    },
+}
+
+impl ServerMessage<'_> {
+    fn to_message(&self) -> Message {
+        Message::Text(serde_json::to_string(self).unwrap().into())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ConnectQuery {
+    // Browsers cannot set headers on the upgrade request.
+    access_token: Option<String>,
+}
+
+// An `Origin` that is not the requested host, i.e. a page on another site
+// opening the socket with the user's cookie.
+fn is_cross_origin(headers: &HeaderMap) -> bool {
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return false;
+    };
+    let origin = origin
+        .to_str()
+        .ok()
+        .and_then(|value| value.parse::<Uri>().ok());
+    let host = headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok());
+    match (origin.as_ref().and_then(Uri::authority), host) {
+        (Some(authority), Some(host)) => authority.as_str() != host,
+        _ => true,
+    }
+}
+
+// Authenticates on upgrade with an `access_token` query parameter, a bearer
+// token or the session cookie.
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "realtime",
+    params(("access_token" = Option<String>, Query, description = "For browsers, which cannot set headers")),
+    responses(
+        (status = 101, description = "Upgraded to a WebSocket. The client sends JSON `subscribe` and `unsubscribe` frames; the server answers with `subscribed`, `unsubscribed` and `error` frames and pushes `event` frames"),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Cross-origin upgrade", body = ErrorBody),
+    )
+)]
+async fn connect(
+    State(state): State<WebSocketState>,
+    Query(query): Query<ConnectQuery>,
+    headers: HeaderMap,
+    user: Result<AuthUser, AppError>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    let username = match query.access_token {
+        Some(token) => {
+            state
+                .tokens
+                .verify(&token)
+                .ok_or_else(|| AppError::unauthorized("Invalid token"))?
+                .sub
+        }
+        None => {
+            let user = user?;
+            if !headers.contains_key(header::AUTHORIZATION) && is_cross_origin(&headers) {
+                return Err(AppError::new(
+                    StatusCode::FORBIDDEN,
+                    "Cross-origin WebSocket rejected",
+                ));
+            }
+            user.username
+        }
+    };
+
+    Ok(upgrade.on_upgrade(move |socket| run(socket, state, username)))
+}
+
+fn handle(subscriber: &Subscriber, text: &str) -> Message {
+    let error = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(ClientMessage::Subscribe { channel }) => match subscriber.subscribe(&channel) {
+            Ok(()) => return ServerMessage::Subscribed { channel: &channel }.to_message(),
+            Err(SubscribeError::Forbidden) => "Not allowed to subscribe to this channel",
+            Err(SubscribeError::TooManySubscriptions) => "Too many subscriptions",
+        },
+        Ok(ClientMessage::Unsubscribe { channel }) => {
+            subscriber.unsubscribe(&channel);
+            return ServerMessage::Unsubscribed { channel: &channel }.to_message();
+        }
+        Err(_) => "Invalid message",
+    };
+    ServerMessage::Error { message: error }.to_message()
+}
+
+async fn run(socket: WebSocket, state: WebSocketState, username: String) {
+    let (subscriber, mut events) = state.hub.connect(&username);
+    let (mut sink, mut stream) = socket.split();
+    let period = state.config.heartbeat;
+    let mut heartbeat = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    let mut last_seen = Instant::now();
+
+    let close = |code, reason: &'static str| {
+        Message::Close(Some(CloseFrame {
+            code,
+            reason: Utf8Bytes::from_static(reason),
+        }))
+    };
+
+    loop {
+        let reply = tokio::select! {
+            message = stream.next() => {
+                let Some(Ok(message)) = message else {
+                    break;
+                };
+                last_seen = Instant::now();
+                match message {
+                    Message::Text(text) => handle(&subscriber, &text),
+                    Message::Binary(_) => ServerMessage::Error {
+                        message: "Binary messages are not supported",
+                    }
+                    .to_message(),
+                    Message::Close(_) => break,
+                    // Pings are answered by the protocol layer.
+                    Message::Ping(_) | Message::Pong(_) => continue,
+                }
+            }
+            event = events.recv() => match event {
+                Some(event) => ServerMessage::Event {
+                    id: event.id,
+                    channel: &event.channel,
+                    data: &event.data,
+                }
+                .to_message(),
+                // Dropped by the hub for not keeping up.
+                None => {
+                    let _ = tokio::time::timeout(
+                        state.config.timeout,
+                        sink.send(close(close_code::POLICY, "Slow consumer")),
+                    )
+                    .await;
+                    break;
+                }
+            },
+            _ = heartbeat.tick() => {
+                if last_seen.elapsed() > state.config.timeout {
+                    let _ = tokio::time::timeout(
+                        state.config.timeout,
+                        sink.send(close(close_code::AWAY, "Heartbeat timeout")),
+                    )
+                    .await;
+                    break;
+                }
+                Message::Ping(Default::default())
+            }
+        };
+        // A client that stops reading would otherwise hold this task, and its
+        // subscriptions, forever once the socket buffers fill up.
+        match tokio::time::timeout(state.config.timeout, sink.send(reply)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(_)) | Err(_) => break,
+        }
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(connect))]
+pub struct WebSocketApi;
+
+pub fn router(state: WebSocketState) -> Router {
+    Router::new().route("/ws", get(connect)).with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        app, auth::TOKEN_COOKIE, blob_store::StorageConfig, config::AppConfig, hub::HubConfig,
+    };
+    use axum_extra::extract::cookie::Cookie;
+    use axum_test::{TestServer, WsMessage};
+    use serde_json::json;
+
+    fn server(config: WebSocketConfig) -> (TestServer, Arc<Hub>, String) {
+        let hub = Arc::new(Hub::new(HubConfig::default()));
+        let tokens = Arc::new(TokenService::new("secret", Duration::from_secs(60)));
+        let token = tokens.issue("hadi");
+        let app = router(WebSocketState {
+            config: Arc::new(config),
+            hub: hub.clone(),
+            tokens,
+        });
+        let server = TestServer::builder().http_transport().build(app).unwrap();
+        (server, hub, token)
+    }
+
+    #[tokio::test]
+    async fn test_authentication() {
+        let (server, _, token) = server(WebSocketConfig::default());
+
+        server
+            .get_websocket("/ws")
+            .expect_failure()
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+
+        server
+            .get_websocket("/ws")
+            .add_cookie(Cookie::new(TOKEN_COOKIE, token.clone()))
+            .add_header("Origin", "https://evil.example")
+            .expect_failure()
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+
+        server
+            .get_websocket("/ws")
+            .add_query_param("access_token", &token)
+            .add_header("Origin", "https://app.example")
+            .await
+            .assert_status_switching_protocols();
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_and_receive() {
+        let (server, hub, token) = server(WebSocketConfig::default());
+        let mut socket = server
+            .get_websocket("/ws")
+            .authorization_bearer(&token)
+            .await
+            .into_websocket()
+            .await;
+
+        socket
+            .send_json(&json!({"type": "subscribe", "channel": "products"}))
+            .await;
+        socket
+            .assert_receive_json(&json!({"type": "subscribed", "channel": "products"}))
+            .await;
+        socket
+            .send_json(&json!({"type": "subscribe", "channel": "user:other"}))
+            .await;
+        socket
+            .assert_receive_json(&json!({
+                "type": "error",
+                "message": "Not allowed to subscribe to this channel",
+            }))
+            .await;
+
+        assert_eq!(hub.publish("products", json!({"id": 1})), 1);
+        assert_eq!(hub.publish("categories", json!({"id": 2})), 0);
+        socket
+            .assert_receive_json(&json!({
+                "type": "event",
+                "id": 1,
+                "channel": "products",
+                "data": {"id": 1},
+            }))
+            .await;
+
+        socket.send_text("not json").await;
+        socket
+            .assert_receive_json(&json!({"type": "error", "message": "Invalid message"}))
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_timeout() {
+        let (server, hub, token) = server(WebSocketConfig {
+            heartbeat: Duration::from_millis(20),
+            timeout: Duration::from_millis(50),
+        });
+        let mut socket = server
+            .get_websocket("/ws")
+            .authorization_bearer(&token)
+            .await
+            .into_websocket()
+            .await;
+
+        socket
+            .send_json(&json!({"type": "subscribe", "channel": "products"}))
+            .await;
+        assert!(matches!(socket.receive_message().await, WsMessage::Text(_)));
+        assert_eq!(hub.connection_count(), 1);
+
+        // Not reading means not answering pings.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(hub.connection_count(), 0);
+    }
+
+    // Catalog writes through the real app reach subscribed sockets.
+    #[tokio::test]
+    async fn test_catalog_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.upload.dir = dir.path().join("uploads");
+        config.tus.dir = dir.path().join("tus");
+        config.storage = StorageConfig::Local {
+            dir: dir.path().join("blobs"),
+            base_url: String::new(),
+        };
+        let token = TokenService::new(&config.token_secret, config.token_ttl).issue("hadi");
+        let server = TestServer::builder()
+            .http_transport()
+            .build(app(&config).await)
+            .unwrap();
+
+        let mut socket = server
+            .get_websocket("/ws")
+            .authorization_bearer(&token)
+            .await
+            .into_websocket()
+            .await;
+        for channel in ["categories", "products"] {
+            socket
+                .send_json(&json!({"type": "subscribe", "channel": channel}))
+                .await;
+            socket
+                .assert_receive_json(&json!({"type": "subscribed", "channel": channel}))
+                .await;
+        }
+
+        let category = server
+            .post("/categories")
+            .authorization_bearer(&token)
+            .json(&json!({"name": "Books"}))
+            .await
+            .json::<serde_json::Value>();
+        let event = socket.receive_json::<serde_json::Value>().await;
+        assert_eq!(event["channel"], "categories");
+        assert_eq!(event["data"], json!({"action": "created", "item": category}));
+
+        let product = server
+            .post("/products")
+            .authorization_bearer(&token)
+            .json(&json!({"name": "Rust", "price_cents": 100, "category_id": category["id"]}))
+            .await
+            .json::<serde_json::Value>();
+        let event = socket.receive_json::<serde_json::Value>().await;
+        assert_eq!(event["channel"], "products");
+        assert_eq!(event["data"], json!({"action": "created", "item": product}));
+
+        server
+            .delete(&format!("/products/{}", product["id"]))
+            .authorization_bearer(&token)
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        let event = socket.receive_json::<serde_json::Value>().await;
+        assert_eq!(event["data"], json!({"action": "deleted", "item": product}));
+    }
+}